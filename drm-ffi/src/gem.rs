@@ -10,7 +10,7 @@ use std::{
     os::unix::io::{AsRawFd, BorrowedFd},
 };
 
-/// Open a GEM object given it's 32-bit name, returning the handle.
+/// Open a GEM object given its 32-bit name, returning the handle.
 pub fn open(fd: BorrowedFd<'_>, name: u32) -> io::Result<drm_gem_open> {
     let mut gem = drm_gem_open {
         name,
@@ -24,7 +24,7 @@ pub fn open(fd: BorrowedFd<'_>, name: u32) -> io::Result<drm_gem_open> {
     Ok(gem)
 }
 
-/// Closes a GEM object given it's handle.
+/// Closes a GEM object given its handle.
 pub fn close(fd: BorrowedFd<'_>, handle: u32) -> io::Result<drm_gem_close> {
     let gem = drm_gem_close {
         handle,
@@ -38,6 +38,20 @@ pub fn close(fd: BorrowedFd<'_>, handle: u32) -> io::Result<drm_gem_close> {
     Ok(gem)
 }
 
+/// Publishes a global 32-bit name for a GEM object given its handle.
+pub fn flink(fd: BorrowedFd<'_>, handle: u32) -> io::Result<drm_gem_flink> {
+    let mut flink = drm_gem_flink {
+        handle,
+        ..Default::default()
+    };
+
+    unsafe {
+        ioctl::gem::flink(fd, &mut flink)?;
+    }
+
+    Ok(flink)
+}
+
 /// Converts a GEM object's handle to a PRIME file descriptor.
 pub fn handle_to_fd(fd: BorrowedFd<'_>, handle: u32, flags: u32) -> io::Result<drm_prime_handle> {
     let mut prime = drm_prime_handle {
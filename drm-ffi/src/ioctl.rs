@@ -1,15 +1,29 @@
-use std::{ffi::c_uint, io, os::unix::io::BorrowedFd};
+use std::{
+    ffi::{c_uint, c_void},
+    io,
+    os::unix::io::BorrowedFd,
+    ptr,
+};
 
+use crate::{trace, transport};
 use drm_sys::*;
 use rustix::ioctl::{
-    ioctl, Getter, NoArg, NoneOpcode, ReadOpcode, ReadWriteOpcode, Setter, Updater, WriteOpcode,
+    ioctl, CompileTimeOpcode, Getter, NoArg, NoneOpcode, ReadOpcode, ReadWriteOpcode, Setter,
+    Updater, WriteOpcode,
 };
 
 macro_rules! ioctl_readwrite {
     ($name:ident, $ioty:expr, $nr:expr, $ty:ty) => {
         pub unsafe fn $name(fd: BorrowedFd, data: &mut $ty) -> io::Result<()> {
             type Opcode = ReadWriteOpcode<$ioty, $nr, $ty>;
-            Ok(ioctl(fd, Updater::<Opcode, $ty>::new(data))?)
+            let request = Opcode::OPCODE.raw() as u32;
+            let span = trace::Span::start_with(request, &*data);
+            let result = match transport::intercept(fd, request, data as *mut $ty as *mut c_void) {
+                Some(result) => result,
+                None => ioctl(fd, Updater::<Opcode, $ty>::new(data)).map_err(io::Error::from),
+            };
+            span.finish(Some(&*data), &result);
+            result
         }
     };
 }
@@ -18,7 +32,18 @@ macro_rules! ioctl_read {
     ($name:ident, $ioty:expr, $nr:expr, $ty:ty) => {
         pub unsafe fn $name(fd: BorrowedFd) -> io::Result<$ty> {
             type Opcode = ReadOpcode<$ioty, $nr, $ty>;
-            Ok(ioctl(fd, Getter::<Opcode, $ty>::new())?)
+            let request = Opcode::OPCODE.raw() as u32;
+            let span = trace::Span::start(request);
+            let mut data: $ty = std::mem::zeroed();
+            let result =
+                match transport::intercept(fd, request, &mut data as *mut $ty as *mut c_void) {
+                    Some(result) => result,
+                    None => ioctl(fd, Getter::<Opcode, $ty>::new())
+                        .map(|value| data = value)
+                        .map_err(io::Error::from),
+                };
+            span.finish(Some(&data), &result);
+            result.map(|()| data)
         }
     };
 }
@@ -27,7 +52,17 @@ macro_rules! ioctl_write_ptr {
     ($name:ident, $ioty:expr, $nr:expr, $ty:ty) => {
         pub unsafe fn $name(fd: BorrowedFd, data: &$ty) -> io::Result<()> {
             type Opcode = WriteOpcode<$ioty, $nr, $ty>;
-            Ok(ioctl(fd, Setter::<Opcode, $ty>::new(*data))?)
+            let request = Opcode::OPCODE.raw() as u32;
+            let span = trace::Span::start(request);
+            // Transports get a copy, so they cannot write through `data`
+            let mut copy = *data;
+            let arg = &mut copy as *mut $ty as *mut c_void;
+            let result = match transport::intercept(fd, request, arg) {
+                Some(result) => result,
+                None => ioctl(fd, Setter::<Opcode, $ty>::new(*data)).map_err(io::Error::from),
+            };
+            span.finish(Some(data), &result);
+            result
         }
     };
 }
@@ -36,7 +71,14 @@ macro_rules! ioctl_none {
     ($name:ident, $ioty:expr, $nr:expr) => {
         pub unsafe fn $name(fd: BorrowedFd) -> io::Result<()> {
             type Opcode = NoneOpcode<$ioty, $nr, ()>;
-            Ok(ioctl(fd, NoArg::<Opcode>::new())?)
+            let request = Opcode::OPCODE.raw() as u32;
+            let span = trace::Span::start(request);
+            let result = match transport::intercept(fd, request, ptr::null_mut()) {
+                Some(result) => result,
+                None => ioctl(fd, NoArg::<Opcode>::new()).map_err(io::Error::from),
+            };
+            span.finish(None::<&()>, &result);
+            result
         }
     };
 }
@@ -125,6 +167,30 @@ ioctl_readwrite!(get_irq_from_bus_id, DRM_IOCTL_BASE, 0x03, drm_irq_busid);
 /// # Nodes: Primary
 ioctl_readwrite!(wait_vblank, DRM_IOCTL_BASE, 0x3a, drm_wait_vblank);
 
+/// Get the current vblank sequence of a CRTC
+///
+/// # Locks DRM mutex: No
+/// # Permissions: None
+/// # Nodes: Primary
+ioctl_readwrite!(
+    crtc_get_sequence,
+    DRM_IOCTL_BASE,
+    0x3b,
+    drm_crtc_get_sequence
+);
+
+/// Queue an event for a vblank sequence of a CRTC
+///
+/// # Locks DRM mutex: No
+/// # Permissions: None
+/// # Nodes: Primary
+ioctl_readwrite!(
+    crtc_queue_sequence,
+    DRM_IOCTL_BASE,
+    0x3c,
+    drm_crtc_queue_sequence
+);
+
 pub(crate) mod mode {
     use super::*;
 
@@ -160,6 +226,7 @@ pub(crate) mod mode {
     ioctl_readwrite!(add_fb, DRM_IOCTL_BASE, 0xAE, drm_mode_fb_cmd);
     ioctl_readwrite!(add_fb2, DRM_IOCTL_BASE, 0xB8, drm_mode_fb_cmd2);
     ioctl_readwrite!(rm_fb, DRM_IOCTL_BASE, 0xAF, c_uint);
+    ioctl_readwrite!(close_fb, DRM_IOCTL_BASE, 0xD0, drm_mode_closefb);
 
     /// Plane related functions
     ioctl_readwrite!(get_plane, DRM_IOCTL_BASE, 0xB6, drm_mode_get_plane);
@@ -234,6 +301,7 @@ pub(crate) mod gem {
     /// GEM related functions
     ioctl_readwrite!(open, DRM_IOCTL_BASE, 0x0b, drm_gem_open);
     ioctl_write_ptr!(close, DRM_IOCTL_BASE, 0x09, drm_gem_close);
+    ioctl_readwrite!(flink, DRM_IOCTL_BASE, 0x0a, drm_gem_flink);
 
     /// Converts a buffer handle into a dma-buf file descriptor.
     ioctl_readwrite!(prime_handle_to_fd, DRM_IOCTL_BASE, 0x2d, drm_prime_handle);
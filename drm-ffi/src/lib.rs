@@ -1,6 +1,9 @@
 //!
 //! Foreign function interface
 //!
+//! Enabling the `log` feature logs every ioctl, with its decoded argument,
+//! result and duration, through the [`log`](https://docs.rs/log) crate.
+//!
 
 #![warn(missing_docs)]
 #![allow(unused_doc_comments)]
@@ -14,6 +17,8 @@ pub mod gem;
 mod ioctl;
 pub mod mode;
 pub mod syncobj;
+mod trace;
+pub mod transport;
 
 use std::{
     ffi::{c_int, c_ulong},
@@ -213,3 +218,39 @@ pub fn wait_vblank(
 
     Ok(unsafe { wait_vblank.reply })
 }
+
+/// Gets the current vblank sequence of a CRTC.
+pub fn get_crtc_sequence(fd: BorrowedFd<'_>, crtc_id: u32) -> io::Result<drm_crtc_get_sequence> {
+    let mut sequence = drm_crtc_get_sequence {
+        crtc_id,
+        ..Default::default()
+    };
+
+    unsafe {
+        ioctl::crtc_get_sequence(fd, &mut sequence)?;
+    }
+
+    Ok(sequence)
+}
+
+/// Queues an event for a vblank sequence of a CRTC.
+pub fn queue_crtc_sequence(
+    fd: BorrowedFd<'_>,
+    crtc_id: u32,
+    flags: u32,
+    sequence: u64,
+    user_data: u64,
+) -> io::Result<drm_crtc_queue_sequence> {
+    let mut queue = drm_crtc_queue_sequence {
+        crtc_id,
+        flags,
+        sequence,
+        user_data,
+    };
+
+    unsafe {
+        ioctl::crtc_queue_sequence(fd, &mut queue)?;
+    }
+
+    Ok(queue)
+}
@@ -176,6 +176,20 @@ pub fn rm_fb(fd: BorrowedFd<'_>, mut id: u32) -> io::Result<()> {
     Ok(())
 }
 
+/// Close a framebuffer without disabling the planes using it.
+pub fn close_fb(fd: BorrowedFd<'_>, fb_id: u32) -> io::Result<()> {
+    let mut close = drm_mode_closefb {
+        fb_id,
+        ..Default::default()
+    };
+
+    unsafe {
+        ioctl::mode::close_fb(fd, &mut close)?;
+    }
+
+    Ok(())
+}
+
 /// Mark a framebuffer as dirty.
 pub fn dirty_fb(
     fd: BorrowedFd<'_>,
@@ -737,12 +751,15 @@ pub fn set_property(
 }
 
 /// Schedule a page flip
+///
+/// `user_data` is returned in the page flip event.
 pub fn page_flip(
     fd: BorrowedFd<'_>,
     crtc_id: u32,
     fb_id: u32,
     flags: u32,
     sequence: u32,
+    user_data: u64,
 ) -> io::Result<()> {
     let mut flip = drm_mode_crtc_page_flip {
         crtc_id,
@@ -750,7 +767,7 @@ pub fn page_flip(
         flags,
         // Same struct as drm_mode_crtc_page_flip_target
         reserved: sequence,
-        user_data: crtc_id as _,
+        user_data,
     };
 
     unsafe {
@@ -761,6 +778,12 @@ pub fn page_flip(
 }
 
 /// Atomically set properties
+///
+/// `user_data` is returned in the page flip events of the affected CRTCs.
+///
+/// `prop_counts` holds the number of properties of each object, `props` and
+/// `values` hold the properties of all objects in order. Fails with `EINVAL`
+/// if their lengths do not match.
 pub fn atomic_commit(
     fd: BorrowedFd<'_>,
     flags: u32,
@@ -768,7 +791,16 @@ pub fn atomic_commit(
     prop_counts: &mut [u32],
     props: &mut [u32],
     values: &mut [u64],
+    user_data: u64,
 ) -> io::Result<()> {
+    let total = prop_counts
+        .iter()
+        .try_fold(0usize, |total, &count| total.checked_add(count as usize));
+    if prop_counts.len() != objs.len() || total != Some(props.len()) || values.len() != props.len()
+    {
+        return Err(rustix::io::Errno::INVAL.into());
+    }
+
     let mut atomic = drm_mode_atomic {
         flags,
         count_objs: objs.len() as _,
@@ -776,6 +808,7 @@ pub fn atomic_commit(
         count_props_ptr: prop_counts.as_mut_ptr() as _,
         props_ptr: props.as_mut_ptr() as _,
         prop_values_ptr: values.as_mut_ptr() as _,
+        user_data,
         ..Default::default()
     };
 
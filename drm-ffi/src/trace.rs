@@ -0,0 +1,637 @@
+//!
+//! Decoded ioctl tracing.
+//!
+//! With the `log` feature enabled, every ioctl is logged through the
+//! [`log`](https://docs.rs/log) crate with its name, the relevant fields of
+//! its argument, its result and its duration. Successful calls are logged at
+//! the `trace` level and failed calls at the `debug` level, both with the
+//! `drm_ffi::ioctl` target. Without the feature this compiles to nothing.
+//!
+
+#[cfg(feature = "log")]
+use std::{ffi::c_uint, fmt, io, ptr, time::Instant};
+
+#[cfg(feature = "log")]
+use drm_sys::*;
+#[cfg(feature = "log")]
+use rustix::io::Errno;
+
+/// Tracks a single ioctl call.
+pub(crate) struct Span {
+    #[cfg(feature = "log")]
+    request: u32,
+    #[cfg(feature = "log")]
+    start: Instant,
+    /// The decoded request of arguments which are overwritten by the reply
+    #[cfg(feature = "log")]
+    input: Option<String>,
+}
+
+#[cfg(not(feature = "log"))]
+impl Span {
+    #[inline(always)]
+    pub(crate) fn start(_request: u32) -> Span {
+        Span {}
+    }
+
+    #[inline(always)]
+    pub(crate) unsafe fn start_with<T>(_request: u32, _arg: &T) -> Span {
+        Span {}
+    }
+
+    #[inline(always)]
+    pub(crate) unsafe fn finish<T>(self, _arg: Option<&T>, _result: &std::io::Result<()>) {}
+}
+
+#[cfg(feature = "log")]
+impl Span {
+    pub(crate) fn start(request: u32) -> Span {
+        Span {
+            request,
+            start: Instant::now(),
+            input: None,
+        }
+    }
+
+    /// Starts tracking a call with an argument the kernel reads and writes.
+    ///
+    /// If the reply overwrites the request, see [`Decode::OVERWRITTEN`], the
+    /// request is decoded now to be logged along with the reply.
+    ///
+    /// # Safety
+    ///
+    /// Any pointers in `arg` must be valid.
+    pub(crate) unsafe fn start_with<T: Decode>(request: u32, arg: &T) -> Span {
+        let enabled = || log::log_enabled!(target: "drm_ffi::ioctl", log::Level::Debug);
+        Span {
+            input: (T::OVERWRITTEN && enabled()).then(|| Request(arg).to_string()),
+            ..Span::start(request)
+        }
+    }
+
+    /// Logs the call.
+    ///
+    /// # Safety
+    ///
+    /// Any pointers in `arg` must still be valid.
+    pub(crate) unsafe fn finish<T: Decode>(self, arg: Option<&T>, result: &io::Result<()>) {
+        let elapsed = self.start.elapsed();
+        let level = match result {
+            Ok(()) => log::Level::Trace,
+            Err(_) => log::Level::Debug,
+        };
+        if !log::log_enabled!(target: "drm_ffi::ioctl", level) {
+            return;
+        }
+
+        let name = Name(self.request);
+        let args = Args(arg);
+        let input: &dyn fmt::Display = match &self.input {
+            Some(input) => input,
+            None => &args,
+        };
+        match result {
+            Ok(()) if self.input.is_some() => log::trace!(
+                target: "drm_ffi::ioctl",
+                "{}({}) = 0, reply: {} ({:?})",
+                name,
+                input,
+                args,
+                elapsed
+            ),
+            Ok(()) => log::trace!(
+                target: "drm_ffi::ioctl",
+                "{}({}) = 0 ({:?})",
+                name,
+                input,
+                elapsed
+            ),
+            Err(err) => log::debug!(
+                target: "drm_ffi::ioctl",
+                "{}({}) = {} ({:?})",
+                name,
+                input,
+                ErrorName(err),
+                elapsed
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+struct Name(u32);
+
+#[cfg(feature = "log")]
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match crate::transport::request_nr(self.0) {
+            0x00 => "VERSION",
+            0x01 => "GET_UNIQUE",
+            0x02 => "GET_MAGIC",
+            0x03 => "IRQ_BUSID",
+            0x05 => "GET_CLIENT",
+            0x07 => "SET_VERSION",
+            0x09 => "GEM_CLOSE",
+            0x0a => "GEM_FLINK",
+            0x0b => "GEM_OPEN",
+            0x0c => "GET_CAP",
+            0x0d => "SET_CLIENT_CAP",
+            0x11 => "AUTH_MAGIC",
+            0x1e => "SET_MASTER",
+            0x1f => "DROP_MASTER",
+            0x2d => "PRIME_HANDLE_TO_FD",
+            0x2e => "PRIME_FD_TO_HANDLE",
+            0x3a => "WAIT_VBLANK",
+            0x3b => "CRTC_GET_SEQUENCE",
+            0x3c => "CRTC_QUEUE_SEQUENCE",
+            0xA0 => "MODE_GETRESOURCES",
+            0xA1 => "MODE_GETCRTC",
+            0xA2 => "MODE_SETCRTC",
+            0xA3 => "MODE_CURSOR",
+            0xA4 => "MODE_GETGAMMA",
+            0xA5 => "MODE_SETGAMMA",
+            0xA6 => "MODE_GETENCODER",
+            0xA7 => "MODE_GETCONNECTOR",
+            0xAA => "MODE_GETPROPERTY",
+            0xAB => "MODE_SETPROPERTY",
+            0xAC => "MODE_GETPROPBLOB",
+            0xAD => "MODE_GETFB",
+            0xAE => "MODE_ADDFB",
+            0xAF => "MODE_RMFB",
+            0xB0 => "MODE_PAGE_FLIP",
+            0xB1 => "MODE_DIRTYFB",
+            0xB2 => "MODE_CREATE_DUMB",
+            0xB3 => "MODE_MAP_DUMB",
+            0xB4 => "MODE_DESTROY_DUMB",
+            0xB5 => "MODE_GETPLANERESOURCES",
+            0xB6 => "MODE_GETPLANE",
+            0xB7 => "MODE_SETPLANE",
+            0xB8 => "MODE_ADDFB2",
+            0xB9 => "MODE_OBJ_GETPROPERTIES",
+            0xBA => "MODE_OBJ_SETPROPERTY",
+            0xBB => "MODE_CURSOR2",
+            0xBC => "MODE_ATOMIC",
+            0xBD => "MODE_CREATEPROPBLOB",
+            0xBE => "MODE_DESTROYPROPBLOB",
+            0xBF => "SYNCOBJ_CREATE",
+            0xC0 => "SYNCOBJ_DESTROY",
+            0xC1 => "SYNCOBJ_HANDLE_TO_FD",
+            0xC2 => "SYNCOBJ_FD_TO_HANDLE",
+            0xC3 => "SYNCOBJ_WAIT",
+            0xC4 => "SYNCOBJ_RESET",
+            0xC5 => "SYNCOBJ_SIGNAL",
+            0xC6 => "MODE_CREATE_LEASE",
+            0xC7 => "MODE_LIST_LESSEES",
+            0xC8 => "MODE_GET_LEASE",
+            0xC9 => "MODE_REVOKE_LEASE",
+            0xCA => "SYNCOBJ_TIMELINE_WAIT",
+            0xCB => "SYNCOBJ_QUERY",
+            0xCC => "SYNCOBJ_TRANSFER",
+            0xCD => "SYNCOBJ_TIMELINE_SIGNAL",
+            0xCE => "MODE_GETFB2",
+            0xCF => "SYNCOBJ_EVENTFD",
+            0xD0 => "MODE_CLOSEFB",
+            nr => return write!(f, "DRM_IOCTL_{:#04x}", nr),
+        };
+        write!(f, "DRM_IOCTL_{}", name)
+    }
+}
+
+#[cfg(feature = "log")]
+struct ErrorName<'a>(&'a io::Error);
+
+#[cfg(feature = "log")]
+impl fmt::Display for ErrorName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errno = match self.0.raw_os_error() {
+            Some(raw) => Errno::from_raw_os_error(raw),
+            None => return write!(f, "{}", self.0),
+        };
+        let name = match errno {
+            Errno::PERM => "EPERM",
+            Errno::NOENT => "ENOENT",
+            Errno::INTR => "EINTR",
+            Errno::IO => "EIO",
+            Errno::NXIO => "ENXIO",
+            Errno::BADF => "EBADF",
+            Errno::AGAIN => "EAGAIN",
+            Errno::NOMEM => "ENOMEM",
+            Errno::ACCESS => "EACCES",
+            Errno::FAULT => "EFAULT",
+            Errno::BUSY => "EBUSY",
+            Errno::EXIST => "EEXIST",
+            Errno::NODEV => "ENODEV",
+            Errno::INVAL => "EINVAL",
+            Errno::NOSPC => "ENOSPC",
+            Errno::NOTTY => "ENOTTY",
+            Errno::RANGE => "ERANGE",
+            Errno::NOSYS => "ENOSYS",
+            Errno::OPNOTSUPP => "EOPNOTSUPP",
+            Errno::TIMEDOUT => "ETIMEDOUT",
+            Errno::TIME => "ETIME",
+            _ => return write!(f, "errno {}", errno.raw_os_error()),
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(feature = "log")]
+struct Args<'a, T>(Option<&'a T>);
+
+#[cfg(feature = "log")]
+impl<T: Decode> fmt::Display for Args<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            // SAFETY: guaranteed by `Span::finish`
+            Some(arg) => unsafe { arg.decode(f) },
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+struct Request<'a, T>(&'a T);
+
+#[cfg(feature = "log")]
+impl<T: Decode> fmt::Display for Request<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // SAFETY: guaranteed by `Span::start_with`
+        unsafe { self.0.decode_request(f) }
+    }
+}
+
+/// Writes the relevant fields of an ioctl argument.
+#[cfg(feature = "log")]
+pub(crate) trait Decode {
+    /// Whether the kernel overwrites the request with its reply, like the
+    /// union of `drm_wait_vblank`, so it has to be decoded before the call.
+    const OVERWRITTEN: bool = false;
+
+    /// Writes the fields after the call.
+    ///
+    /// # Safety
+    ///
+    /// Any pointers in the argument must be valid.
+    unsafe fn decode(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+
+    /// Writes the fields before the call, see [`Decode::OVERWRITTEN`].
+    ///
+    /// # Safety
+    ///
+    /// Any pointers in the argument must be valid.
+    unsafe fn decode_request(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.decode(f)
+    }
+}
+
+#[cfg(feature = "log")]
+fn fields(f: &mut fmt::Formatter<'_>, fields: &[(&str, &dyn fmt::Debug)]) -> fmt::Result {
+    for (i, (name, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}={:?}", name, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "log")]
+fn flags(f: &mut fmt::Formatter<'_>, value: u32, names: &[(u32, &str)]) -> fmt::Result {
+    let mut rest = value;
+    let mut first = true;
+    for (flag, name) in names {
+        if value & flag == *flag && *flag != 0 {
+            if !first {
+                f.write_str("|")?;
+            }
+            f.write_str(name)?;
+            rest &= !flag;
+            first = false;
+        }
+    }
+    if rest != 0 || first {
+        if !first {
+            f.write_str("|")?;
+        }
+        write!(f, "{:#x}", rest)?;
+    }
+    Ok(())
+}
+
+/// Implements [`Decode`] by listing the given fields.
+macro_rules! decode_fields {
+    ($($ty:ty { $($field:ident),* $(,)? })*) => {
+        $(
+            #[cfg(feature = "log")]
+            impl Decode for $ty {
+                unsafe fn decode(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    fields(f, &[$((stringify!($field), &self.$field)),*])
+                }
+            }
+        )*
+    };
+}
+
+/// Implements [`Decode`] for arguments without interesting fields.
+macro_rules! decode_nothing {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            #[cfg(feature = "log")]
+            impl Decode for $ty {}
+        )*
+    };
+}
+
+decode_fields! {
+    drm_get_cap { capability, value }
+    drm_set_client_cap { capability, value }
+    drm_set_version { drm_di_major, drm_di_minor, drm_dd_major, drm_dd_minor }
+    drm_auth { magic }
+    drm_client { idx, auth }
+    drm_mode_card_res { count_fbs, count_crtcs, count_connectors, count_encoders }
+    drm_mode_get_plane_res { count_planes }
+    drm_mode_get_connector {
+        connector_id, encoder_id, connection, count_modes, count_props, count_encoders,
+    }
+    drm_mode_get_encoder { encoder_id, encoder_type, crtc_id, possible_crtcs }
+    drm_mode_crtc { crtc_id, fb_id, x, y, mode_valid, count_connectors }
+    drm_mode_crtc_lut { crtc_id, gamma_size }
+    drm_mode_fb_cmd { fb_id, width, height, pitch, bpp, depth, handle }
+    drm_mode_closefb { fb_id }
+    drm_mode_get_plane { plane_id, crtc_id, fb_id, possible_crtcs, count_format_types }
+    drm_mode_set_plane {
+        plane_id, crtc_id, fb_id, flags, crtc_x, crtc_y, crtc_w, crtc_h, src_x, src_y, src_w,
+        src_h,
+    }
+    drm_mode_create_dumb { width, height, bpp, flags, handle, pitch, size }
+    drm_mode_map_dumb { handle, offset }
+    drm_mode_destroy_dumb { handle }
+    drm_mode_cursor { flags, crtc_id, x, y, width, height, handle }
+    drm_mode_cursor2 { flags, crtc_id, x, y, width, height, handle, hot_x, hot_y }
+    drm_mode_connector_set_property { connector_id, prop_id, value }
+    drm_mode_obj_get_properties { obj_id, obj_type, count_props }
+    drm_mode_obj_set_property { obj_id, obj_type, prop_id, value }
+    drm_mode_get_blob { blob_id, length }
+    drm_mode_create_blob { blob_id, length }
+    drm_mode_destroy_blob { blob_id }
+    drm_mode_fb_dirty_cmd { fb_id, flags, color, num_clips }
+    drm_mode_create_lease { object_count, flags, lessee_id }
+    drm_mode_list_lessees { count_lessees }
+    drm_mode_get_lease { count_objects }
+    drm_mode_revoke_lease { lessee_id }
+    drm_gem_open { name, handle, size }
+    drm_gem_close { handle }
+    drm_gem_flink { handle, name }
+    drm_prime_handle { handle, flags, fd }
+    drm_syncobj_create { handle, flags }
+    drm_syncobj_destroy { handle }
+    drm_syncobj_handle { handle, flags, fd }
+    drm_syncobj_wait { count_handles, timeout_nsec, flags, first_signaled }
+    drm_syncobj_timeline_wait { count_handles, timeout_nsec, flags, first_signaled }
+    drm_syncobj_array { count_handles }
+    drm_syncobj_timeline_array { count_handles, flags }
+    drm_syncobj_transfer { src_handle, dst_handle, src_point, dst_point, flags }
+    drm_syncobj_eventfd { handle, flags, point, fd }
+    drm_crtc_get_sequence { crtc_id, active, sequence, sequence_ns }
+    drm_crtc_queue_sequence { crtc_id, flags, sequence, user_data }
+}
+
+decode_nothing!((), drm_unique, drm_version, drm_irq_busid);
+
+#[cfg(feature = "log")]
+impl Decode for c_uint {
+    unsafe fn decode(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[cfg(feature = "log")]
+impl Decode for drm_wait_vblank {
+    const OVERWRITTEN: bool = true;
+
+    unsafe fn decode(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // After a successful call the union holds the reply
+        let reply = self.reply;
+        fields(
+            f,
+            &[
+                ("type", &reply.type_),
+                ("sequence", &reply.sequence),
+                ("tval_sec", &reply.tval_sec),
+                ("tval_usec", &reply.tval_usec),
+            ],
+        )
+    }
+
+    unsafe fn decode_request(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let request = self.request;
+        fields(
+            f,
+            &[
+                ("type", &request.type_),
+                ("sequence", &request.sequence),
+                ("signal", &request.signal),
+            ],
+        )
+    }
+}
+
+#[cfg(feature = "log")]
+impl Decode for drm_mode_get_property {
+    unsafe fn decode(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name: Vec<u8> = self
+            .name
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as u8)
+            .collect();
+        fields(
+            f,
+            &[
+                ("prop_id", &self.prop_id),
+                ("name", &String::from_utf8_lossy(&name)),
+                ("flags", &format_args!("{:#x}", self.flags)),
+                ("count_values", &self.count_values),
+                ("count_enum_blobs", &self.count_enum_blobs),
+            ],
+        )
+    }
+}
+
+#[cfg(feature = "log")]
+impl Decode for drm_mode_fb_cmd2 {
+    unsafe fn decode(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = self.pixel_format.to_le_bytes().map(|b| b as char);
+        fields(
+            f,
+            &[
+                ("fb_id", &self.fb_id),
+                ("width", &self.width),
+                ("height", &self.height),
+                ("pixel_format", &format.iter().collect::<String>()),
+                ("flags", &self.flags),
+                ("handles", &self.handles),
+                ("pitches", &self.pitches),
+                ("offsets", &self.offsets),
+                ("modifier", &format_args!("{:#x}", self.modifier[0])),
+            ],
+        )
+    }
+}
+
+#[cfg(feature = "log")]
+const PAGE_FLIP_FLAGS: &[(u32, &str)] = &[
+    (DRM_MODE_PAGE_FLIP_EVENT, "EVENT"),
+    (DRM_MODE_PAGE_FLIP_ASYNC, "ASYNC"),
+    (DRM_MODE_PAGE_FLIP_TARGET_ABSOLUTE, "TARGET_ABSOLUTE"),
+    (DRM_MODE_PAGE_FLIP_TARGET_RELATIVE, "TARGET_RELATIVE"),
+    (DRM_MODE_ATOMIC_TEST_ONLY, "TEST_ONLY"),
+    (DRM_MODE_ATOMIC_NONBLOCK, "NONBLOCK"),
+    (DRM_MODE_ATOMIC_ALLOW_MODESET, "ALLOW_MODESET"),
+];
+
+#[cfg(feature = "log")]
+impl Decode for drm_mode_crtc_page_flip {
+    unsafe fn decode(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "crtc_id={}, fb_id={}, flags=", self.crtc_id, self.fb_id)?;
+        flags(f, self.flags, &PAGE_FLIP_FLAGS[..4])?;
+        write!(
+            f,
+            ", sequence={}, user_data={:#x}",
+            self.reserved, self.user_data
+        )
+    }
+}
+
+#[cfg(feature = "log")]
+impl Decode for drm_mode_atomic {
+    unsafe fn decode(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("flags=")?;
+        flags(f, self.flags, PAGE_FLIP_FLAGS)?;
+        write!(f, ", user_data={:#x}, objs=[", self.user_data)?;
+
+        // Decoding stops at the first null or out of range pointer
+        let ptr = |raw: u64| usize::try_from(raw).ok().filter(|&ptr| ptr != 0);
+        let (objs, counts) = match (ptr(self.objs_ptr), ptr(self.count_props_ptr)) {
+            (Some(objs), Some(counts)) => (objs as *const u32, counts as *const u32),
+            _ => return f.write_str("]"),
+        };
+        let props = ptr(self.props_ptr).map(|props| props as *const u32);
+        let values = ptr(self.prop_values_ptr).map(|values| values as *const u64);
+
+        let mut index = 0;
+        for i in 0..self.count_objs as usize {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            let obj = ptr::read_unaligned(objs.add(i));
+            let count = ptr::read_unaligned(counts.add(i)) as usize;
+            write!(f, "{} {{", obj)?;
+            for j in 0..count {
+                let (props, values) = match (props, values) {
+                    (Some(props), Some(values)) => (props, values),
+                    _ => return f.write_str(" ... }]"),
+                };
+                if j > 0 {
+                    f.write_str(",")?;
+                }
+                let prop = ptr::read_unaligned(props.add(index));
+                let value = ptr::read_unaligned(values.add(index));
+                write!(f, " {}={}", prop, value)?;
+                index += 1;
+            }
+            f.write_str(" }")?;
+        }
+        f.write_str("]")
+    }
+}
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use super::*;
+
+    fn decode<T: Decode>(arg: &T) -> String {
+        Args(Some(arg)).to_string()
+    }
+
+    #[test]
+    fn atomic() {
+        let mut objs = [31u32, 40];
+        let mut counts = [2u32, 1];
+        let mut props = [1u32, 2, 3];
+        let mut values = [10u64, 20, 30];
+        let atomic = drm_mode_atomic {
+            flags: DRM_MODE_ATOMIC_TEST_ONLY | DRM_MODE_ATOMIC_ALLOW_MODESET,
+            count_objs: 2,
+            objs_ptr: objs.as_mut_ptr() as _,
+            count_props_ptr: counts.as_mut_ptr() as _,
+            props_ptr: props.as_mut_ptr() as _,
+            prop_values_ptr: values.as_mut_ptr() as _,
+            user_data: 0x2a,
+            ..Default::default()
+        };
+        assert_eq!(
+            decode(&atomic),
+            "flags=TEST_ONLY|ALLOW_MODESET, user_data=0x2a, objs=[31 { 1=10, 2=20 }, 40 { 3=30 }]"
+        );
+
+        let atomic = drm_mode_atomic {
+            prop_values_ptr: 0,
+            ..atomic
+        };
+        assert_eq!(
+            decode(&atomic),
+            "flags=TEST_ONLY|ALLOW_MODESET, user_data=0x2a, objs=[31 { ... }]"
+        );
+        let atomic = drm_mode_atomic {
+            objs_ptr: 0,
+            ..atomic
+        };
+        assert_eq!(
+            decode(&atomic),
+            "flags=TEST_ONLY|ALLOW_MODESET, user_data=0x2a, objs=[]"
+        );
+    }
+
+    #[test]
+    fn wait_vblank() {
+        let mut vblank = drm_wait_vblank {
+            request: drm_wait_vblank_request {
+                type_: drm_vblank_seq_type::_DRM_VBLANK_RELATIVE,
+                sequence: 1,
+                signal: 0,
+            },
+        };
+        assert_eq!(Request(&vblank).to_string(), "type=1, sequence=1, signal=0");
+
+        vblank.reply = drm_wait_vblank_reply {
+            type_: drm_vblank_seq_type::_DRM_VBLANK_RELATIVE,
+            sequence: 120,
+            tval_sec: 5,
+            tval_usec: 250,
+        };
+        assert_eq!(
+            decode(&vblank),
+            "type=1, sequence=120, tval_sec=5, tval_usec=250"
+        );
+    }
+
+    #[test]
+    fn get_property() {
+        let mut info = drm_mode_get_property {
+            prop_id: 7,
+            flags: DRM_MODE_PROP_RANGE,
+            count_values: 2,
+            ..Default::default()
+        };
+        for (c, b) in info.name.iter_mut().zip(b"SRC_W") {
+            *c = *b as _;
+        }
+        assert_eq!(
+            decode(&info),
+            "prop_id=7, name=\"SRC_W\", flags=0x2, count_values=2, count_enum_blobs=0"
+        );
+    }
+}
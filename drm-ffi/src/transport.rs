@@ -0,0 +1,155 @@
+//!
+//! Pluggable ioctl transport.
+//!
+//! By default every ioctl is issued directly on the file descriptor. A
+//! [`Transport`] registered for a file descriptor intercepts the ioctls issued
+//! on it instead, which allows answering them from userspace. This is useful
+//! to test modesetting logic deterministically without a GPU.
+//!
+//! Transports are keyed by the raw file descriptor, so a transport must be
+//! unregistered before its file descriptor is closed.
+//!
+
+use std::{
+    ffi::c_void,
+    io,
+    os::unix::io::{AsRawFd, BorrowedFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// Handles the ioctls issued on a file descriptor.
+pub trait Transport: Send + Sync {
+    /// Handles an ioctl issued on a file descriptor this transport is
+    /// registered for.
+    ///
+    /// `request` is the ioctl request number, its [`request_nr`] and
+    /// [`request_size`] describe the command and the size of the argument
+    /// struct. `arg` points to the argument struct, or is null for ioctls
+    /// without argument. For requests which only pass data to the kernel,
+    /// `arg` points to a copy of it, so changes to it are discarded.
+    ///
+    /// Returning `None` passes the ioctl on to the kernel.
+    ///
+    /// # Safety
+    ///
+    /// `arg` must either be null or point to a valid argument struct of the
+    /// size encoded in `request`.
+    unsafe fn ioctl(
+        &self,
+        fd: BorrowedFd<'_>,
+        request: u32,
+        arg: *mut c_void,
+    ) -> Option<io::Result<()>>;
+
+    /// Returns the file descriptor to map the memory of a file descriptor this
+    /// transport is registered for from, e.g. the offsets of dumb buffers.
+    ///
+    /// Returning `None` maps the file descriptor itself.
+    fn map_fd(&self, _fd: BorrowedFd<'_>) -> Option<BorrowedFd<'_>> {
+        None
+    }
+}
+
+/// Set whenever at least one transport is registered, so that the common case
+/// does not need to take the lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static TRANSPORTS: RwLock<Vec<(RawFd, Arc<dyn Transport>)>> = RwLock::new(Vec::new());
+
+/// Registers a transport for a file descriptor, returning the transport it
+/// replaces.
+///
+/// The registration is keyed by the number of the file descriptor, not by the
+/// open file. If the file descriptor is closed while the transport is still
+/// registered, the number may be reused by an unrelated file, whose ioctls
+/// are then answered by the stale transport. Always [`unregister`] before
+/// closing the file descriptor.
+pub fn register(fd: BorrowedFd<'_>, transport: Arc<dyn Transport>) -> Option<Arc<dyn Transport>> {
+    let fd = fd.as_raw_fd();
+    let mut transports = TRANSPORTS.write().unwrap_or_else(|e| e.into_inner());
+
+    let previous = match transports.iter_mut().find(|(raw, _)| *raw == fd) {
+        Some((_, current)) => Some(std::mem::replace(current, transport)),
+        None => {
+            transports.push((fd, transport));
+            None
+        }
+    };
+    ACTIVE.store(true, Ordering::Release);
+
+    previous
+}
+
+/// Registers a transport for a file descriptor, unless one is registered
+/// already.
+///
+/// Fails with [`io::ErrorKind::AlreadyExists`] instead of replacing the
+/// registered transport, see [`register`] for details.
+pub fn try_register(fd: BorrowedFd<'_>, transport: Arc<dyn Transport>) -> io::Result<()> {
+    let fd = fd.as_raw_fd();
+    let mut transports = TRANSPORTS.write().unwrap_or_else(|e| e.into_inner());
+
+    if transports.iter().any(|(raw, _)| *raw == fd) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "a transport is already registered for the file descriptor",
+        ));
+    }
+    transports.push((fd, transport));
+    ACTIVE.store(true, Ordering::Release);
+
+    Ok(())
+}
+
+/// Unregisters the transport of a file descriptor, returning it.
+pub fn unregister(fd: BorrowedFd<'_>) -> Option<Arc<dyn Transport>> {
+    let fd = fd.as_raw_fd();
+    let mut transports = TRANSPORTS.write().unwrap_or_else(|e| e.into_inner());
+
+    let index = transports.iter().position(|(raw, _)| *raw == fd)?;
+    let (_, transport) = transports.swap_remove(index);
+    ACTIVE.store(!transports.is_empty(), Ordering::Release);
+
+    Some(transport)
+}
+
+/// Returns the transport registered for a file descriptor.
+pub fn get(fd: BorrowedFd<'_>) -> Option<Arc<dyn Transport>> {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return None;
+    }
+
+    let fd = fd.as_raw_fd();
+    let transports = TRANSPORTS.read().unwrap_or_else(|e| e.into_inner());
+    transports
+        .iter()
+        .find(|(raw, _)| *raw == fd)
+        .map(|(_, transport)| transport.clone())
+}
+
+/// Returns the command number of an ioctl request, e.g. `0xA0` for
+/// `DRM_IOCTL_MODE_GETRESOURCES`.
+pub fn request_nr(request: u32) -> u8 {
+    request as u8
+}
+
+/// Returns the size of the argument struct of an ioctl request.
+pub fn request_size(request: u32) -> usize {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const SIZE_MASK: u32 = 0x3fff;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const SIZE_MASK: u32 = 0x1fff;
+
+    ((request >> 16) & SIZE_MASK) as usize
+}
+
+/// Passes an ioctl to the transport registered for the file descriptor, if any.
+pub(crate) unsafe fn intercept(
+    fd: BorrowedFd<'_>,
+    request: u32,
+    arg: *mut c_void,
+) -> Option<io::Result<()>> {
+    get(fd)?.ioctl(fd, request, arg)
+}
@@ -11,6 +11,7 @@ pub fn main() {
 
     // Get the Bus ID of the device
     println!("Getting Bus ID: {:?}", card.get_bus_id().unwrap());
+    println!("Getting Bus info: {:?}", card.get_bus_info());
 
     // Figure out driver in use
     println!("Getting driver info");
@@ -30,4 +31,5 @@ pub fn main() {
     for &cap in capabilities::DRIVER_CAP_ENUMS {
         println!("\t{:?}: {:?}", cap, card.get_driver_capability(cap));
     }
+    println!("{:#?}", card.get_driver_capabilities());
 }
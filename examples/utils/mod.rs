@@ -29,7 +29,19 @@ impl Card {
     }
 
     pub fn open_global() -> Self {
-        Self::open("/dev/dri/card0")
+        // Pick the primary node of the first device, if any can be found.
+        let path = drm::node::enumerate()
+            .ok()
+            .and_then(|devices| {
+                devices.iter().find_map(|device| {
+                    device
+                        .path(drm::node::NodeType::Primary)
+                        .and_then(|path| path.to_str())
+                        .map(str::to_owned)
+                })
+            })
+            .unwrap_or_else(|| "/dev/dri/card0".to_owned());
+        Self::open(&path)
     }
 }
 
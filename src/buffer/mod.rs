@@ -16,13 +16,13 @@
 //! There are two methods of sharing a GEM handle between processes:
 //!
 //! 1. Using `Flink` to globally publish a handle using a 32-bit 'name'. This
-//! requires either holding the DRM Master lock or having the process'
-//! [`AuthToken`](struct@crate::AuthToken) authenticated. However, any process can
-//! open these handles if they know (or even guess) the global name.
+//!    requires either holding the DRM Master lock or having the process'
+//!    [`AuthToken`](struct@crate::AuthToken) authenticated. However, any process can
+//!    open these handles if they know (or even guess) the global name.
 //!
 //! 2. Converting the GEM handle into a PRIME file descriptor, and passing it
-//! like a regular one. This allows better control and security, and is the
-//! recommended method of sharing buffers.
+//!    like a regular one. This allows better control and security, and is the
+//!    recommended method of sharing buffers.
 
 use crate::control;
 pub use drm_fourcc::{DrmFourcc, DrmModifier, DrmVendor, UnrecognizedFourcc, UnrecognizedVendor};
@@ -36,6 +36,11 @@ pub use drm_fourcc::{DrmFourcc, DrmModifier, DrmVendor, UnrecognizedFourcc, Unre
 /// prevent buffers from leaking by properly closing them after they are done.
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Handle(control::RawResourceHandle);
 
 // Safety: Handle is repr(transparent) over NonZeroU32
@@ -75,6 +80,11 @@ impl std::fmt::Debug for Handle {
 /// prevent buffers from leaking by properly closing them after they are done.
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Name(u32);
 
 impl From<Name> for u32 {
@@ -83,6 +93,12 @@ impl From<Name> for u32 {
     }
 }
 
+impl From<u32> for Name {
+    fn from(name: u32) -> Name {
+        Name(name)
+    }
+}
+
 impl std::fmt::Debug for Name {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_tuple("buffer::Name").field(&self.0).finish()
@@ -12,6 +12,11 @@ use drm_ffi as ffi;
 /// A handle to a connector
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Handle(control::RawResourceHandle);
 
 // Safety: Handle is repr(transparent) over NonZeroU32
@@ -48,6 +53,7 @@ impl std::fmt::Debug for Handle {
 
 /// Information about a connector
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Info {
     pub(crate) handle: Handle,
     pub(crate) interface: Interface,
@@ -121,6 +127,7 @@ impl Info {
 #[allow(clippy::upper_case_acronyms)]
 #[non_exhaustive]
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Interface {
     Unknown,
     VGA,
@@ -235,6 +242,7 @@ impl From<Interface> for u32 {
 /// The state of a connector.
 #[allow(missing_docs)]
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum State {
     Connected,
     Disconnected,
@@ -268,6 +276,7 @@ impl From<State> for u32 {
 /// Subpixel order of the connected sink
 #[non_exhaustive]
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SubPixel {
     /// Unknown geometry
     Unknown,
@@ -9,6 +9,11 @@ use drm_ffi as ffi;
 /// A handle to an encoder
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Handle(control::RawResourceHandle);
 
 // Safety: Handle is repr(transparent) over NonZeroU32
@@ -45,6 +50,7 @@ impl std::fmt::Debug for Handle {
 
 /// Information about an encoder
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Info {
     pub(crate) handle: Handle,
     pub(crate) enc_type: Kind,
@@ -83,9 +89,13 @@ impl Info {
         control::CrtcListFilter(self.pos_crtcs)
     }
 
-    /// Returns a filter for the possible encoders that clones this one.
-    pub fn possible_clones(&self) {
-        unimplemented!()
+    /// Returns a filter for the possible encoders that can clone this one,
+    /// i.e. be driven by the same CRTC at the same time.
+    ///
+    /// Use with [`control::ResourceHandles::filter_encoders`]
+    /// to receive a list of encoders.
+    pub fn possible_clones(&self) -> control::EncoderListFilter {
+        control::EncoderListFilter(self.pos_clones)
     }
 }
 
@@ -93,6 +103,7 @@ impl Info {
 #[allow(missing_docs)]
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Kind {
     None,
     DAC,
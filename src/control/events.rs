@@ -0,0 +1,383 @@
+//!
+//! # Events
+//!
+//! Integrations for receiving [`Event`]s in event loops and async executors.
+//!
+//! * [`EventStream`] implements [`Stream`](futures_core::Stream) on top of
+//!   the readiness notifications of [tokio](https://docs.rs/tokio) (with the
+//!   `tokio` feature) or [async-io](https://docs.rs/async-io) (with the
+//!   `async-io` feature).
+//! * [`EventSource`] is an event source for
+//!   [calloop](https://docs.rs/calloop) (with the `calloop` feature).
+//!
+//! Both switch the file descriptor of the device to non-blocking mode, and
+//! read all pending events with an [`EventReader`] whenever it becomes
+//! readable. Note that this also affects other users of the same file
+//! description, such as [`Device::receive_events`], which fails with
+//! [`WouldBlock`](io::ErrorKind::WouldBlock) instead of blocking afterwards.
+//!
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+use std::collections::VecDeque;
+use std::io;
+#[cfg(any(feature = "tokio", feature = "calloop"))]
+use std::os::unix::io::BorrowedFd;
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+use rustix::io::Errno;
+
+use super::{Device, Event, EventReader};
+
+#[cfg(any(feature = "tokio", feature = "calloop"))]
+fn set_nonblocking(fd: BorrowedFd<'_>) -> io::Result<()> {
+    let flags = rustix::fs::fcntl_getfl(fd)?;
+    rustix::fs::fcntl_setfl(fd, flags | rustix::fs::OFlags::NONBLOCK)?;
+    Ok(())
+}
+
+/// Reads all pending events into `queue`.
+///
+/// Fails with [`WouldBlock`](io::ErrorKind::WouldBlock) if there are none.
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+fn read_events<D: Device + ?Sized>(
+    reader: &mut EventReader,
+    device: &D,
+    queue: &mut VecDeque<Event>,
+) -> io::Result<()> {
+    match reader.read(device, |event| queue.push_back(event.into()))? {
+        0 => Err(Errno::AGAIN.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "tokio")]
+struct TokioFd<D>(D);
+
+#[cfg(feature = "tokio")]
+impl<D: std::os::unix::io::AsFd> std::os::unix::io::AsRawFd for TokioFd<D> {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.0.as_fd().as_raw_fd()
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+enum Registration<D: Device> {
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::io::unix::AsyncFd<TokioFd<D>>),
+    #[cfg(feature = "async-io")]
+    AsyncIo(async_io::Async<D>),
+}
+
+/// A [`Stream`](futures_core::Stream) of the [`Event`]s of a device.
+///
+/// The device can be owned or borrowed, e.g. as `&Card` or `Arc<Card>`.
+///
+/// ```no_run
+/// # async fn example() -> std::io::Result<()> {
+/// use drm::control::{events::EventStream, Event};
+/// use drm::device::OwnedDevice;
+/// use futures_core::Stream;
+/// use std::future::poll_fn;
+/// use std::pin::Pin;
+///
+/// let card = OwnedDevice::open("/dev/dri/card0")?;
+/// let mut events = EventStream::tokio(&card)?;
+/// while let Some(event) = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await {
+///     if let Event::PageFlip(flip) = event? {
+///         println!("Page flip on {:?}", flip.crtc);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub struct EventStream<D: Device> {
+    registration: Registration<D>,
+    reader: EventReader,
+    queue: VecDeque<Event>,
+}
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+impl<D: Device> EventStream<D> {
+    /// Creates a stream driven by the tokio reactor.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called outside of a tokio runtime with IO enabled.
+    #[cfg(feature = "tokio")]
+    pub fn tokio(device: D) -> io::Result<Self> {
+        use tokio::io::{unix::AsyncFd, Interest};
+
+        set_nonblocking(device.as_fd())?;
+        let fd = AsyncFd::with_interest(TokioFd(device), Interest::READABLE)?;
+
+        Ok(EventStream {
+            registration: Registration::Tokio(fd),
+            reader: EventReader::new(),
+            queue: VecDeque::new(),
+        })
+    }
+
+    /// Creates a stream driven by the async-io reactor.
+    #[cfg(feature = "async-io")]
+    pub fn async_io(device: D) -> io::Result<Self> {
+        // Also switches the file descriptor to non-blocking mode
+        let fd = async_io::Async::new(device)?;
+
+        Ok(EventStream {
+            registration: Registration::AsyncIo(fd),
+            reader: EventReader::new(),
+            queue: VecDeque::new(),
+        })
+    }
+
+    /// Returns the device of this stream.
+    pub fn get_ref(&self) -> &D {
+        match &self.registration {
+            #[cfg(feature = "tokio")]
+            Registration::Tokio(fd) => &fd.get_ref().0,
+            #[cfg(feature = "async-io")]
+            Registration::AsyncIo(fd) => fd.get_ref(),
+        }
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+impl<D: Device + Unpin> futures_core::Stream for EventStream<D> {
+    type Item = io::Result<Event>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use std::task::{ready, Poll};
+
+        let this = self.get_mut();
+        loop {
+            if let Some(event) = this.queue.pop_front() {
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            match &mut this.registration {
+                #[cfg(feature = "tokio")]
+                Registration::Tokio(fd) => {
+                    let mut guard = ready!(fd.poll_read_ready(cx))?;
+                    // Clears the readiness on `WouldBlock`
+                    let (reader, queue) = (&mut this.reader, &mut this.queue);
+                    if let Ok(result) =
+                        guard.try_io(|fd| read_events(reader, &fd.get_ref().0, queue))
+                    {
+                        result?;
+                    }
+                }
+                #[cfg(feature = "async-io")]
+                Registration::AsyncIo(fd) => {
+                    match read_events(&mut this.reader, fd.get_ref(), &mut this.queue) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            ready!(fd.poll_readable(cx))?;
+                        }
+                        Err(err) => return Poll::Ready(Some(Err(err))),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A [calloop](https://docs.rs/calloop) event source dispatching the
+/// [`Event`]s of a device.
+///
+/// The device can be owned or borrowed, e.g. as `Arc<Card>`.
+///
+/// ```no_run
+/// use drm::control::{events::EventSource, Event};
+/// use drm::device::OwnedDevice;
+///
+/// let card = OwnedDevice::open("/dev/dri/card0").unwrap();
+/// let mut event_loop = calloop::EventLoop::<()>::try_new().unwrap();
+/// event_loop
+///     .handle()
+///     .insert_source(EventSource::new(card).unwrap(), |event, _, _| {
+///         if let Event::PageFlip(flip) = event {
+///             println!("Page flip on {:?}", flip.crtc);
+///         }
+///     })
+///     .unwrap();
+/// event_loop.run(None, &mut (), |_| {}).unwrap();
+/// ```
+#[cfg(feature = "calloop")]
+#[derive(Debug)]
+pub struct EventSource<D: Device> {
+    source: calloop::generic::Generic<D>,
+    reader: EventReader,
+}
+
+#[cfg(feature = "calloop")]
+impl<D: Device> EventSource<D> {
+    /// Creates an event source for a device.
+    pub fn new(device: D) -> io::Result<Self> {
+        use calloop::{Interest, Mode};
+
+        set_nonblocking(device.as_fd())?;
+        Ok(EventSource {
+            source: calloop::generic::Generic::new(device, Interest::READ, Mode::Level),
+            reader: EventReader::new(),
+        })
+    }
+
+    /// Returns the device of this event source.
+    pub fn get_ref(&self) -> &D {
+        self.source.get_ref()
+    }
+}
+
+#[cfg(feature = "calloop")]
+impl<D: Device> calloop::EventSource for EventSource<D> {
+    type Event = Event;
+    type Metadata = ();
+    type Ret = ();
+    type Error = io::Error;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: calloop::Readiness,
+        token: calloop::Token,
+        mut callback: C,
+    ) -> io::Result<calloop::PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let reader = &mut self.reader;
+        self.source.process_events(readiness, token, |_, device| {
+            match reader.read(device.as_ref(), |event| callback(event.into(), &mut ())) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+            Ok(calloop::PostAction::Continue)
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut calloop::Poll,
+        token_factory: &mut calloop::TokenFactory,
+    ) -> calloop::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut calloop::Poll,
+        token_factory: &mut calloop::TokenFactory,
+    ) -> calloop::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut calloop::Poll) -> calloop::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+#[cfg(all(
+    test,
+    any(feature = "tokio", feature = "async-io", feature = "calloop"),
+    feature = "emulator"
+))]
+mod tests {
+    use super::*;
+    use crate::control::{connector, crtc, encoder, framebuffer, PageFlipFlags, PlaneType};
+    use crate::device::OwnedDevice;
+    use crate::emulator::{self, Emulator};
+    use drm_fourcc::DrmFourcc;
+
+    fn setup() -> (OwnedDevice, crtc::Handle, framebuffer::Handle) {
+        let mut builder = Emulator::builder();
+        let crtc = builder.crtc();
+        let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+        let mode = emulator::mode(1920, 1080, 60);
+        let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &[mode]);
+        builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+        let (card, _emulator) = builder.build().unwrap();
+
+        let buffer = card
+            .create_dumb_buffer((1920, 1080), DrmFourcc::Xrgb8888, 32)
+            .unwrap();
+        let fb = card.add_framebuffer(&buffer, 24, 32).unwrap();
+        card.set_crtc(crtc, Some(fb), (0, 0), &[connector], Some(mode))
+            .unwrap();
+        (card, crtc, fb)
+    }
+
+    #[cfg(any(feature = "tokio", feature = "async-io"))]
+    #[test]
+    fn read_events_drains_more_than_a_kilobyte() {
+        use crate::control::CrtcSequenceFlags;
+
+        let (card, crtc, _) = setup();
+        // 64 sequence events of 32 bytes each
+        for user_data in 0..64 {
+            card.queue_crtc_sequence(crtc, 1, CrtcSequenceFlags::RELATIVE, user_data)
+                .unwrap();
+        }
+
+        let mut queue = VecDeque::new();
+        read_events(&mut EventReader::new(), &card, &mut queue).unwrap();
+        let seen: Vec<u64> = queue
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::CrtcSequence(event) => Some(event.user_data),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(seen, (0..64).collect::<Vec<_>>());
+
+        let err = read_events(&mut EventReader::new(), &card, &mut VecDeque::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_stream_delivers_page_flips() {
+        use futures_core::Stream;
+        use std::future::poll_fn;
+        use std::pin::Pin;
+
+        let (card, crtc, fb) = setup();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut events = EventStream::tokio(&card).unwrap();
+            card.page_flip(crtc, fb, PageFlipFlags::EVENT, None)
+                .unwrap();
+            let event = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await;
+            assert!(matches!(event, Some(Ok(Event::PageFlip(flip))) if flip.crtc == crtc));
+        });
+    }
+
+    #[cfg(feature = "calloop")]
+    #[test]
+    fn calloop_source_dispatches_page_flips() {
+        use std::time::Duration;
+
+        let (card, crtc, fb) = setup();
+        let mut event_loop = calloop::EventLoop::<Vec<Event>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(EventSource::new(&card).unwrap(), |event, _, events| {
+                events.push(event)
+            })
+            .unwrap();
+
+        card.page_flip(crtc, fb, PageFlipFlags::EVENT, None)
+            .unwrap();
+        let mut events = Vec::new();
+        event_loop
+            .dispatch(Some(Duration::from_secs(1)), &mut events)
+            .unwrap();
+        assert!(matches!(&events[..], [Event::PageFlip(flip)] if flip.crtc == crtc));
+    }
+}
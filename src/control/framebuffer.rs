@@ -10,6 +10,11 @@ use drm_fourcc::{DrmFourcc, DrmModifier};
 /// A handle to a framebuffer
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Handle(control::RawResourceHandle);
 
 // Safety: Handle is repr(transparent) over NonZeroU32
@@ -46,6 +51,7 @@ impl std::fmt::Debug for Handle {
 
 /// Information about a framebuffer
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Info {
     pub(crate) handle: Handle,
     pub(crate) size: (u32, u32),
@@ -95,6 +101,7 @@ impl Info {
 
 /// Information about a framebuffer (with modifiers)
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PlanarInfo {
     pub(crate) handle: Handle,
     pub(crate) size: (u32, u32),
@@ -6,20 +6,20 @@
 //! exposing the following resource types:
 //!
 //! * FrameBuffer - Specific to an individual process, these wrap around generic
-//! GPU buffers so that they can be attached to a Plane.
+//!   GPU buffers so that they can be attached to a Plane.
 //!
 //! * Planes - Dedicated memory objects which contain a buffer that can then be
-//! scanned out by a CRTC. There exist a few different types of planes depending
-//! on the use case.
+//!   scanned out by a CRTC. There exist a few different types of planes depending
+//!   on the use case.
 //!
 //! * CRTC - Scanout engines that read pixel data from a Plane and sends it to
-//! a Connector. Each CRTC has at least one Primary Plane.
+//!   a Connector. Each CRTC has at least one Primary Plane.
 //!
 //! * Connector - Represents the physical output, such as a DisplayPort or
-//! VGA connector.
+//!   VGA connector.
 //!
 //! * Encoder - Encodes pixel data from a CRTC into something a Connector can
-//! understand.
+//!   understand.
 //!
 //! Further details on each resource can be found in their respective modules.
 //!
@@ -39,9 +39,18 @@ pub mod connector;
 pub mod crtc;
 pub mod dumbbuffer;
 pub mod encoder;
+#[cfg(any(feature = "tokio", feature = "async-io", feature = "calloop"))]
+pub mod events;
 pub mod framebuffer;
+pub mod owned;
 pub mod plane;
+pub mod prime;
+pub mod routing;
+pub mod saved_state;
+pub mod snapshot;
 pub mod syncobj;
+pub mod timing;
+pub mod user_data;
 
 pub mod property;
 
@@ -54,11 +63,13 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::error;
 use std::fmt;
+use std::fs::File;
 use std::io;
 use std::iter::Zip;
 use std::mem;
 use std::ops::RangeBounds;
 use std::os::unix::io::{AsFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
 use std::time::Duration;
 
 use core::num::NonZeroU32;
@@ -126,8 +137,9 @@ impl From<UnrecognizedFourcc> for GetPlanarFramebufferError {
 /// This trait should be implemented by any object that acts as a DRM device and
 /// provides modesetting functionality.
 ///
-/// Like the parent [`super::Device`] trait, this crate does not
-/// provide a concrete object for this trait.
+/// Like the parent [`super::Device`] trait, this is implemented by
+/// [`crate::device::OwnedDevice`], [`OwnedFd`], [`File`], as well as references
+/// and [`Arc`]s of other devices.
 ///
 /// # Example
 /// ```ignore
@@ -214,7 +226,7 @@ pub trait Device: super::Device {
             },
             modes: Mode::wrap_vec(modes),
             encoders: unsafe { transmute_vec_from_u32(encoders) },
-            curr_enc: unsafe { mem::transmute(ffi_info.encoder_id) },
+            curr_enc: from_u32(ffi_info.encoder_id),
             subpixel: connector::SubPixel::from_raw(ffi_info.subpixel),
         };
 
@@ -404,6 +416,53 @@ pub trait Device: super::Device {
         ffi::mode::rm_fb(self.as_fd(), handle.into())
     }
 
+    /// Close a framebuffer
+    ///
+    /// Unlike [`Device::destroy_framebuffer()`], planes still scanning out the
+    /// framebuffer are not disabled, which allows the next DRM master to take
+    /// over without flickering. The framebuffer is destroyed once it is no
+    /// longer used.
+    ///
+    /// Falls back to [`Device::destroy_framebuffer()`] on kernels before 6.8,
+    /// which do not support this.
+    ///
+    /// ```
+    /// # #[cfg(feature = "emulator")] {
+    /// use drm::control::{connector, encoder, Device as ControlDevice, PlaneType};
+    /// use drm::emulator::{self, Emulator};
+    /// use drm_fourcc::DrmFourcc;
+    ///
+    /// let mut builder = Emulator::builder();
+    /// let crtc = builder.crtc();
+    /// let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+    /// let mode = emulator::mode(640, 480, 60);
+    /// let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &[mode]);
+    /// let plane = builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+    /// let (card, emulator) = builder.build().unwrap();
+    ///
+    /// let buffer = card.create_dumb_buffer((640, 480), DrmFourcc::Xrgb8888, 32).unwrap();
+    /// let fb = card.add_framebuffer(&buffer, 24, 32).unwrap();
+    /// card.set_crtc(crtc, Some(fb), (0, 0), &[connector], Some(mode)).unwrap();
+    ///
+    /// // The frame stays on screen
+    /// card.close_framebuffer(fb).unwrap();
+    /// assert_eq!(emulator.framebuffer(plane), Some(fb));
+    /// # }
+    /// ```
+    fn close_framebuffer(&self, handle: framebuffer::Handle) -> io::Result<()> {
+        match ffi::mode::close_fb(self.as_fd(), handle.into()) {
+            Err(err)
+                if matches!(
+                    Errno::from_io_error(&err),
+                    Some(Errno::INVAL | Errno::NOTTY)
+                ) =>
+            {
+                self.destroy_framebuffer(handle)
+            }
+            result => result,
+        }
+    }
+
     /// Returns information about a specific plane
     fn get_plane(&self, handle: plane::Handle) -> io::Result<plane::Info> {
         let mut formats = Vec::new();
@@ -421,6 +480,41 @@ pub trait Device: super::Device {
         Ok(plane)
     }
 
+    /// Returns the formats and modifiers supported by a plane, decoded from its
+    /// `IN_FORMATS` property.
+    ///
+    /// Returns `None` if the plane does not have the property, e.g. because
+    /// the driver does not support modifiers.
+    fn get_plane_format_modifiers(
+        &self,
+        handle: plane::Handle,
+    ) -> io::Result<Option<plane::FormatModifiers>> {
+        plane_format_modifiers(self, handle, "IN_FORMATS")
+    }
+
+    /// Returns the formats and modifiers supported by a plane for async page
+    /// flips, decoded from its `IN_FORMATS_ASYNC` property.
+    ///
+    /// Returns `None` if the plane does not have the property.
+    fn get_plane_async_format_modifiers(
+        &self,
+        handle: plane::Handle,
+    ) -> io::Result<Option<plane::FormatModifiers>> {
+        plane_format_modifiers(self, handle, "IN_FORMATS_ASYNC")
+    }
+
+    /// Gathers what a plane supports from its properties.
+    ///
+    /// Primary and cursor planes, and their properties, are only visible with
+    /// the [`UniversalPlanes`](crate::ClientCapability::UniversalPlanes) client
+    /// capability.
+    fn get_plane_capabilities(
+        &self,
+        handle: plane::Handle,
+    ) -> io::Result<plane::PlaneCapabilities> {
+        plane::PlaneCapabilities::query(self, handle)
+    }
+
     /// Set plane state.
     ///
     /// Providing no framebuffer clears the plane.
@@ -515,7 +609,13 @@ pub trait Device: super::Device {
             val_type,
             mutable: !flags.contains(ModePropFlags::IMMUTABLE),
             atomic: flags.contains(ModePropFlags::ATOMIC),
-            info,
+            // The pointers to the values and enums are dangling by now
+            info: ffi::drm_mode_get_property {
+                name: info.name,
+                prop_id: info.prop_id,
+                flags: info.flags,
+                ..Default::default()
+            },
         };
 
         Ok(property)
@@ -651,10 +751,20 @@ pub trait Device: super::Device {
         Ok(())
     }
 
-    /// Open a GEM buffer handle by name
-    fn open_buffer(&self, name: buffer::Name) -> io::Result<buffer::Handle> {
+    /// Open a GEM buffer handle by name, returning the handle and the size of the buffer
+    fn open_buffer(&self, name: buffer::Name) -> io::Result<(buffer::Handle, u64)> {
         let info = drm_ffi::gem::open(self.as_fd(), name.into())?;
-        Ok(from_u32(info.handle).unwrap())
+        Ok((from_u32(info.handle).unwrap(), info.size))
+    }
+
+    /// Publish a global name for a GEM buffer handle, which other processes can
+    /// open with [`Device::open_buffer()`]
+    ///
+    /// Any process that can guess the name can open the buffer, prefer
+    /// [`Device::buffer_to_prime_fd()`] where possible.
+    fn flink_buffer(&self, handle: buffer::Handle) -> io::Result<buffer::Name> {
+        let info = drm_ffi::gem::flink(self.as_fd(), handle.into())?;
+        Ok(buffer::Name::from(info.name))
     }
 
     /// Close a GEM buffer handle
@@ -691,6 +801,8 @@ pub trait Device: super::Device {
             let prot = mm::ProtFlags::READ | mm::ProtFlags::WRITE;
             let flags = mm::MapFlags::SHARED;
             let fd = self.as_fd();
+            let transport = drm_ffi::transport::get(fd);
+            let fd = transport.as_ref().and_then(|t| t.map_fd(fd)).unwrap_or(fd);
             let offset = info.offset as _;
             unsafe { mm::mmap(std::ptr::null_mut(), buffer.length, prot, flags, fd, offset)? }
         };
@@ -767,10 +879,29 @@ pub trait Device: super::Device {
 
     /// Request an atomic commit with given flags and property-value pair for a list of objects.
     fn atomic_commit(
+        &self,
+        flags: AtomicCommitFlags,
+        req: atomic::AtomicModeReq,
+    ) -> io::Result<()> {
+        self.atomic_commit_with_user_data(flags, req, 0)
+    }
+
+    /// Request an atomic commit like [`Device::atomic_commit()`], returning
+    /// `user_data` in the [`PageFlipEvent`] of every affected CRTC.
+    ///
+    /// Like for [`Device::page_flip_with_user_data()`], the highest bit of
+    /// `user_data` is reserved, passing it set fails with `EINVAL`.
+    ///
+    /// See [`user_data::UserDataRegistry`] for associating a payload with it.
+    fn atomic_commit_with_user_data(
         &self,
         flags: AtomicCommitFlags,
         mut req: atomic::AtomicModeReq,
+        user_data: u64,
     ) -> io::Result<()> {
+        if user_data & PAGE_FLIP_CRTC != 0 {
+            return Err(Errno::INVAL.into());
+        }
         drm_ffi::mode::atomic_commit(
             self.as_fd(),
             flags.bits(),
@@ -778,6 +909,7 @@ pub trait Device: super::Device {
             &mut req.count_props_per_object,
             unsafe { &mut *(&mut *req.props as *mut _ as *mut [u32]) },
             &mut req.values,
+            user_data,
         )
     }
 
@@ -801,29 +933,59 @@ pub trait Device: super::Device {
         flags: PageFlipFlags,
         target_sequence: Option<PageFlipTarget>,
     ) -> io::Result<()> {
-        let mut flags = flags.bits();
+        // Kernels before 4.12 don't report the crtc in the event, so pass it along
+        let user_data = PAGE_FLIP_CRTC | u64::from(u32::from(handle));
+        queue_page_flip(self, handle, framebuffer, flags, target_sequence, user_data)
+    }
 
-        let sequence = match target_sequence {
-            Some(PageFlipTarget::Absolute(n)) => {
-                flags |= ffi::drm_sys::DRM_MODE_PAGE_FLIP_TARGET_ABSOLUTE;
-                n
-            }
-            Some(PageFlipTarget::Relative(n)) => {
-                flags |= ffi::drm_sys::DRM_MODE_PAGE_FLIP_TARGET_RELATIVE;
-                n
-            }
-            None => 0,
-        };
+    /// Queue a page flip like [`Device::page_flip()`], returning `user_data`
+    /// in its [`PageFlipEvent`].
+    ///
+    /// The highest bit (bit 63) of `user_data` is reserved, passing it set
+    /// fails with `EINVAL`. Kernels before 4.12 don't report the crtc of page
+    /// flip events, unlike those of [`Device::page_flip()`] the events are
+    /// returned as [`Event::Unknown`] there.
+    ///
+    /// See [`user_data::UserDataRegistry`] for associating a payload with it.
+    fn page_flip_with_user_data(
+        &self,
+        handle: crtc::Handle,
+        framebuffer: framebuffer::Handle,
+        flags: PageFlipFlags,
+        target_sequence: Option<PageFlipTarget>,
+        user_data: u64,
+    ) -> io::Result<()> {
+        if user_data & PAGE_FLIP_CRTC != 0 {
+            return Err(Errno::INVAL.into());
+        }
+        queue_page_flip(self, handle, framebuffer, flags, target_sequence, user_data)
+    }
 
-        ffi::mode::page_flip(
-            self.as_fd(),
-            handle.into(),
-            framebuffer.into(),
-            flags,
-            sequence,
-        )?;
+    /// Returns the current vblank sequence of a CRTC.
+    fn get_crtc_sequence(&self, crtc: crtc::Handle) -> io::Result<CrtcSequence> {
+        let info = ffi::get_crtc_sequence(self.as_fd(), crtc.into())?;
 
-        Ok(())
+        Ok(CrtcSequence {
+            sequence: info.sequence,
+            time: Duration::from_nanos(info.sequence_ns.max(0) as u64),
+            active: info.active != 0,
+        })
+    }
+
+    /// Requests a [`Event::CrtcSequence`] once a CRTC reaches a vblank sequence.
+    ///
+    /// Returns the absolute sequence the event was queued for.
+    fn queue_crtc_sequence(
+        &self,
+        crtc: crtc::Handle,
+        target: u64,
+        flags: CrtcSequenceFlags,
+        user_data: u64,
+    ) -> io::Result<u64> {
+        let info =
+            ffi::queue_crtc_sequence(self.as_fd(), crtc.into(), flags.bits(), target, user_data)?;
+
+        Ok(info.sequence)
     }
 
     /// Creates a syncobj.
@@ -1003,6 +1165,49 @@ pub trait Device: super::Device {
     }
 }
 
+/// Set in the user data of page flips queued with [`Device::page_flip()`],
+/// whose lower 32 bits hold the crtc for kernels that don't report it.
+const PAGE_FLIP_CRTC: u64 = 1 << 63;
+
+fn queue_page_flip<D: Device + ?Sized>(
+    device: &D,
+    handle: crtc::Handle,
+    framebuffer: framebuffer::Handle,
+    flags: PageFlipFlags,
+    target_sequence: Option<PageFlipTarget>,
+    user_data: u64,
+) -> io::Result<()> {
+    let mut flags = flags.bits();
+
+    let sequence = match target_sequence {
+        Some(PageFlipTarget::Absolute(n)) => {
+            flags |= ffi::drm_sys::DRM_MODE_PAGE_FLIP_TARGET_ABSOLUTE;
+            n
+        }
+        Some(PageFlipTarget::Relative(n)) => {
+            flags |= ffi::drm_sys::DRM_MODE_PAGE_FLIP_TARGET_RELATIVE;
+            n
+        }
+        None => 0,
+    };
+
+    ffi::mode::page_flip(
+        device.as_fd(),
+        handle.into(),
+        framebuffer.into(),
+        flags,
+        sequence,
+        user_data,
+    )?;
+
+    Ok(())
+}
+
+impl Device for OwnedFd {}
+impl Device for File {}
+impl<T: Device + ?Sized> Device for &T {}
+impl<T: Device + ?Sized> Device for Arc<T> {}
+
 /// List of leased resources
 pub struct LeaseResources {
     /// leased crtcs
@@ -1083,6 +1288,28 @@ pub enum PageFlipTarget {
     Relative(u32),
 }
 
+bitflags::bitflags! {
+    /// Flags to alter the target of [`Device::queue_crtc_sequence()`]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct CrtcSequenceFlags : u32 {
+        /// The target is relative to the current sequence
+        const RELATIVE = ffi::DRM_CRTC_SEQUENCE_RELATIVE;
+        /// Use the next sequence if the target has already passed
+        const NEXT_ON_MISS = ffi::DRM_CRTC_SEQUENCE_NEXT_ON_MISS;
+    }
+}
+
+/// The vblank sequence of a CRTC, see [`Device::get_crtc_sequence()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CrtcSequence {
+    /// Number of the current vblank
+    pub sequence: u64,
+    /// Time at which the current vblank started, in `CLOCK_MONOTONIC`
+    pub time: Duration,
+    /// Whether the CRTC is active
+    pub active: bool,
+}
+
 /// Iterator over [`Event`]s of a device. Create via [`Device::receive_events()`].
 pub struct Events {
     event_buf: [u8; 1024],
@@ -1108,6 +1335,8 @@ pub enum Event {
     Vblank(VblankEvent),
     /// A page flip happened
     PageFlip(PageFlipEvent),
+    /// A vblank sequence queued with [`Device::queue_crtc_sequence()`] happened
+    CrtcSequence(CrtcSequenceEvent),
     /// Unknown event, raw data provided
     Unknown(Vec<u8>),
 }
@@ -1132,63 +1361,251 @@ pub struct PageFlipEvent {
     pub duration: Duration,
     /// crtc that did throw the event
     pub crtc: crtc::Handle,
+    /// user data that was passed to the page flip or atomic commit
+    ///
+    /// [`Device::page_flip()`] passes the crtc id with the highest bit set.
+    pub user_data: u64,
+}
+
+/// CRTC sequence event
+pub struct CrtcSequenceEvent {
+    /// sequence of the frame
+    pub sequence: u64,
+    /// time at which the vblank occurred, in `CLOCK_MONOTONIC`
+    pub time: Duration,
+    /// user data that was passed to queue_crtc_sequence
+    pub user_data: u64,
 }
 
 impl Iterator for Events {
     type Item = Event;
 
     fn next(&mut self) -> Option<Event> {
-        if self.amount > 0 && self.i < self.amount {
-            let event_ptr = unsafe { self.event_buf.as_ptr().add(self.i) as *const ffi::drm_event };
-            let event = unsafe { std::ptr::read_unaligned(event_ptr) };
-            self.i += event.length as usize;
-            match event.type_ {
-                ffi::DRM_EVENT_VBLANK => {
-                    let vblank_event = unsafe {
-                        std::ptr::read_unaligned(event_ptr as *const ffi::drm_event_vblank)
-                    };
-                    Some(Event::Vblank(VblankEvent {
-                        frame: vblank_event.sequence,
-                        time: Duration::new(
-                            vblank_event.tv_sec as u64,
-                            vblank_event.tv_usec * 1000,
-                        ),
-                        #[allow(clippy::unnecessary_cast)]
-                        crtc: from_u32(vblank_event.crtc_id as u32).unwrap(),
-                        user_data: vblank_event.user_data as usize,
-                    }))
-                }
-                ffi::DRM_EVENT_FLIP_COMPLETE => {
-                    let vblank_event = unsafe {
-                        std::ptr::read_unaligned(event_ptr as *const ffi::drm_event_vblank)
-                    };
-                    Some(Event::PageFlip(PageFlipEvent {
-                        frame: vblank_event.sequence,
-                        duration: Duration::new(
-                            vblank_event.tv_sec as u64,
-                            vblank_event.tv_usec * 1000,
-                        ),
-                        crtc: from_u32(if vblank_event.crtc_id != 0 {
-                            vblank_event.crtc_id
-                        } else {
-                            vblank_event.user_data as u32
-                        })
-                        .unwrap(),
-                    }))
-                }
-                _ => Some(Event::Unknown(
-                    self.event_buf[self.i - (event.length as usize)..self.i].to_vec(),
-                )),
+        let (event, length) = parse_event(self.event_buf.get(self.i..self.amount)?)?;
+        self.i += length;
+        Some(event.into())
+    }
+}
+
+/// An event from a device, borrowing the buffer of an [`EventReader`].
+pub enum EventRef<'a> {
+    /// A vblank happened
+    Vblank(VblankEvent),
+    /// A page flip happened
+    PageFlip(PageFlipEvent),
+    /// A vblank sequence queued with [`Device::queue_crtc_sequence()`] happened
+    CrtcSequence(CrtcSequenceEvent),
+    /// Unknown event, raw data provided
+    Unknown(&'a [u8]),
+}
+
+impl From<EventRef<'_>> for Event {
+    fn from(event: EventRef<'_>) -> Self {
+        match event {
+            EventRef::Vblank(event) => Event::Vblank(event),
+            EventRef::PageFlip(event) => Event::PageFlip(event),
+            EventRef::CrtcSequence(event) => Event::CrtcSequence(event),
+            EventRef::Unknown(data) => Event::Unknown(data.to_vec()),
+        }
+    }
+}
+
+/// Parses the event at the start of `buf`, returning it and its length.
+///
+/// Returns `None` if `buf` does not start with a complete event.
+fn parse_event(buf: &[u8]) -> Option<(EventRef<'_>, usize)> {
+    if buf.len() < mem::size_of::<ffi::drm_event>() {
+        return None;
+    }
+    let header = unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const ffi::drm_event) };
+    let length = header.length as usize;
+    if length < mem::size_of::<ffi::drm_event>() || length > buf.len() {
+        return None;
+    }
+
+    let data = &buf[..length];
+    let vblank_event = (length >= mem::size_of::<ffi::drm_event_vblank>()).then(|| unsafe {
+        std::ptr::read_unaligned(data.as_ptr() as *const ffi::drm_event_vblank)
+    });
+    let time =
+        |event: &ffi::drm_event_vblank| Duration::new(event.tv_sec as u64, event.tv_usec * 1000);
+
+    let event = match (header.type_, vblank_event) {
+        #[allow(clippy::unnecessary_cast)]
+        (ffi::DRM_EVENT_VBLANK, Some(event)) => match from_u32(event.crtc_id as u32) {
+            Some(crtc) => EventRef::Vblank(VblankEvent {
+                frame: event.sequence,
+                time: time(&event),
+                crtc,
+                user_data: event.user_data as usize,
+            }),
+            None => EventRef::Unknown(data),
+        },
+        (ffi::DRM_EVENT_FLIP_COMPLETE, Some(event)) => {
+            // Only `Device::page_flip()` passes the crtc along, for kernels
+            // that don't report it
+            let crtc = match event.crtc_id {
+                0 if event.user_data & PAGE_FLIP_CRTC != 0 => event.user_data as u32,
+                crtc => crtc,
+            };
+            match from_u32(crtc) {
+                Some(crtc) => EventRef::PageFlip(PageFlipEvent {
+                    frame: event.sequence,
+                    duration: time(&event),
+                    crtc,
+                    user_data: event.user_data,
+                }),
+                None => EventRef::Unknown(data),
+            }
+        }
+        (ffi::DRM_EVENT_CRTC_SEQUENCE, _)
+            if length >= mem::size_of::<ffi::drm_event_crtc_sequence>() =>
+        {
+            let event = unsafe {
+                std::ptr::read_unaligned(data.as_ptr() as *const ffi::drm_event_crtc_sequence)
+            };
+            EventRef::CrtcSequence(CrtcSequenceEvent {
+                sequence: event.sequence,
+                time: Duration::from_nanos(event.time_ns.max(0) as u64),
+                user_data: event.user_data,
+            })
+        }
+        _ => EventRef::Unknown(data),
+    };
+
+    Some((event, length))
+}
+
+/// Reads the events of a device into a reusable buffer.
+///
+/// Unlike [`Device::receive_events()`], a single call drains all pending
+/// events, even if they do not fit into the buffer at once, and unknown events
+/// are passed on without copying them.
+///
+/// ```no_run
+/// use drm::control::{EventReader, EventRef};
+/// use drm::device::OwnedDevice;
+///
+/// let card = OwnedDevice::open("/dev/dri/card0").unwrap();
+/// let mut reader = EventReader::new();
+/// reader
+///     .read(&card, |event| match event {
+///         EventRef::PageFlip(flip) => println!("Page flip on {:?}", flip.crtc),
+///         EventRef::Unknown(data) => println!("Unknown event of {} bytes", data.len()),
+///         _ => {}
+///     })
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct EventReader {
+    buffer: Box<[u8]>,
+}
+
+impl EventReader {
+    /// Creates a reader with a buffer of 1024 bytes.
+    pub fn new() -> Self {
+        Self::with_capacity(1024)
+    }
+
+    /// Creates a reader with a buffer of the given size.
+    ///
+    /// The kernel never splits an event across reads, so the buffer must be
+    /// large enough for the largest event the device emits. It is at least
+    /// large enough for vblank and page flip events.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(mem::size_of::<ffi::drm_event_vblank>());
+        EventReader {
+            buffer: vec![0; capacity].into_boxed_slice(),
+        }
+    }
+
+    /// Returns the size of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Reads all pending events of a device, passing each of them to
+    /// `callback`, and returns how many there were.
+    ///
+    /// Like [`Device::receive_events()`], this blocks until an event arrives
+    /// if there is none pending and the file descriptor is in blocking mode.
+    pub fn read<D, F>(&mut self, device: &D, mut callback: F) -> io::Result<usize>
+    where
+        D: Device + ?Sized,
+        F: FnMut(EventRef<'_>),
+    {
+        let fd = device.as_fd();
+        let mut count = 0;
+        let mut first = true;
+
+        loop {
+            let amount = match rustix::io::read(fd, &mut self.buffer) {
+                Ok(amount) => amount,
+                Err(Errno::INTR) => continue,
+                Err(Errno::AGAIN) if !first => break,
+                Err(err) => return Err(err.into()),
+            };
+            first = false;
+
+            let mut data = &self.buffer[..amount];
+            while let Some((event, length)) = parse_event(data) {
+                callback(event);
+                count += 1;
+                data = &data[length..];
+            }
+
+            if amount == 0 || !poll_readable(fd)? {
+                break;
             }
-        } else {
-            None
         }
+
+        Ok(count)
+    }
+}
+
+impl Default for EventReader {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
+/// Checks whether a file descriptor can be read from without blocking.
+fn poll_readable(fd: BorrowedFd<'_>) -> io::Result<bool> {
+    use rustix::event::{poll, PollFd, PollFlags};
+
+    let mut fds = [PollFd::new(&fd, PollFlags::IN)];
+    loop {
+        match poll(&mut fds, 0) {
+            Ok(ready) => return Ok(ready > 0 && fds[0].revents().contains(PollFlags::IN)),
+            Err(Errno::INTR) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Decodes the format blob of a plane property, if the plane has it.
+fn plane_format_modifiers<D: Device + ?Sized>(
+    device: &D,
+    handle: plane::Handle,
+    name: &str,
+) -> io::Result<Option<plane::FormatModifiers>> {
+    for (&prop, &value) in device.get_properties(handle)?.iter() {
+        if device.get_property(prop)?.name().to_bytes() != name.as_bytes() {
+            continue;
+        }
+        if value == 0 {
+            return Ok(None);
+        }
+        let blob = device.get_property_blob(value)?;
+        return plane::FormatModifiers::from_blob(&blob).map(Some);
+    }
+    Ok(None)
+}
+
 /// The set of [`ResourceHandles`] that a
 /// [`Device`] exposes. Excluding Plane resources.
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ResourceHandles {
     /// Set of [`framebuffer::Handle`]
     pub fbs: Vec<framebuffer::Handle>,
@@ -1235,23 +1652,187 @@ impl ResourceHandles {
 
     /// Apply a filter the all crtcs of these resources, resulting in a list of crtcs allowed.
     pub fn filter_crtcs(&self, filter: CrtcListFilter) -> Vec<crtc::Handle> {
+        filter
+            .indices()
+            .filter_map(|index| self.crtcs.get(index as usize).copied())
+            .collect()
+    }
+
+    /// Returns the index of a CRTC, also known as its pipe.
+    ///
+    /// This is the bit of the CRTC in a [`CrtcListFilter`] and the `high_crtc`
+    /// argument of [`Device::wait_vblank`](crate::Device::wait_vblank).
+    pub fn crtc_index(&self, crtc: crtc::Handle) -> Option<u32> {
         self.crtcs
             .iter()
-            .enumerate()
-            .filter(|&(n, _)| (1 << n) & filter.0 != 0)
-            .map(|(_, &e)| e)
+            .position(|&c| c == crtc)
+            .map(|index| index as u32)
+    }
+
+    /// Returns the index of an encoder, which is its bit in an
+    /// [`EncoderListFilter`].
+    pub fn encoder_index(&self, encoder: encoder::Handle) -> Option<u32> {
+        self.encoders
+            .iter()
+            .position(|&e| e == encoder)
+            .map(|index| index as u32)
+    }
+
+    /// Apply a filter the all encoders of these resources, resulting in a list of encoders allowed.
+    pub fn filter_encoders(&self, filter: EncoderListFilter) -> Vec<encoder::Handle> {
+        filter
+            .indices()
+            .filter_map(|index| self.encoders.get(index as usize).copied())
             .collect()
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
 /// A filter that can be used with a [`ResourceHandles`] to determine the set of
 /// Crtcs that can attach to a specific encoder.
+///
+/// Each bit represents the CRTC with that index in
+/// [`ResourceHandles::crtcs`].
 pub struct CrtcListFilter(u32);
 
+impl CrtcListFilter {
+    /// Creates a filter from its raw bitmask.
+    pub fn from_bits(bits: u32) -> Self {
+        CrtcListFilter(bits)
+    }
+
+    /// Returns the raw bitmask of the filter.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns whether the filter does not allow any CRTC.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the indices of the CRTCs allowed by the filter, in ascending
+    /// order.
+    pub fn indices(self) -> impl Iterator<Item = u32> {
+        (0..u32::BITS).filter(move |&index| self.contains_index(index))
+    }
+
+    /// Returns whether the filter allows the CRTC with the given index.
+    pub fn contains_index(self, index: u32) -> bool {
+        index < u32::BITS && self.0 & (1 << index) != 0
+    }
+
+    /// Returns whether the filter allows a CRTC of the given resources.
+    pub fn contains(self, resources: &ResourceHandles, crtc: crtc::Handle) -> bool {
+        resources
+            .crtc_index(crtc)
+            .map_or(false, |index| self.contains_index(index))
+    }
+
+    /// Returns the CRTCs allowed by both filters.
+    pub fn intersection(self, other: Self) -> Self {
+        CrtcListFilter(self.0 & other.0)
+    }
+
+    /// Returns the CRTCs allowed by either filter.
+    pub fn union(self, other: Self) -> Self {
+        CrtcListFilter(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitAnd for CrtcListFilter {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        self.intersection(other)
+    }
+}
+
+impl std::ops::BitOr for CrtcListFilter {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+/// A filter that can be used with a [`ResourceHandles`] to determine the set of
+/// encoders that can clone a specific encoder.
+///
+/// Each bit represents the encoder with that index in
+/// [`ResourceHandles::encoders`].
+pub struct EncoderListFilter(u32);
+
+impl EncoderListFilter {
+    /// Creates a filter from its raw bitmask.
+    pub fn from_bits(bits: u32) -> Self {
+        EncoderListFilter(bits)
+    }
+
+    /// Returns the raw bitmask of the filter.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns whether the filter does not allow any encoder.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the indices of the encoders allowed by the filter, in
+    /// ascending order.
+    pub fn indices(self) -> impl Iterator<Item = u32> {
+        (0..u32::BITS).filter(move |&index| self.contains_index(index))
+    }
+
+    /// Returns whether the filter allows the encoder with the given index.
+    pub fn contains_index(self, index: u32) -> bool {
+        index < u32::BITS && self.0 & (1 << index) != 0
+    }
+
+    /// Returns whether the filter allows an encoder of the given resources.
+    pub fn contains(self, resources: &ResourceHandles, encoder: encoder::Handle) -> bool {
+        resources
+            .encoder_index(encoder)
+            .map_or(false, |index| self.contains_index(index))
+    }
+
+    /// Returns the encoders allowed by both filters.
+    pub fn intersection(self, other: Self) -> Self {
+        EncoderListFilter(self.0 & other.0)
+    }
+
+    /// Returns the encoders allowed by either filter.
+    pub fn union(self, other: Self) -> Self {
+        EncoderListFilter(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitAnd for EncoderListFilter {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        self.intersection(other)
+    }
+}
+
+impl std::ops::BitOr for EncoderListFilter {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
 /// Resolution and timing information for a display mode.
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq, bytemuck::TransparentWrapper)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "SerdeMode", into = "SerdeMode")
+)]
 pub struct Mode {
     // We're using the FFI struct because the DRM API expects it when giving it
     // to a CRTC or creating a blob from it. Rather than rearranging the fields
@@ -1323,6 +1904,78 @@ impl From<Mode> for ffi::drm_mode_modeinfo {
     }
 }
 
+/// Serialized form of [`Mode`], with the fields of the raw mode.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "Mode")]
+struct SerdeMode {
+    name: String,
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    #[serde(rename = "type")]
+    type_: u32,
+}
+
+#[cfg(feature = "serde")]
+impl From<Mode> for SerdeMode {
+    fn from(mode: Mode) -> Self {
+        let mode = mode.mode;
+        SerdeMode {
+            name: c_name_to_string(&mode.name),
+            clock: mode.clock,
+            hdisplay: mode.hdisplay,
+            hsync_start: mode.hsync_start,
+            hsync_end: mode.hsync_end,
+            htotal: mode.htotal,
+            hskew: mode.hskew,
+            vdisplay: mode.vdisplay,
+            vsync_start: mode.vsync_start,
+            vsync_end: mode.vsync_end,
+            vtotal: mode.vtotal,
+            vscan: mode.vscan,
+            vrefresh: mode.vrefresh,
+            flags: mode.flags,
+            type_: mode.type_,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SerdeMode> for Mode {
+    fn from(mode: SerdeMode) -> Self {
+        Mode {
+            mode: ffi::drm_mode_modeinfo {
+                name: string_to_c_name(&mode.name),
+                clock: mode.clock,
+                hdisplay: mode.hdisplay,
+                hsync_start: mode.hsync_start,
+                hsync_end: mode.hsync_end,
+                htotal: mode.htotal,
+                hskew: mode.hskew,
+                vdisplay: mode.vdisplay,
+                vsync_start: mode.vsync_start,
+                vsync_end: mode.vsync_end,
+                vtotal: mode.vtotal,
+                vscan: mode.vscan,
+                vrefresh: mode.vrefresh,
+                flags: mode.flags,
+                type_: mode.type_,
+            },
+        }
+    }
+}
+
 impl fmt::Debug for Mode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Mode")
@@ -1422,6 +2075,7 @@ bitflags::bitflags! {
 /// Type of a plane
 #[repr(u32)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PlaneType {
     /// Overlay plane
     Overlay = ffi::DRM_PLANE_TYPE_OVERLAY,
@@ -1575,6 +2229,7 @@ bitflags::bitflags! {
 bitflags::bitflags! {
     /// Planar framebuffer flags
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct FbCmd2Flags : u32 {
         /// For interlaced framebuffers
         const INTERLACED = ffi::DRM_MODE_FB_INTERLACED;
@@ -1582,3 +2237,141 @@ bitflags::bitflags! {
         const MODIFIERS = ffi::DRM_MODE_FB_MODIFIERS;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes<T>(event: &T) -> Vec<u8> {
+        unsafe { std::slice::from_raw_parts(event as *const T as *const u8, mem::size_of::<T>()) }
+            .to_vec()
+    }
+
+    fn vblank(type_: u32, length: u32, crtc_id: u32) -> Vec<u8> {
+        bytes(&ffi::drm_event_vblank {
+            base: ffi::drm_event { type_, length },
+            user_data: 7,
+            tv_sec: 1,
+            tv_usec: 500,
+            sequence: 3,
+            crtc_id,
+        })
+    }
+
+    const VBLANK_SIZE: u32 = mem::size_of::<ffi::drm_event_vblank>() as u32;
+
+    #[test]
+    fn parse_complete_events() {
+        let mut buf = vblank(ffi::DRM_EVENT_VBLANK, VBLANK_SIZE, 5);
+        buf.extend(vblank(ffi::DRM_EVENT_FLIP_COMPLETE, VBLANK_SIZE, 6));
+
+        let (event, length) = parse_event(&buf).unwrap();
+        assert_eq!(length, VBLANK_SIZE as usize);
+        match event {
+            EventRef::Vblank(event) => {
+                assert_eq!(u32::from(event.crtc), 5);
+                assert_eq!(event.frame, 3);
+                assert_eq!(event.time, Duration::new(1, 500_000));
+                assert_eq!(event.user_data, 7);
+            }
+            _ => panic!("expected a vblank event"),
+        }
+
+        let (event, _) = parse_event(&buf[length..]).unwrap();
+        assert!(matches!(event, EventRef::PageFlip(flip) if u32::from(flip.crtc) == 6));
+    }
+
+    #[test]
+    fn parse_rejects_truncated_events() {
+        let buf = vblank(ffi::DRM_EVENT_VBLANK, VBLANK_SIZE, 5);
+        // Incomplete header
+        assert!(parse_event(&buf[..4]).is_none());
+        // Incomplete body
+        assert!(parse_event(&buf[..VBLANK_SIZE as usize - 1]).is_none());
+        // Lengths smaller than the header would never advance
+        assert!(parse_event(&vblank(ffi::DRM_EVENT_VBLANK, 4, 5)).is_none());
+        assert!(parse_event(&vblank(ffi::DRM_EVENT_VBLANK, 0, 5)).is_none());
+    }
+
+    #[test]
+    fn parse_short_known_events_as_unknown() {
+        let header = mem::size_of::<ffi::drm_event>() as u32;
+        let mut buf = vblank(ffi::DRM_EVENT_FLIP_COMPLETE, header, 5);
+        buf.truncate(header as usize);
+
+        let (event, length) = parse_event(&buf).unwrap();
+        assert_eq!(length, header as usize);
+        assert!(matches!(event, EventRef::Unknown(data) if data.len() == header as usize));
+
+        let buf = vblank(ffi::DRM_EVENT_CRTC_SEQUENCE, VBLANK_SIZE - 8, 5);
+        assert!(matches!(
+            parse_event(&buf),
+            Some((EventRef::Unknown(_), 24))
+        ));
+    }
+
+    #[test]
+    fn page_flip_crtc_fallback() {
+        let flip = |user_data, crtc_id| {
+            bytes(&ffi::drm_event_vblank {
+                base: ffi::drm_event {
+                    type_: ffi::DRM_EVENT_FLIP_COMPLETE,
+                    length: VBLANK_SIZE,
+                },
+                user_data,
+                tv_sec: 0,
+                tv_usec: 0,
+                sequence: 0,
+                crtc_id,
+            })
+        };
+        let crtc = |buf: &[u8]| match parse_event(buf) {
+            Some((EventRef::PageFlip(flip), _)) => Some(u32::from(flip.crtc)),
+            _ => None,
+        };
+
+        // `Device::page_flip()` on kernels that don't report the crtc
+        assert_eq!(crtc(&flip(PAGE_FLIP_CRTC | 5, 0)), Some(5));
+        assert_eq!(crtc(&flip(PAGE_FLIP_CRTC | 5, 6)), Some(6));
+        // Other user data is never taken for a crtc
+        assert_eq!(crtc(&flip(5, 0)), None);
+        assert_eq!(crtc(&flip(1 << 32 | 5, 0)), None);
+        assert_eq!(crtc(&flip(5, 6)), Some(6));
+    }
+
+    #[test]
+    fn events_iterator_stops_at_truncated_events() {
+        let mut event_buf = [0; 1024];
+        let event = vblank(ffi::DRM_EVENT_VBLANK, VBLANK_SIZE, 5);
+        event_buf[..event.len()].copy_from_slice(&event);
+        event_buf[event.len()..2 * event.len()].copy_from_slice(&event);
+
+        let events = Events::with_event_buf(event_buf, 2 * event.len() - 1);
+        assert_eq!(events.count(), 1);
+    }
+
+    #[test]
+    fn encoder_list_filter_set_operations() {
+        let encoders = [10, 11, 12].map(|id| from_u32(id).unwrap());
+        let resources = ResourceHandles {
+            fbs: Vec::new(),
+            crtcs: Vec::new(),
+            connectors: Vec::new(),
+            encoders: encoders.to_vec(),
+            width: (0, 0),
+            height: (0, 0),
+        };
+
+        let a = EncoderListFilter::from_bits(0b011);
+        let b = EncoderListFilter::from_bits(0b110);
+        assert_eq!(a & b, EncoderListFilter::from_bits(0b010));
+        assert_eq!(a | b, EncoderListFilter::from_bits(0b111));
+        assert!((a & EncoderListFilter::default()).is_empty());
+        assert!(!a.is_empty());
+
+        assert!(a.contains(&resources, encoders[1]));
+        assert!(!a.contains(&resources, encoders[2]));
+        assert!(!a.contains(&resources, from_u32(13).unwrap()));
+        assert_eq!(resources.filter_encoders(a | b), encoders);
+    }
+}
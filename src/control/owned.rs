@@ -0,0 +1,366 @@
+//! # Owned
+//!
+//! Kernel objects that are released when dropped.
+//!
+//! Each type holds the device it was created on, which can be owned or
+//! borrowed, e.g. as `&Card` or `Arc<Card>`. Errors while releasing an object
+//! on drop are ignored. Use `leak` or `into_raw` to take over the
+//! responsibility of releasing it manually.
+//!
+//! Constructors which take over an existing object, like `from_raw`, are safe.
+//! Objects are identified by handles, not memory, so releasing an object that
+//! is also owned elsewhere cannot cause undefined behaviour. It does make the
+//! other owner's handle stale, or release a different object that reused the
+//! handle, so every object must have at most one owner.
+//!
+//! ```no_run
+//! use drm::control::owned::{OwnedDumbBuffer, OwnedFramebuffer};
+//! use drm::device::OwnedDevice;
+//! use drm_fourcc::DrmFourcc;
+//!
+//! let card = OwnedDevice::open("/dev/dri/card0").unwrap();
+//! let buffer = OwnedDumbBuffer::create(&card, (1920, 1080), DrmFourcc::Xrgb8888, 32).unwrap();
+//! let fb = OwnedFramebuffer::add(&card, &buffer, 24, 32).unwrap();
+//! // Both the framebuffer and the buffer are released at the end of the scope
+//! ```
+
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::unix::io::BorrowedFd;
+use std::ptr;
+
+use crate::buffer;
+use crate::control::{
+    dumbbuffer::{DumbBuffer, DumbMapping},
+    framebuffer, property, syncobj, Device, FbCmd2Flags,
+};
+
+/// Implements the accessors and escape hatches shared by all owned types.
+macro_rules! owned {
+    ($ty:ident, $field:ident: $raw:ty, $what:literal) => {
+        impl<D: Device> $ty<D> {
+            #[doc = concat!("Takes ownership of an existing ", $what, ".")]
+            ///
+            /// It must not be owned elsewhere, see the [module
+            /// documentation](self).
+            pub fn from_raw(device: D, $field: $raw) -> Self {
+                $ty { device, $field }
+            }
+
+            /// Returns the device this object belongs to.
+            pub fn device(&self) -> &D {
+                &self.device
+            }
+
+            /// Releases ownership without releasing the object, returning the
+            /// device and the raw object.
+            pub fn into_raw(self) -> (D, $raw) {
+                let this = ManuallyDrop::new(self);
+                // SAFETY: `this` is never used or dropped again
+                let device = unsafe { ptr::read(&this.device) };
+                let $field = unsafe { ptr::read(&this.$field) };
+                (device, $field)
+            }
+
+            /// Releases ownership without releasing the object, which is
+            /// leaked unless released manually.
+            pub fn leak(self) -> $raw {
+                self.into_raw().1
+            }
+        }
+    };
+}
+
+/// A framebuffer that is destroyed when dropped.
+#[derive(Debug)]
+pub struct OwnedFramebuffer<D: Device> {
+    device: D,
+    handle: framebuffer::Handle,
+}
+
+owned!(OwnedFramebuffer, handle: framebuffer::Handle, "framebuffer");
+
+impl<D: Device> OwnedFramebuffer<D> {
+    /// Adds a framebuffer, see [`Device::add_framebuffer()`].
+    pub fn add<B>(device: D, buffer: &B, depth: u32, bpp: u32) -> io::Result<Self>
+    where
+        B: buffer::Buffer + ?Sized,
+    {
+        let handle = device.add_framebuffer(buffer, depth, bpp)?;
+        Ok(Self::from_raw(device, handle))
+    }
+
+    /// Adds a planar framebuffer, see [`Device::add_planar_framebuffer()`].
+    pub fn add_planar<B>(device: D, buffer: &B, flags: FbCmd2Flags) -> io::Result<Self>
+    where
+        B: buffer::PlanarBuffer + ?Sized,
+    {
+        let handle = device.add_planar_framebuffer(buffer, flags)?;
+        Ok(Self::from_raw(device, handle))
+    }
+
+    /// Returns the handle of the framebuffer.
+    pub fn handle(&self) -> framebuffer::Handle {
+        self.handle
+    }
+
+    /// Closes the framebuffer instead of destroying it, leaving it on screen,
+    /// see [`Device::close_framebuffer()`].
+    pub fn close(self) -> io::Result<()> {
+        let (device, handle) = self.into_raw();
+        device.close_framebuffer(handle)
+    }
+}
+
+impl<D: Device> Drop for OwnedFramebuffer<D> {
+    fn drop(&mut self) {
+        let _ = self.device.destroy_framebuffer(self.handle);
+    }
+}
+
+/// A property blob that is destroyed when dropped.
+#[derive(Debug)]
+pub struct OwnedBlob<D: Device> {
+    device: D,
+    id: u64,
+}
+
+owned!(OwnedBlob, id: u64, "property blob");
+
+impl<D: Device> OwnedBlob<D> {
+    /// Creates a property blob, see [`Device::create_property_blob()`].
+    pub fn create<T>(device: D, data: &T) -> io::Result<Self> {
+        match device.create_property_blob(data)? {
+            property::Value::Blob(id) => Ok(Self::from_raw(device, id)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "created property blob is not a blob value",
+            )),
+        }
+    }
+
+    /// Returns the id of the blob.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the blob as property value.
+    pub fn value(&self) -> property::Value<'static> {
+        property::Value::Blob(self.id)
+    }
+}
+
+impl<D: Device> Drop for OwnedBlob<D> {
+    fn drop(&mut self) {
+        let _ = self.device.destroy_property_blob(self.id);
+    }
+}
+
+/// A GEM handle that is closed when dropped.
+#[derive(Debug)]
+pub struct OwnedGemHandle<D: Device> {
+    device: D,
+    handle: buffer::Handle,
+}
+
+owned!(OwnedGemHandle, handle: buffer::Handle, "GEM handle");
+
+impl<D: Device> OwnedGemHandle<D> {
+    /// Opens a GEM buffer by name, returning it with its size, see
+    /// [`Device::open_buffer()`].
+    pub fn open(device: D, name: buffer::Name) -> io::Result<(Self, u64)> {
+        let (handle, size) = device.open_buffer(name)?;
+        Ok((Self::from_raw(device, handle), size))
+    }
+
+    /// Imports a PRIME file descriptor, see [`Device::prime_fd_to_buffer()`].
+    ///
+    /// Importing a buffer that was already imported on the same device
+    /// returns the same GEM handle, and closing it invalidates every import.
+    /// Like for [`OwnedGemHandle::from_raw`], no other owner of the handle may
+    /// exist while the returned one is alive, otherwise dropping either of
+    /// them closes the handle the other one still uses.
+    /// [`PrimeImports`](crate::control::prime::PrimeImports) shares imports of
+    /// the same buffer instead.
+    pub fn from_prime_fd(device: D, fd: BorrowedFd<'_>) -> io::Result<Self> {
+        let handle = device.prime_fd_to_buffer(fd)?;
+        Ok(Self::from_raw(device, handle))
+    }
+
+    /// Returns the GEM handle.
+    pub fn handle(&self) -> buffer::Handle {
+        self.handle
+    }
+}
+
+impl<D: Device> Drop for OwnedGemHandle<D> {
+    fn drop(&mut self) {
+        let _ = self.device.close_buffer(self.handle);
+    }
+}
+
+/// A syncobj that is destroyed when dropped.
+#[derive(Debug)]
+pub struct OwnedSyncobj<D: Device> {
+    device: D,
+    handle: syncobj::Handle,
+}
+
+owned!(OwnedSyncobj, handle: syncobj::Handle, "syncobj");
+
+impl<D: Device> OwnedSyncobj<D> {
+    /// Creates a syncobj, see [`Device::create_syncobj()`].
+    pub fn create(device: D, signalled: bool) -> io::Result<Self> {
+        let handle = device.create_syncobj(signalled)?;
+        Ok(Self::from_raw(device, handle))
+    }
+
+    /// Returns the handle of the syncobj.
+    pub fn handle(&self) -> syncobj::Handle {
+        self.handle
+    }
+}
+
+impl<D: Device> Drop for OwnedSyncobj<D> {
+    fn drop(&mut self) {
+        let _ = self.device.destroy_syncobj(self.handle);
+    }
+}
+
+/// A dumb buffer that is destroyed when dropped.
+#[derive(Debug)]
+pub struct OwnedDumbBuffer<D: Device> {
+    device: D,
+    buffer: DumbBuffer,
+}
+
+owned!(OwnedDumbBuffer, buffer: DumbBuffer, "dumb buffer");
+
+impl<D: Device> OwnedDumbBuffer<D> {
+    /// Creates a dumb buffer, see [`Device::create_dumb_buffer()`].
+    pub fn create(
+        device: D,
+        size: (u32, u32),
+        format: buffer::DrmFourcc,
+        bpp: u32,
+    ) -> io::Result<Self> {
+        let buffer = device.create_dumb_buffer(size, format, bpp)?;
+        Ok(Self::from_raw(device, buffer))
+    }
+
+    /// Returns the dumb buffer.
+    pub fn buffer(&self) -> &DumbBuffer {
+        &self.buffer
+    }
+
+    /// Maps the buffer for access by the CPU, see [`Device::map_dumb_buffer()`].
+    pub fn map(&mut self) -> io::Result<DumbMapping<'_>> {
+        self.device.map_dumb_buffer(&mut self.buffer)
+    }
+}
+
+impl<D: Device> buffer::Buffer for OwnedDumbBuffer<D> {
+    fn size(&self) -> (u32, u32) {
+        self.buffer.size()
+    }
+    fn format(&self) -> buffer::DrmFourcc {
+        self.buffer.format()
+    }
+    fn pitch(&self) -> u32 {
+        self.buffer.pitch()
+    }
+    fn handle(&self) -> buffer::Handle {
+        self.buffer.handle()
+    }
+}
+
+impl<D: Device> Drop for OwnedDumbBuffer<D> {
+    fn drop(&mut self) {
+        let _ = self.device.destroy_dumb_buffer(self.buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsFd;
+
+    /// A device whose blobs come back as another type of value.
+    #[derive(Debug)]
+    struct NotABlob(std::fs::File);
+
+    impl AsFd for NotABlob {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            self.0.as_fd()
+        }
+    }
+
+    impl crate::Device for NotABlob {}
+    impl Device for NotABlob {
+        fn create_property_blob<T>(&self, _: &T) -> io::Result<property::Value<'static>> {
+            Ok(property::Value::UnsignedRange(1))
+        }
+    }
+
+    #[test]
+    fn blob_of_another_type() {
+        let device = NotABlob(std::fs::File::open("/dev/null").unwrap());
+        let err = OwnedBlob::create(device, &0u32).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "emulator")]
+    fn emulator() -> crate::device::OwnedDevice {
+        use crate::control::{encoder, PlaneType};
+        use crate::emulator::Emulator;
+        use drm_fourcc::DrmFourcc;
+
+        let mut builder = Emulator::builder();
+        let crtc = builder.crtc();
+        builder.encoder(encoder::Kind::TMDS, &[crtc]);
+        builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+        builder.build().unwrap().0
+    }
+
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn framebuffers_are_destroyed_on_drop() {
+        let card = emulator();
+        let buffer =
+            OwnedDumbBuffer::create(&card, (64, 64), buffer::DrmFourcc::Xrgb8888, 32).unwrap();
+
+        let fb = OwnedFramebuffer::add(&card, &buffer, 24, 32).unwrap();
+        let handle = fb.handle();
+        assert!(card.get_framebuffer(handle).is_ok());
+        drop(fb);
+        assert!(card.get_framebuffer(handle).is_err());
+
+        let handle = OwnedFramebuffer::add(&card, &buffer, 24, 32)
+            .unwrap()
+            .leak();
+        assert!(card.get_framebuffer(handle).is_ok());
+        let (_, handle) = OwnedFramebuffer::from_raw(&card, handle).into_raw();
+        assert!(card.get_framebuffer(handle).is_ok());
+        drop(OwnedFramebuffer::from_raw(&card, handle));
+        assert!(card.get_framebuffer(handle).is_err());
+    }
+
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn blobs_are_destroyed_on_drop() {
+        let card = emulator();
+
+        let blob = OwnedBlob::create(&card, &[1u32, 2, 3]).unwrap();
+        let id = blob.id();
+        assert!(card.get_property_blob(id).is_ok());
+        drop(blob);
+        assert!(card.get_property_blob(id).is_err());
+
+        let id = OwnedBlob::create(&card, &[1u32, 2, 3]).unwrap().leak();
+        assert!(card.get_property_blob(id).is_ok());
+        let (_, id) = OwnedBlob::from_raw(&card, id).into_raw();
+        assert!(card.get_property_blob(id).is_ok());
+        drop(OwnedBlob::from_raw(&card, id));
+        assert!(card.get_property_blob(id).is_err());
+    }
+}
@@ -7,20 +7,31 @@
 //! types of planes available for use:
 //!
 //! * Primary - A CRTC's built-in plane. When attaching a framebuffer to a CRTC,
-//! it is actually being attached to this kind of plane.
+//!   it is actually being attached to this kind of plane.
 //!
 //! * Overlay - Can be overlaid on top of a primary plane, utilizing extremely
-//! fast hardware compositing.
+//!   fast hardware compositing.
 //!
 //! * Cursor - Similar to an overlay plane, these are typically used to display
-//! cursor type objects.
+//!   cursor type objects.
 
-use crate::control;
+use std::io;
+use std::mem;
+
+use crate::control::{self, property, PlaneType};
+use crate::DriverCapability;
 use drm_ffi as ffi;
+use drm_fourcc::{DrmFourcc, DrmModifier};
+use rustix::io::Errno;
 
 /// A handle to a plane
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Handle(control::RawResourceHandle);
 
 // Safety: Handle is repr(transparent) over NonZeroU32
@@ -57,6 +68,7 @@ impl std::fmt::Debug for Handle {
 
 /// Information about a plane
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Info {
     pub(crate) handle: Handle,
     pub(crate) crtc: Option<control::crtc::Handle>,
@@ -96,7 +108,506 @@ impl Info {
     }
 
     /// Returns the formats this plane supports.
+    ///
+    /// See [`FormatModifiers`] for the modifiers supported with each format.
     pub fn formats(&self) -> &[u32] {
         &self.formats
     }
 }
+
+/// The only version of the `IN_FORMATS` blob layout
+const FORMAT_BLOB_VERSION: u32 = 1;
+
+/// The formats and modifiers supported by a plane, as reported by its
+/// `IN_FORMATS` or `IN_FORMATS_ASYNC` property.
+///
+/// Formats are kept in the order of the blob and as raw fourccs, so formats
+/// unknown to [`DrmFourcc`] are not lost.
+///
+/// Use [`control::Device::get_plane_format_modifiers`] to query them.
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FormatModifiers {
+    formats: Vec<(u32, Vec<DrmModifier>)>,
+}
+
+impl FormatModifiers {
+    /// Decodes the contents of an `IN_FORMATS` blob.
+    ///
+    /// Returns `EINVAL` if the blob is malformed or of an unknown version.
+    pub fn from_blob(blob: &[u8]) -> io::Result<Self> {
+        // Offsets come from the blob, so none of them may overflow
+        let u32_at = |offset: usize| -> io::Result<u32> {
+            let end = offset.checked_add(4).ok_or(Errno::INVAL)?;
+            let bytes = blob.get(offset..end).ok_or(Errno::INVAL)?;
+            Ok(u32::from_ne_bytes(bytes.try_into().unwrap()))
+        };
+        let u64_at = |offset: usize| -> io::Result<u64> {
+            let end = offset.checked_add(8).ok_or(Errno::INVAL)?;
+            let bytes = blob.get(offset..end).ok_or(Errno::INVAL)?;
+            Ok(u64::from_ne_bytes(bytes.try_into().unwrap()))
+        };
+        let element = |base: usize, index: usize, size: usize| -> io::Result<usize> {
+            let offset = index.checked_mul(size).and_then(|o| base.checked_add(o));
+            Ok(offset.ok_or(Errno::INVAL)?)
+        };
+
+        let header_size = mem::size_of::<ffi::drm_format_modifier_blob>();
+        if blob.len() < header_size || u32_at(0)? != FORMAT_BLOB_VERSION {
+            return Err(Errno::INVAL.into());
+        }
+        let count_formats = u32_at(8)? as usize;
+        let formats_offset = u32_at(12)? as usize;
+        let count_modifiers = u32_at(16)? as usize;
+        let modifiers_offset = u32_at(20)? as usize;
+
+        let mut formats = (0..count_formats)
+            .map(|i| Ok((u32_at(element(formats_offset, i, 4)?)?, Vec::new())))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let modifier_size = mem::size_of::<ffi::drm_format_modifier>();
+        for i in 0..count_modifiers {
+            // Each modifier applies to up to 64 formats, starting at an offset
+            let entry = element(modifiers_offset, i, modifier_size)?;
+            let mask = u64_at(entry)?;
+            let offset = u32_at(element(entry, 1, 8)?)? as usize;
+            let modifier = DrmModifier::from(u64_at(element(entry, 2, 8)?)?);
+
+            for bit in (0..64).filter(|bit| mask & (1 << bit) != 0) {
+                let index = offset.checked_add(bit).ok_or(Errno::INVAL)?;
+                let (_, modifiers) = formats.get_mut(index).ok_or(Errno::INVAL)?;
+                modifiers.push(modifier);
+            }
+        }
+
+        Ok(FormatModifiers { formats })
+    }
+
+    /// Returns the modifiers supported with a format, or `None` if the format
+    /// is not supported.
+    pub fn get(&self, format: DrmFourcc) -> Option<&[DrmModifier]> {
+        self.get_raw(format as u32)
+    }
+
+    /// Returns the modifiers supported with a raw fourcc, which may be
+    /// unknown to [`DrmFourcc`].
+    pub fn get_raw(&self, fourcc: u32) -> Option<&[DrmModifier]> {
+        self.formats
+            .iter()
+            .find(|(format, _)| *format == fourcc)
+            .map(|(_, modifiers)| &modifiers[..])
+    }
+
+    /// Returns whether a combination of format and modifier is supported.
+    pub fn supports(&self, format: DrmFourcc, modifier: DrmModifier) -> bool {
+        self.get(format)
+            .map_or(false, |modifiers| modifiers.contains(&modifier))
+    }
+
+    /// Returns the raw fourccs and their modifiers, in the order of the blob.
+    ///
+    /// Use [`DrmFourcc::try_from`] to convert the fourccs.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[DrmModifier])> {
+        self.formats
+            .iter()
+            .map(|(format, modifiers)| (*format, &modifiers[..]))
+    }
+
+    /// Returns the number of formats.
+    pub fn len(&self) -> usize {
+        self.formats.len()
+    }
+
+    /// Returns whether there are no formats.
+    pub fn is_empty(&self) -> bool {
+        self.formats.is_empty()
+    }
+}
+
+bitflags::bitflags! {
+    /// Rotations and reflections of a plane
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Rotation : u32 {
+        /// No rotation
+        const ROTATE_0 = ffi::DRM_MODE_ROTATE_0;
+        /// Rotate 90 degrees counter clockwise
+        const ROTATE_90 = ffi::DRM_MODE_ROTATE_90;
+        /// Rotate 180 degrees counter clockwise
+        const ROTATE_180 = ffi::DRM_MODE_ROTATE_180;
+        /// Rotate 270 degrees counter clockwise
+        const ROTATE_270 = ffi::DRM_MODE_ROTATE_270;
+        /// Mirror along the y axis
+        const REFLECT_X = ffi::DRM_MODE_REFLECT_X;
+        /// Mirror along the x axis
+        const REFLECT_Y = ffi::DRM_MODE_REFLECT_Y;
+    }
+}
+
+/// How the pixels of a plane are blended with the planes below, see the
+/// `pixel blend mode` property.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlendMode {
+    /// The alpha channel of the pixels is ignored
+    None,
+    /// The colors of the pixels are premultiplied with their alpha
+    PreMultiplied,
+    /// The colors of the pixels are not premultiplied with their alpha
+    Coverage,
+}
+
+/// The encoding used to convert YCbCr pixels to RGB, see the `COLOR_ENCODING`
+/// property.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorEncoding {
+    /// ITU-R BT.601
+    Bt601,
+    /// ITU-R BT.709
+    Bt709,
+    /// ITU-R BT.2020
+    Bt2020,
+}
+
+/// The range of YCbCr pixels, see the `COLOR_RANGE` property.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColorRange {
+    /// Limited range, e.g. 16 to 235 for 8 bits
+    Limited,
+    /// Full range
+    Full,
+}
+
+/// The filter used when scaling a plane, see the `SCALING_FILTER` property.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScalingFilter {
+    /// The default filter of the driver
+    Default,
+    /// Nearest neighbor
+    NearestNeighbor,
+}
+
+/// The range of the `zpos` property of a plane.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Zpos {
+    /// The lowest position
+    pub min: i64,
+    /// The highest position
+    pub max: i64,
+    /// Whether the position can be changed, or is fixed by the hardware
+    pub mutable: bool,
+}
+
+/// What a plane supports, gathered from its properties.
+///
+/// Properties unknown to the driver are reported as empty or `None`. Use
+/// [`control::Device::get_plane_capabilities`] to query them.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlaneCapabilities {
+    /// The handle of the plane
+    pub handle: Handle,
+    /// The type of the plane, overlay if the plane has no `type` property
+    pub kind: PlaneType,
+    /// The supported formats, see [`Info::formats`]
+    pub formats: Vec<u32>,
+    /// The supported modifiers of each format, from `IN_FORMATS`
+    pub format_modifiers: Option<FormatModifiers>,
+    /// The supported rotations and reflections, only
+    /// [`ROTATE_0`](Rotation::ROTATE_0) if the plane cannot be rotated
+    pub rotations: Rotation,
+    /// The range of the stacking position of the plane
+    pub zpos: Option<Zpos>,
+    /// The supported scaling filters.
+    ///
+    /// Planes without a `SCALING_FILTER` property may still be able to scale,
+    /// which can only be found out with a `TEST_ONLY` commit.
+    pub scaling_filters: Vec<ScalingFilter>,
+    /// Whether the plane has an `alpha` property for its opacity
+    pub alpha: bool,
+    /// The supported blend modes
+    pub blend_modes: Vec<BlendMode>,
+    /// The supported encodings of YCbCr formats
+    pub color_encodings: Vec<ColorEncoding>,
+    /// The supported ranges of YCbCr formats
+    pub color_ranges: Vec<ColorRange>,
+    /// The preferred sizes of the plane, from `SIZE_HINTS`.
+    ///
+    /// Cursor planes without hints report the cursor size of the driver
+    /// instead, see [`DriverCapability::CursorWidth`].
+    pub size_hints: Vec<(u32, u32)>,
+}
+
+impl PlaneCapabilities {
+    pub(crate) fn query<D>(device: &D, handle: Handle) -> io::Result<Self>
+    where
+        D: control::Device + ?Sized,
+    {
+        let mut caps = PlaneCapabilities {
+            handle,
+            kind: PlaneType::Overlay,
+            formats: device.get_plane(handle)?.formats,
+            format_modifiers: None,
+            rotations: Rotation::ROTATE_0,
+            zpos: None,
+            scaling_filters: Vec::new(),
+            alpha: false,
+            blend_modes: Vec::new(),
+            color_encodings: Vec::new(),
+            color_ranges: Vec::new(),
+            size_hints: Vec::new(),
+        };
+
+        for (&prop, &value) in device.get_properties(handle)?.iter() {
+            let info = device.get_property(prop)?;
+            let names = enum_names(&info.value_type());
+            match info.name().to_bytes() {
+                b"type" => {
+                    caps.kind = match value as u32 {
+                        ffi::DRM_PLANE_TYPE_PRIMARY => PlaneType::Primary,
+                        ffi::DRM_PLANE_TYPE_CURSOR => PlaneType::Cursor,
+                        _ => PlaneType::Overlay,
+                    }
+                }
+                b"IN_FORMATS" if value != 0 => {
+                    let blob = device.get_property_blob(value)?;
+                    caps.format_modifiers = Some(FormatModifiers::from_blob(&blob)?);
+                }
+                b"rotation" => {
+                    // The names of bitmask values are not part of the value type
+                    let (mut values, mut enums) = (Vec::new(), Vec::new());
+                    let fd = device.as_fd();
+                    ffi::mode::get_property(fd, prop.into(), Some(&mut values), Some(&mut enums))?;
+                    caps.rotations = enums
+                        .iter()
+                        .filter(|e| e.value < 32)
+                        .map(|e| Rotation::from_bits_truncate(1 << e.value))
+                        .collect();
+                }
+                b"zpos" => {
+                    let range = match info.value_type() {
+                        property::ValueType::UnsignedRange(min, max) => {
+                            Some((min as i64, max as i64))
+                        }
+                        property::ValueType::SignedRange(min, max) => Some((min, max)),
+                        property::ValueType::Boolean => Some((0, 1)),
+                        _ => None,
+                    };
+                    caps.zpos = range.map(|(min, max)| Zpos {
+                        min,
+                        max,
+                        mutable: info.mutable(),
+                    });
+                }
+                b"SCALING_FILTER" => {
+                    caps.scaling_filters = map_names(&names, |name| match name {
+                        b"Default" => Some(ScalingFilter::Default),
+                        b"Nearest Neighbor" => Some(ScalingFilter::NearestNeighbor),
+                        _ => None,
+                    })
+                }
+                b"alpha" => caps.alpha = true,
+                b"pixel blend mode" => {
+                    caps.blend_modes = map_names(&names, |name| match name {
+                        b"None" => Some(BlendMode::None),
+                        b"Pre-multiplied" => Some(BlendMode::PreMultiplied),
+                        b"Coverage" => Some(BlendMode::Coverage),
+                        _ => None,
+                    })
+                }
+                b"COLOR_ENCODING" => {
+                    caps.color_encodings = map_names(&names, |name| match name {
+                        b"ITU-R BT.601 YCbCr" => Some(ColorEncoding::Bt601),
+                        b"ITU-R BT.709 YCbCr" => Some(ColorEncoding::Bt709),
+                        b"ITU-R BT.2020 YCbCr" => Some(ColorEncoding::Bt2020),
+                        _ => None,
+                    })
+                }
+                b"COLOR_RANGE" => {
+                    caps.color_ranges = map_names(&names, |name| match name {
+                        b"YCbCr limited range" => Some(ColorRange::Limited),
+                        b"YCbCr full range" => Some(ColorRange::Full),
+                        _ => None,
+                    })
+                }
+                b"SIZE_HINTS" if value != 0 => {
+                    // An array of struct drm_plane_size_hint { __u16 width, height; }
+                    caps.size_hints = device
+                        .get_property_blob(value)?
+                        .chunks_exact(4)
+                        .map(|hint| {
+                            let width = u16::from_ne_bytes([hint[0], hint[1]]);
+                            let height = u16::from_ne_bytes([hint[2], hint[3]]);
+                            (width as u32, height as u32)
+                        })
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+
+        if caps.kind == PlaneType::Cursor && caps.size_hints.is_empty() {
+            let width = device.get_driver_capability(DriverCapability::CursorWidth);
+            let height = device.get_driver_capability(DriverCapability::CursorHeight);
+            if let (Ok(width), Ok(height)) = (width, height) {
+                caps.size_hints.push((width as u32, height as u32));
+            }
+        }
+
+        Ok(caps)
+    }
+}
+
+/// Returns the names of the values of an enum property.
+fn enum_names(value_type: &property::ValueType) -> Vec<Vec<u8>> {
+    match value_type {
+        property::ValueType::Enum(values) => values
+            .values()
+            .1
+            .iter()
+            .map(|value| value.name().to_bytes().to_vec())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Maps the known names of an enum property, ignoring unknown ones.
+fn map_names<T>(names: &[Vec<u8>], f: impl Fn(&[u8]) -> Option<T>) -> Vec<T> {
+    names.iter().filter_map(|name| f(name)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_SIZE: usize = mem::size_of::<ffi::drm_format_modifier_blob>();
+
+    /// Lays out a version 1 blob like the kernel does, with the formats after
+    /// the header and the modifiers after the formats.
+    fn blob(formats: &[u32], modifiers: &[(u64, u32, u64)]) -> Vec<u8> {
+        let formats_offset = HEADER_SIZE;
+        let modifiers_offset = (formats_offset + formats.len() * 4 + 7) & !7;
+
+        let mut blob = Vec::new();
+        for value in [
+            FORMAT_BLOB_VERSION,
+            0,
+            formats.len() as u32,
+            formats_offset as u32,
+            modifiers.len() as u32,
+            modifiers_offset as u32,
+        ] {
+            blob.extend_from_slice(&value.to_ne_bytes());
+        }
+        for format in formats {
+            blob.extend_from_slice(&format.to_ne_bytes());
+        }
+        blob.resize(modifiers_offset, 0);
+        for &(mask, offset, modifier) in modifiers {
+            blob.extend_from_slice(&mask.to_ne_bytes());
+            blob.extend_from_slice(&offset.to_ne_bytes());
+            blob.extend_from_slice(&0u32.to_ne_bytes());
+            blob.extend_from_slice(&modifier.to_ne_bytes());
+        }
+        blob
+    }
+
+    fn set_u32(blob: &mut [u8], offset: usize, value: u32) {
+        blob[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    fn errno(result: io::Result<FormatModifiers>) -> Errno {
+        Errno::from_io_error(&result.unwrap_err()).expect("not an OS error")
+    }
+
+    #[test]
+    fn valid_blob() {
+        let linear = u64::from(DrmModifier::Linear);
+        let tiled = u64::from(DrmModifier::I915_x_tiled);
+        let unknown = 0x3231_5258;
+        let formats = [
+            DrmFourcc::Xrgb8888 as u32,
+            unknown,
+            DrmFourcc::Argb8888 as u32,
+        ];
+        let blob = blob(&formats, &[(0b111, 0, linear), (0b101, 0, tiled)]);
+
+        let decoded = FormatModifiers::from_blob(&blob).unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(
+            decoded.get(DrmFourcc::Xrgb8888),
+            Some(&[DrmModifier::Linear, DrmModifier::I915_x_tiled][..])
+        );
+        assert_eq!(decoded.get_raw(unknown), Some(&[DrmModifier::Linear][..]));
+        assert!(decoded.supports(DrmFourcc::Argb8888, DrmModifier::I915_x_tiled));
+        assert_eq!(decoded.get(DrmFourcc::Rgb565), None);
+        let order: Vec<_> = decoded.iter().map(|(format, _)| format).collect();
+        assert_eq!(order, formats);
+    }
+
+    #[test]
+    fn bad_version() {
+        let mut blob = blob(&[DrmFourcc::Xrgb8888 as u32], &[]);
+        set_u32(&mut blob, 0, FORMAT_BLOB_VERSION + 1);
+        assert_eq!(errno(FormatModifiers::from_blob(&blob)), Errno::INVAL);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let blob = blob(&[], &[]);
+        assert!(FormatModifiers::from_blob(&blob).unwrap().is_empty());
+        for len in 0..HEADER_SIZE {
+            let result = FormatModifiers::from_blob(&blob[..len]);
+            assert_eq!(errno(result), Errno::INVAL);
+        }
+    }
+
+    #[test]
+    fn modifiers_past_the_formats() {
+        let formats = [DrmFourcc::Xrgb8888 as u32, DrmFourcc::Argb8888 as u32];
+        let decode = |modifiers| errno(FormatModifiers::from_blob(&blob(&formats, modifiers)));
+        // The mask reaches past the last format
+        assert_eq!(decode(&[(0b100, 0, 0)]), Errno::INVAL);
+        // The window starts past the last format
+        assert_eq!(decode(&[(0b1, 2, 0)]), Errno::INVAL);
+        // The window would overflow
+        assert_eq!(decode(&[(0b10, u32::MAX, 0)]), Errno::INVAL);
+    }
+
+    #[test]
+    fn offsets_past_the_blob() {
+        let formats = [DrmFourcc::Xrgb8888 as u32];
+        let valid = blob(&formats, &[(0b1, 0, 0)]);
+        // formats_offset, then modifiers_offset
+        for field in [12, 20] {
+            for offset in [valid.len() as u32, u32::MAX - 1, u32::MAX] {
+                let mut blob = valid.clone();
+                set_u32(&mut blob, field, offset);
+                let result = FormatModifiers::from_blob(&blob);
+                assert_eq!(errno(result), Errno::INVAL);
+            }
+        }
+        // More modifiers than the blob holds
+        let mut blob = valid;
+        set_u32(&mut blob, 16, u32::MAX);
+        assert_eq!(errno(FormatModifiers::from_blob(&blob)), Errno::INVAL);
+    }
+
+    #[test]
+    fn overlapping_windows() {
+        // 70 formats need a second window of 64 formats, which overlaps the first
+        let formats: Vec<u32> = (0..70).map(|i| 0x1000 + i).collect();
+        let blob = blob(&formats, &[(u64::MAX, 0, 1), (u64::MAX, 6, 2)]);
+
+        let decoded = FormatModifiers::from_blob(&blob).unwrap();
+        let modifiers = |index: usize| decoded.get_raw(formats[index]).unwrap().to_vec();
+        assert_eq!(modifiers(0), [DrmModifier::from(1)]);
+        assert_eq!(modifiers(6), [DrmModifier::from(1), DrmModifier::from(2)]);
+        assert_eq!(modifiers(63), [DrmModifier::from(1), DrmModifier::from(2)]);
+        assert_eq!(modifiers(69), [DrmModifier::from(2)]);
+    }
+}
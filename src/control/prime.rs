@@ -0,0 +1,318 @@
+//! # PRIME
+//!
+//! Reference counting for GEM handles of imported PRIME buffers.
+//!
+//! Importing the same dma-buf twice with [`Device::prime_fd_to_buffer()`]
+//! returns the same GEM handle both times, so closing it for one import also
+//! invalidates the other. A [`PrimeImports`] table counts the references to
+//! every imported handle and only closes it when the last one is released.
+//!
+//! ```
+//! # #[cfg(feature = "emulator")] {
+//! use drm::buffer::Buffer;
+//! use drm::control::prime::PrimeImports;
+//! use drm::control::{Device as ControlDevice, PlaneType};
+//! use drm::emulator::Emulator;
+//! use drm_fourcc::DrmFourcc;
+//! use std::os::unix::io::AsFd;
+//!
+//! let mut builder = Emulator::builder();
+//! let crtc = builder.crtc();
+//! builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+//! let (card, _emulator) = builder.build().unwrap();
+//!
+//! // A dma-buf that would usually come from another device or process
+//! let buffer = card.create_dumb_buffer((64, 64), DrmFourcc::Xrgb8888, 32).unwrap();
+//! let dmabuf = card.buffer_to_prime_fd(buffer.handle(), drm::CLOEXEC).unwrap();
+//!
+//! let imports = PrimeImports::new(&card);
+//!
+//! // Both planes of a buffer may well come from the same dma-buf
+//! let y = imports.import(dmabuf.as_fd()).unwrap();
+//! let uv = imports.import(dmabuf.as_fd()).unwrap();
+//! assert_eq!(y.handle(), uv.handle());
+//! drop(y);
+//! // The handle of `uv` is still valid
+//! assert_eq!(imports.references(uv.handle()), 1);
+//!
+//! // The framebuffer keeps the imports it uses alive
+//! let fb = imports.add_framebuffer(&buffer, 24, 32).unwrap();
+//! drop(uv);
+//! assert_eq!(fb.buffers().len(), 1);
+//!
+//! // The handle is closed once the framebuffer is gone
+//! drop(fb);
+//! assert!(imports.is_empty());
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::unix::io::BorrowedFd;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::buffer;
+use crate::control::{framebuffer, Device, FbCmd2Flags};
+
+struct Table<D: Device> {
+    device: D,
+    references: Mutex<HashMap<buffer::Handle, usize>>,
+}
+
+impl<D: Device> Table<D> {
+    fn references(&self) -> MutexGuard<'_, HashMap<buffer::Handle, usize>> {
+        // The map is never left in an inconsistent state
+        self.references
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Adds a reference to a handle that is already in the table.
+    fn acquire(self: &Arc<Self>, handle: buffer::Handle) -> Option<PrimeImport<D>> {
+        let mut references = self.references();
+        *references.get_mut(&handle)? += 1;
+        Some(PrimeImport {
+            table: self.clone(),
+            handle,
+        })
+    }
+}
+
+/// A table of reference counted GEM handles of imported PRIME buffers.
+///
+/// The table can be cloned cheaply, all clones share the same handles.
+pub struct PrimeImports<D: Device> {
+    table: Arc<Table<D>>,
+}
+
+impl<D: Device> PrimeImports<D> {
+    /// Creates an empty table for a device.
+    ///
+    /// Every import of the device should go through the same table.
+    pub fn new(device: D) -> Self {
+        PrimeImports {
+            table: Arc::new(Table {
+                device,
+                references: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Returns the device of this table.
+    pub fn device(&self) -> &D {
+        &self.table.device
+    }
+
+    /// Imports a PRIME file descriptor, returning a new reference to its GEM
+    /// handle.
+    pub fn import(&self, fd: BorrowedFd<'_>) -> io::Result<PrimeImport<D>> {
+        // Holding the lock keeps the handle from being closed concurrently
+        let mut references = self.table.references();
+        let handle = self.table.device.prime_fd_to_buffer(fd)?;
+        *references.entry(handle).or_insert(0) += 1;
+
+        Ok(PrimeImport {
+            table: self.table.clone(),
+            handle,
+        })
+    }
+
+    /// Returns a new reference to an imported GEM handle, or `None` if the
+    /// handle is not in the table.
+    pub fn get(&self, handle: buffer::Handle) -> Option<PrimeImport<D>> {
+        self.table.acquire(handle)
+    }
+
+    /// Returns the number of references to an imported GEM handle.
+    pub fn references(&self, handle: buffer::Handle) -> usize {
+        self.table.references().get(&handle).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of imported GEM handles.
+    pub fn len(&self) -> usize {
+        self.table.references().len()
+    }
+
+    /// Returns whether no GEM handles are imported.
+    pub fn is_empty(&self) -> bool {
+        self.table.references().is_empty()
+    }
+
+    /// Adds a framebuffer, see [`Device::add_framebuffer()`].
+    ///
+    /// The framebuffer holds a reference to the buffer if it was imported
+    /// through this table.
+    pub fn add_framebuffer<B>(
+        &self,
+        buffer: &B,
+        depth: u32,
+        bpp: u32,
+    ) -> io::Result<PrimeFramebuffer<D>>
+    where
+        B: buffer::Buffer + ?Sized,
+    {
+        let handle = self.table.device.add_framebuffer(buffer, depth, bpp)?;
+        Ok(self.framebuffer(handle, [Some(buffer.handle())]))
+    }
+
+    /// Adds a planar framebuffer, see [`Device::add_planar_framebuffer()`].
+    ///
+    /// The framebuffer holds a reference to every plane that was imported
+    /// through this table.
+    pub fn add_planar_framebuffer<B>(
+        &self,
+        buffer: &B,
+        flags: FbCmd2Flags,
+    ) -> io::Result<PrimeFramebuffer<D>>
+    where
+        B: buffer::PlanarBuffer + ?Sized,
+    {
+        let handle = self.table.device.add_planar_framebuffer(buffer, flags)?;
+        Ok(self.framebuffer(handle, buffer.handles()))
+    }
+
+    fn framebuffer<I>(&self, handle: framebuffer::Handle, buffers: I) -> PrimeFramebuffer<D>
+    where
+        I: IntoIterator<Item = Option<buffer::Handle>>,
+    {
+        let mut handles: Vec<_> = buffers.into_iter().flatten().collect();
+        handles.sort_unstable_by_key(|handle| u32::from(*handle));
+        handles.dedup();
+
+        PrimeFramebuffer {
+            buffers: handles
+                .into_iter()
+                .filter_map(|handle| self.table.acquire(handle))
+                .collect(),
+            table: self.table.clone(),
+            handle,
+        }
+    }
+}
+
+impl<D: Device> Clone for PrimeImports<D> {
+    fn clone(&self) -> Self {
+        PrimeImports {
+            table: self.table.clone(),
+        }
+    }
+}
+
+impl<D: Device> fmt::Debug for PrimeImports<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrimeImports")
+            .field("references", &*self.table.references())
+            .finish()
+    }
+}
+
+/// A reference to the GEM handle of an imported PRIME buffer.
+///
+/// The handle is closed when the last reference is dropped. Cloning adds a
+/// reference.
+pub struct PrimeImport<D: Device> {
+    table: Arc<Table<D>>,
+    handle: buffer::Handle,
+}
+
+impl<D: Device> PrimeImport<D> {
+    /// Returns the GEM handle.
+    pub fn handle(&self) -> buffer::Handle {
+        self.handle
+    }
+}
+
+impl<D: Device> Clone for PrimeImport<D> {
+    fn clone(&self) -> Self {
+        self.table
+            .acquire(self.handle)
+            .expect("Imported handle missing from its table")
+    }
+}
+
+impl<D: Device> Drop for PrimeImport<D> {
+    fn drop(&mut self) {
+        let mut references = self.table.references();
+        if let Some(count) = references.get_mut(&self.handle) {
+            *count -= 1;
+            if *count == 0 {
+                references.remove(&self.handle);
+                let _ = self.table.device.close_buffer(self.handle);
+            }
+        }
+    }
+}
+
+impl<D: Device> fmt::Debug for PrimeImport<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PrimeImport").field(&self.handle).finish()
+    }
+}
+
+/// A framebuffer that holds references to the imported buffers it uses.
+///
+/// The framebuffer is destroyed when dropped, before the references to its
+/// buffers are released.
+pub struct PrimeFramebuffer<D: Device> {
+    table: Arc<Table<D>>,
+    handle: framebuffer::Handle,
+    buffers: Vec<PrimeImport<D>>,
+}
+
+impl<D: Device> PrimeFramebuffer<D> {
+    /// Returns the handle of the framebuffer.
+    pub fn handle(&self) -> framebuffer::Handle {
+        self.handle
+    }
+
+    /// Returns the imported buffers used by the framebuffer.
+    pub fn buffers(&self) -> &[PrimeImport<D>] {
+        &self.buffers
+    }
+
+    /// Closes the framebuffer instead of destroying it, leaving it on screen,
+    /// see [`Device::close_framebuffer()`].
+    ///
+    /// The references to the buffers are released afterwards, the kernel
+    /// keeps buffers alive while they are scanned out.
+    pub fn close(self) -> io::Result<()> {
+        let (table, handle, buffers) = self.into_parts();
+        let result = table.device.close_framebuffer(handle);
+        drop(buffers);
+        result
+    }
+
+    /// Releases ownership without destroying the framebuffer, returning its
+    /// handle and the references to its buffers.
+    pub fn into_raw(self) -> (framebuffer::Handle, Vec<PrimeImport<D>>) {
+        let (_, handle, buffers) = self.into_parts();
+        (handle, buffers)
+    }
+
+    fn into_parts(self) -> (Arc<Table<D>>, framebuffer::Handle, Vec<PrimeImport<D>>) {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never used or dropped again
+        let table = unsafe { ptr::read(&this.table) };
+        let buffers = unsafe { ptr::read(&this.buffers) };
+        (table, this.handle, buffers)
+    }
+}
+
+impl<D: Device> Drop for PrimeFramebuffer<D> {
+    fn drop(&mut self) {
+        let _ = self.table.device.destroy_framebuffer(self.handle);
+        // The buffers are released afterwards, when the fields are dropped
+    }
+}
+
+impl<D: Device> fmt::Debug for PrimeFramebuffer<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrimeFramebuffer")
+            .field("handle", &self.handle)
+            .field("buffers", &self.buffers)
+            .finish()
+    }
+}
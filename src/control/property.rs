@@ -20,6 +20,11 @@ pub type RawValue = u64;
 /// A handle to a property
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Handle(RawResourceHandle);
 
 // Safety: Handle is repr(transparent) over NonZeroU32
@@ -56,6 +61,11 @@ impl std::fmt::Debug for Handle {
 
 /// Information about a property
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "SerdeInfo", into = "SerdeInfo")
+)]
 pub struct Info {
     pub(crate) handle: Handle,
     pub(crate) val_type: ValueType,
@@ -91,10 +101,56 @@ impl Info {
     }
 }
 
+/// Serialized form of [`Info`], without the pointers of the raw struct.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "Info")]
+struct SerdeInfo {
+    handle: Handle,
+    name: String,
+    value_type: ValueType,
+    mutable: bool,
+    atomic: bool,
+    flags: u32,
+}
+
+#[cfg(feature = "serde")]
+impl From<Info> for SerdeInfo {
+    fn from(info: Info) -> Self {
+        SerdeInfo {
+            handle: info.handle,
+            name: crate::util::c_name_to_string(&info.info.name),
+            value_type: info.val_type,
+            mutable: info.mutable,
+            atomic: info.atomic,
+            flags: info.info.flags,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SerdeInfo> for Info {
+    fn from(info: SerdeInfo) -> Self {
+        Info {
+            handle: info.handle,
+            val_type: info.value_type,
+            mutable: info.mutable,
+            atomic: info.atomic,
+            info: ffi::drm_mode_get_property {
+                name: crate::util::string_to_c_name(&info.name),
+                prop_id: info.handle.into(),
+                flags: info.flags,
+                ..Default::default()
+            },
+        }
+    }
+}
+
 /// Describes the types of value that a property uses.
 #[allow(clippy::upper_case_acronyms)]
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ValueType {
     /// A catch-all for any unknown types
     Unknown,
@@ -128,7 +184,7 @@ pub enum ValueType {
 
 impl ValueType {
     /// Given a [`RawValue`], convert it into a specific [`Value`]
-    pub fn convert_value(&self, value: RawValue) -> Value {
+    pub fn convert_value(&self, value: RawValue) -> Value<'_> {
         match self {
             ValueType::Unknown => Value::Unknown(value),
             ValueType::Boolean => Value::Boolean(value != 0),
@@ -285,6 +341,11 @@ impl<'a> Value<'a> {
 /// A single value of [`ValueType::Enum`] type
 #[repr(transparent)]
 #[derive(Copy, Clone, Hash, PartialEq, Eq, bytemuck::TransparentWrapper)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "SerdeEnumValue", into = "SerdeEnumValue")
+)]
 pub struct EnumValue(ffi::drm_mode_property_enum);
 
 impl EnumValue {
@@ -305,6 +366,35 @@ impl From<ffi::drm_mode_property_enum> for EnumValue {
     }
 }
 
+/// Serialized form of [`EnumValue`].
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "EnumValue")]
+struct SerdeEnumValue {
+    value: RawValue,
+    name: String,
+}
+
+#[cfg(feature = "serde")]
+impl From<EnumValue> for SerdeEnumValue {
+    fn from(value: EnumValue) -> Self {
+        SerdeEnumValue {
+            value: value.0.value,
+            name: crate::util::c_name_to_string(&value.0.name),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SerdeEnumValue> for EnumValue {
+    fn from(value: SerdeEnumValue) -> Self {
+        EnumValue(ffi::drm_mode_property_enum {
+            value: value.value,
+            name: crate::util::string_to_c_name(&value.name),
+        })
+    }
+}
+
 impl std::fmt::Debug for EnumValue {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("EnumValue")
@@ -316,6 +406,7 @@ impl std::fmt::Debug for EnumValue {
 
 /// A set of [`EnumValue`]s for a single property
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EnumValues {
     pub(crate) values: Vec<u64>,
     pub(crate) enums: Vec<EnumValue>,
@@ -0,0 +1,487 @@
+//! # Routing
+//!
+//! Finds a connector → encoder → CRTC assignment for a set of connectors.
+//!
+//! Every connector needs its own encoder and CRTC, and each encoder can only
+//! drive some of the CRTCs. Assigning them greedily can fail where another
+//! assignment would succeed, so the [`Router`] backtracks until it finds an
+//! assignment for all connectors.
+//!
+//! ```
+//! # #[cfg(feature = "emulator")] {
+//! use drm::control::routing::Router;
+//! use drm::control::{connector, encoder};
+//! use drm::emulator::{self, Emulator};
+//!
+//! let mut builder = Emulator::builder();
+//! let crtcs = [builder.crtc(), builder.crtc()];
+//! // The first encoder can use both CRTCs, the second only the first one
+//! let flexible = builder.encoder(encoder::Kind::TMDS, &crtcs);
+//! let restricted = builder.encoder(encoder::Kind::DAC, &crtcs[..1]);
+//! let modes = [emulator::mode(1920, 1080, 60)];
+//! let hdmi = builder.connector(connector::Interface::HDMIA, &[flexible], &modes);
+//! let vga = builder.connector(connector::Interface::VGA, &[restricted], &modes);
+//! let (card, _emulator) = builder.build().unwrap();
+//!
+//! let routes = Router::new(&card).unwrap().route(&[hdmi, vga]).unwrap().unwrap();
+//! assert_eq!(routes[0].crtc, crtcs[1]);
+//! assert_eq!(routes[1].crtc, crtcs[0]);
+//! # }
+//! ```
+//!
+//! With fewer CRTCs than displays, [`Router::mirror()`] finds routes which
+//! show the same CRTC on several connectors, if their encoders can clone each
+//! other.
+//!
+//! ```
+//! # #[cfg(feature = "emulator")] {
+//! use drm::control::routing::Router;
+//! use drm::control::{connector, encoder};
+//! use drm::emulator::{self, Emulator};
+//!
+//! let mut builder = Emulator::builder();
+//! let crtc = builder.crtc();
+//! let encoders = [
+//!     builder.encoder(encoder::Kind::TMDS, &[crtc]),
+//!     builder.encoder(encoder::Kind::TMDS, &[crtc]),
+//! ];
+//! builder.encoder_clones(&encoders);
+//! let modes = [emulator::mode(1920, 1080, 60)];
+//! let first = builder.connector(connector::Interface::HDMIA, &encoders[..1], &modes);
+//! let second = builder.connector(connector::Interface::HDMIA, &encoders[1..], &modes);
+//! let (card, _emulator) = builder.build().unwrap();
+//!
+//! let router = Router::new(&card).unwrap();
+//! assert_eq!(router.route(&[first, second]).unwrap(), None);
+//! let routes = router.mirror(&[first, second]).unwrap().unwrap();
+//! assert!(routes.iter().all(|route| route.crtc == crtc));
+//! # }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+
+use rustix::io::Errno;
+
+use crate::control::{
+    atomic::AtomicModeReq, connector, crtc, encoder, framebuffer, plane, property,
+    AtomicCommitFlags, Device, Mode, ModeTypeFlags, PlaneType, RawResourceHandle, ResourceHandle,
+    ResourceHandles,
+};
+
+/// The route of a connector through an encoder to a CRTC.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Route {
+    /// The connector
+    pub connector: connector::Handle,
+    /// The encoder driven by the CRTC
+    pub encoder: encoder::Handle,
+    /// The CRTC scanning out to the connector
+    pub crtc: crtc::Handle,
+}
+
+/// Solves the routing of connectors, see the [module documentation](self).
+#[derive(Debug)]
+pub struct Router<'a, D: Device + ?Sized> {
+    device: &'a D,
+    resources: ResourceHandles,
+    connectors: HashMap<connector::Handle, connector::Info>,
+    encoders: HashMap<encoder::Handle, encoder::Info>,
+    prefer_existing: bool,
+    test_only: bool,
+    test_framebuffer: Option<framebuffer::Handle>,
+    /// Property handles of each object by name, queried on first use
+    properties: RefCell<HashMap<RawResourceHandle, HashMap<Vec<u8>, property::Handle>>>,
+}
+
+impl<'a, D: Device + ?Sized> Router<'a, D> {
+    /// Queries the connectors and encoders of a device.
+    ///
+    /// Connectors are not probed, see [`Device::get_connector()`].
+    pub fn new(device: &'a D) -> io::Result<Self> {
+        let resources = device.resource_handles()?;
+        let connectors = resources
+            .connectors()
+            .iter()
+            .map(|&handle| Ok((handle, device.get_connector(handle, false)?)))
+            .collect::<io::Result<_>>()?;
+        let encoders = resources
+            .encoders()
+            .iter()
+            .map(|&handle| Ok((handle, device.get_encoder(handle)?)))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Router {
+            device,
+            resources,
+            connectors,
+            encoders,
+            prefer_existing: false,
+            test_only: false,
+            test_framebuffer: None,
+            properties: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Tries the current route of each connector first, to avoid modesets.
+    pub fn prefer_existing(mut self, prefer_existing: bool) -> Self {
+        self.prefer_existing = prefer_existing;
+        self
+    }
+
+    /// Verifies each complete assignment with an atomic `TEST_ONLY` commit,
+    /// continuing the search if it is rejected.
+    ///
+    /// The commit describes the routes as the complete configuration, using
+    /// the preferred mode of each connector: all other connectors and CRTCs
+    /// are disabled, as are the planes on those CRTCs. The primary plane of
+    /// each used CRTC shows a framebuffer covering the whole mode, see
+    /// [`Router::test_framebuffer()`].
+    ///
+    /// This requires the [`Atomic`](crate::ClientCapability::Atomic) client
+    /// capability, which has to be enabled before routing.
+    pub fn test_only(mut self, test_only: bool) -> Self {
+        self.test_only = test_only;
+        self
+    }
+
+    /// Sets the framebuffer shown on the primary planes in `TEST_ONLY`
+    /// commits, see [`Router::test_only()`].
+    ///
+    /// By default, each primary plane keeps its current framebuffer. If a
+    /// primary plane has none, routing fails with
+    /// [`io::ErrorKind::NotFound`], as the kernel may reject a CRTC without
+    /// one. The framebuffer has to be at least as large as the tested modes,
+    /// otherwise they are rejected.
+    pub fn test_framebuffer(mut self, fb: Option<framebuffer::Handle>) -> Self {
+        self.test_framebuffer = fb;
+        self
+    }
+
+    /// Finds a route for each connector, in the same order.
+    ///
+    /// Returns `None` if there is no valid assignment.
+    pub fn route(&self, connectors: &[connector::Handle]) -> io::Result<Option<Vec<Route>>> {
+        let candidates = connectors
+            .iter()
+            .map(|&connector| self.candidates(connector))
+            .collect::<Vec<_>>();
+        let mut routes = Vec::with_capacity(connectors.len());
+        if self.solve(&candidates, &mut routes, false)? {
+            Ok(Some(routes))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Finds routes which drive all connectors from the same CRTC, mirroring
+    /// its contents, in the same order.
+    ///
+    /// Each connector still needs its own encoder, and those encoders must be
+    /// able to clone each other, see [`encoder::Info::possible_clones()`].
+    /// Returns `None` if the connectors cannot share a CRTC. The routes are
+    /// not verified with a test commit.
+    pub fn mirror(&self, connectors: &[connector::Handle]) -> io::Result<Option<Vec<Route>>> {
+        let mut crtcs = self.resources.crtcs().to_vec();
+        if self.prefer_existing {
+            let current = connectors
+                .first()
+                .and_then(|connector| self.connectors.get(connector))
+                .and_then(|info| self.current_route(info));
+            if let Some(current) = current {
+                crtcs.sort_by_key(|&crtc| crtc != current.crtc);
+            }
+        }
+
+        for crtc in crtcs {
+            let candidates = connectors
+                .iter()
+                .map(|&connector| {
+                    let mut routes = self.candidates(connector);
+                    routes.retain(|route| route.crtc == crtc);
+                    routes
+                })
+                .collect::<Vec<_>>();
+            let mut routes = Vec::with_capacity(connectors.len());
+            if self.solve(&candidates, &mut routes, true)? {
+                return Ok(Some(routes));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the possible routes of a connector, in order of preference.
+    fn candidates(&self, connector: connector::Handle) -> Vec<Route> {
+        let info = match self.connectors.get(&connector) {
+            Some(info) => info,
+            None => return Vec::new(),
+        };
+
+        let mut routes = Vec::new();
+        for encoder in info.encoders() {
+            let encoder = match self.encoders.get(encoder) {
+                Some(encoder) => encoder,
+                None => continue,
+            };
+            for crtc in self.resources.filter_crtcs(encoder.possible_crtcs()) {
+                routes.push(Route {
+                    connector,
+                    encoder: encoder.handle(),
+                    crtc,
+                });
+            }
+        }
+
+        if self.prefer_existing {
+            if let Some(current) = self.current_route(info) {
+                // Stable, so the order of the other routes is kept
+                routes.sort_by_key(|route| *route != current);
+            }
+        }
+        routes
+    }
+
+    fn current_route(&self, info: &connector::Info) -> Option<Route> {
+        let encoder = self.encoders.get(&info.current_encoder()?)?;
+        Some(Route {
+            connector: info.handle(),
+            encoder: encoder.handle(),
+            crtc: encoder.crtc()?,
+        })
+    }
+
+    /// Extends `routes` with one of the candidates of each remaining
+    /// connector, either sharing the CRTC when mirroring or using distinct
+    /// CRTCs otherwise.
+    fn solve(
+        &self,
+        candidates: &[Vec<Route>],
+        routes: &mut Vec<Route>,
+        mirror: bool,
+    ) -> io::Result<bool> {
+        let Some(next) = candidates.get(routes.len()) else {
+            return if self.test_only && !mirror {
+                self.test(routes)
+            } else {
+                Ok(true)
+            };
+        };
+
+        for &route in next {
+            let compatible = routes.iter().all(|r| {
+                if mirror {
+                    self.can_clone(r.encoder, route.encoder)
+                } else {
+                    r.encoder != route.encoder && r.crtc != route.crtc
+                }
+            });
+            if !compatible {
+                continue;
+            }
+            routes.push(route);
+            if self.solve(candidates, routes, mirror)? {
+                return Ok(true);
+            }
+            routes.pop();
+        }
+        Ok(false)
+    }
+
+    /// Returns whether two distinct encoders can be driven by the same CRTC.
+    fn can_clone(&self, a: encoder::Handle, b: encoder::Handle) -> bool {
+        let clones = |encoder, clone| {
+            self.encoders.get(&encoder).map_or(false, |info| {
+                self.resources
+                    .filter_encoders(info.possible_clones())
+                    .contains(&clone)
+            })
+        };
+        a != b && clones(a, b) && clones(b, a)
+    }
+
+    /// Returns whether the kernel accepts the routes.
+    fn test(&self, routes: &[Route]) -> io::Result<bool> {
+        let device = self.device;
+        let mut req = AtomicModeReq::new();
+        let mut blobs = Vec::new();
+
+        let result = (|| {
+            let mut modes = HashMap::new();
+            for (&handle, info) in &self.connectors {
+                let crtc = routes
+                    .iter()
+                    .find(|route| route.connector == handle)
+                    .map(|route| route.crtc);
+                let crtc_id = self.property(handle, "CRTC_ID")?;
+                req.add_property(handle, crtc_id, property::Value::CRTC(crtc));
+
+                if let Some(crtc) = crtc {
+                    let mode = match preferred_mode(info) {
+                        Some(mode) => mode,
+                        None => return Ok(false),
+                    };
+                    modes.insert(crtc, mode);
+                    let blob = device.create_property_blob(&mode)?;
+                    if let property::Value::Blob(id) = blob {
+                        blobs.push(id);
+                    }
+                    req.add_property(crtc, self.property(crtc, "MODE_ID")?, blob);
+                    req.add_property(
+                        crtc,
+                        self.property(crtc, "ACTIVE")?,
+                        property::Value::Boolean(true),
+                    );
+                }
+            }
+
+            for &crtc in self.resources.crtcs() {
+                if modes.contains_key(&crtc) {
+                    continue;
+                }
+                req.add_property(
+                    crtc,
+                    self.property(crtc, "MODE_ID")?,
+                    property::Value::Blob(0),
+                );
+                req.add_property(
+                    crtc,
+                    self.property(crtc, "ACTIVE")?,
+                    property::Value::Boolean(false),
+                );
+            }
+
+            let mut primaries = Vec::new();
+            for plane in device.plane_handles()? {
+                let info = device.get_plane(plane)?;
+                if info.crtc().map_or(false, |crtc| !modes.contains_key(&crtc)) {
+                    let fb_id = self.property(plane, "FB_ID")?;
+                    let crtc_id = self.property(plane, "CRTC_ID")?;
+                    req.add_property(plane, fb_id, property::Value::Framebuffer(None));
+                    req.add_property(plane, crtc_id, property::Value::CRTC(None));
+                }
+
+                let kind = self.property(plane, "type")?;
+                let primary = device
+                    .get_properties(plane)?
+                    .iter()
+                    .any(|(&prop, &value)| prop == kind && value == PlaneType::Primary as u64);
+                if primary {
+                    primaries.push(info);
+                }
+            }
+
+            for route in routes {
+                let crtc = route.crtc;
+                // Prefer the primary plane already on the CRTC
+                let index = primaries
+                    .iter()
+                    .position(|info| info.crtc() == Some(crtc))
+                    .or_else(|| {
+                        primaries.iter().position(|info| {
+                            self.resources
+                                .filter_crtcs(info.possible_crtcs())
+                                .contains(&crtc)
+                        })
+                    });
+                if let Some(index) = index {
+                    let info = primaries.remove(index);
+                    self.add_primary_plane(&mut req, &info, crtc, modes[&crtc])?;
+                }
+            }
+
+            let flags = AtomicCommitFlags::TEST_ONLY | AtomicCommitFlags::ALLOW_MODESET;
+            match device.atomic_commit(flags, req) {
+                Ok(()) => Ok(true),
+                Err(err) => match Errno::from_io_error(&err) {
+                    Some(Errno::INVAL | Errno::RANGE | Errno::NOSPC) => Ok(false),
+                    _ => Err(err),
+                },
+            }
+        })();
+
+        for blob in blobs {
+            let _ = device.destroy_property_blob(blob);
+        }
+        result
+    }
+
+    /// Shows a framebuffer covering the whole mode on a primary plane.
+    fn add_primary_plane(
+        &self,
+        req: &mut AtomicModeReq,
+        info: &plane::Info,
+        crtc: crtc::Handle,
+        mode: Mode,
+    ) -> io::Result<()> {
+        let fb = self
+            .test_framebuffer
+            .or_else(|| info.framebuffer())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no framebuffer to test the primary plane of a CRTC with",
+                )
+            })?;
+        let plane = info.handle();
+        let (width, height) = mode.size();
+        let (width, height) = (u64::from(width), u64::from(height));
+
+        req.add_property(
+            plane,
+            self.property(plane, "FB_ID")?,
+            property::Value::Framebuffer(Some(fb)),
+        );
+        req.add_property(
+            plane,
+            self.property(plane, "CRTC_ID")?,
+            property::Value::CRTC(Some(crtc)),
+        );
+        let rects = [
+            ("SRC_X", 0),
+            ("SRC_Y", 0),
+            ("SRC_W", width << 16),
+            ("SRC_H", height << 16),
+            ("CRTC_X", 0),
+            ("CRTC_Y", 0),
+            ("CRTC_W", width),
+            ("CRTC_H", height),
+        ];
+        for (name, value) in rects {
+            req.add_raw_property(plane.into(), self.property(plane, name)?, value);
+        }
+        Ok(())
+    }
+
+    /// Returns the handle of a property of an object by name.
+    fn property<H: ResourceHandle>(&self, handle: H, name: &str) -> io::Result<property::Handle> {
+        let mut properties = self.properties.borrow_mut();
+        let names = match properties.entry(handle.into()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut names = HashMap::new();
+                for (&prop, _) in self.device.get_properties(handle)?.iter() {
+                    let info = self.device.get_property(prop)?;
+                    names.insert(info.name().to_bytes().to_vec(), prop);
+                }
+                entry.insert(names)
+            }
+        };
+        names
+            .get(name.as_bytes())
+            .copied()
+            .ok_or_else(|| Errno::NOENT.into())
+    }
+}
+
+/// Returns the preferred mode of a connector, or its first mode.
+fn preferred_mode(info: &connector::Info) -> Option<Mode> {
+    let modes = info.modes();
+    modes
+        .iter()
+        .find(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+        .or_else(|| modes.first())
+        .copied()
+}
@@ -0,0 +1,409 @@
+//! # Saved State
+//!
+//! Saves the display configuration found at startup, e.g. the one of the
+//! console, to restore it when exiting.
+//!
+//! A [`SavedState`] records the mode, framebuffer, position, connectors and
+//! gamma ramp of every CRTC and the assignments of the planes. If the
+//! [`Atomic`](crate::ClientCapability::Atomic) client capability is enabled
+//! when it is captured, it also records the values of all atomic properties and
+//! restores them with a single atomic commit. Otherwise it is restored with
+//! [`Device::set_crtc()`], [`Device::set_gamma()`] and [`Device::set_plane()`],
+//! which can only restore planes whose position is known from the atomic
+//! properties, see [`Restored`].
+//!
+//! By default the state is restored when the [`SavedState`] is dropped, which
+//! also happens when unwinding from a panic.
+//!
+//! ```
+//! # #[cfg(feature = "emulator")] {
+//! use drm::control::saved_state::SavedState;
+//! use drm::control::{connector, encoder, Device as ControlDevice, PlaneType};
+//! use drm::emulator::{self, Emulator};
+//! use drm_fourcc::DrmFourcc;
+//!
+//! let mut builder = Emulator::builder();
+//! let crtc = builder.crtc();
+//! let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+//! let modes = [emulator::mode(1920, 1080, 60), emulator::mode(1280, 720, 60)];
+//! let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &modes);
+//! builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+//! let (card, emulator) = builder.build().unwrap();
+//!
+//! let buffer = card.create_dumb_buffer((1920, 1080), DrmFourcc::Xrgb8888, 32).unwrap();
+//! let console = card.add_framebuffer(&buffer, 24, 32).unwrap();
+//! card.set_crtc(crtc, Some(console), (0, 0), &[connector], Some(modes[0])).unwrap();
+//!
+//! let saved = SavedState::capture(&card).unwrap();
+//! let fb = card.add_framebuffer(&buffer, 24, 32).unwrap();
+//! card.set_crtc(crtc, Some(fb), (0, 0), &[connector], Some(modes[1])).unwrap();
+//!
+//! drop(saved);
+//! assert_eq!(emulator.crtc_mode(crtc), Some(modes[0]));
+//! assert_eq!(card.get_crtc(crtc).unwrap().framebuffer(), Some(console));
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::control::{
+    atomic::AtomicModeReq, connector, crtc, plane, property, AtomicCommitFlags, Device,
+    RawResourceHandle, ResourceHandle,
+};
+use drm_ffi as ffi;
+
+/// The display configuration of a device at some point in time.
+///
+/// See the [module documentation](self).
+#[derive(Debug)]
+pub struct SavedState<D: Device> {
+    device: D,
+    restore_on_drop: bool,
+    crtcs: Vec<SavedCrtc>,
+    planes: Vec<SavedPlane>,
+    atomic: Option<SavedProperties>,
+}
+
+/// How much of the configuration [`SavedState::restore()`] restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Restored {
+    /// The whole configuration was restored
+    Complete,
+    /// The legacy restore left planes other than the primary planes of the
+    /// CRTCs alone, as their position is unknown without the atomic
+    /// properties
+    Partial,
+}
+
+/// The `CRTC_*` and `SRC_*` rectangles of a plane.
+type PlanePosition = ((i32, i32, u32, u32), (u32, u32, u32, u32));
+
+#[derive(Debug)]
+struct SavedPlane {
+    info: plane::Info,
+    /// Whether the `type` property marks it as primary plane
+    primary: bool,
+    /// Only known from the atomic properties
+    position: Option<PlanePosition>,
+}
+
+#[derive(Debug)]
+struct SavedCrtc {
+    info: crtc::Info,
+    connectors: Vec<connector::Handle>,
+    gamma: Option<[Vec<u16>; 3]>,
+}
+
+#[derive(Debug, Default)]
+struct SavedProperties {
+    /// Values of the properties and whether they are blob properties
+    values: Vec<(
+        RawResourceHandle,
+        property::Handle,
+        property::RawValue,
+        bool,
+    )>,
+    /// Contents of the blobs referenced by the values, as the blobs may be
+    /// destroyed once they are no longer used
+    blobs: HashMap<property::RawValue, Vec<u8>>,
+    /// CRTCs without a `GAMMA_LUT` property, whose gamma ramp is restored
+    /// separately
+    legacy_gamma: Vec<crtc::Handle>,
+}
+
+impl<D: Device> SavedState<D> {
+    /// Saves the current display configuration of a device.
+    ///
+    /// The atomic state is only saved if the
+    /// [`Atomic`](crate::ClientCapability::Atomic) client capability is
+    /// enabled.
+    pub fn capture(device: D) -> io::Result<Self> {
+        let resources = device.resource_handles()?;
+
+        let mut connectors = HashMap::<crtc::Handle, Vec<_>>::new();
+        for &handle in resources.connectors() {
+            let crtc = match device.get_connector(handle, false)?.current_encoder() {
+                Some(encoder) => device.get_encoder(encoder)?.crtc(),
+                None => None,
+            };
+            if let Some(crtc) = crtc {
+                connectors.entry(crtc).or_default().push(handle);
+            }
+        }
+
+        let mut crtcs = Vec::new();
+        for &handle in resources.crtcs() {
+            let info = device.get_crtc(handle)?;
+            let gamma = match info.gamma_length() as usize {
+                0 => None,
+                length => {
+                    let mut gamma = [vec![0; length], vec![0; length], vec![0; length]];
+                    let [red, green, blue] = &mut gamma;
+                    device
+                        .get_gamma(handle, red, green, blue)
+                        .ok()
+                        .map(|()| gamma)
+                }
+            };
+            crtcs.push(SavedCrtc {
+                info,
+                connectors: connectors.remove(&handle).unwrap_or_default(),
+                gamma,
+            });
+        }
+
+        let planes = device
+            .plane_handles()?
+            .into_iter()
+            .map(|handle| SavedPlane::capture(&device, handle))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut atomic = SavedProperties::default();
+        for &handle in resources.connectors() {
+            atomic.save(&device, handle)?;
+        }
+        for crtc in &crtcs {
+            if !atomic.save(&device, crtc.info.handle())? {
+                atomic.legacy_gamma.push(crtc.info.handle());
+            }
+        }
+        for plane in &planes {
+            atomic.save(&device, plane.info.handle())?;
+        }
+        // Atomic properties are only visible to atomic clients
+        let atomic = Some(atomic).filter(|atomic| atomic.is_atomic(&device));
+
+        Ok(SavedState {
+            device,
+            restore_on_drop: true,
+            crtcs,
+            planes,
+            atomic,
+        })
+    }
+
+    /// Returns the device of the saved state.
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Sets whether the state is restored when dropped, which is the default.
+    pub fn set_restore_on_drop(&mut self, restore_on_drop: bool) {
+        self.restore_on_drop = restore_on_drop;
+    }
+
+    /// Returns whether the state will be restored with an atomic commit.
+    pub fn is_atomic(&self) -> bool {
+        self.atomic.is_some()
+    }
+
+    /// Restores the saved configuration.
+    ///
+    /// If the atomic commit fails, e.g. because a saved framebuffer is gone,
+    /// the legacy restore is used instead. The legacy restore continues after
+    /// errors, to restore as much as possible, and returns the first one.
+    ///
+    /// The legacy restore can only restore planes showing a framebuffer if
+    /// they are the primary plane of their CRTC, or if their position was
+    /// saved from the atomic properties. Other planes are left as they are,
+    /// and [`Restored::Partial`] is returned.
+    pub fn restore(&self) -> io::Result<Restored> {
+        match &self.atomic {
+            Some(atomic) => {
+                if atomic.restore(&self.device).is_err() {
+                    return self.restore_legacy();
+                }
+                let mut result = Ok(Restored::Complete);
+                for crtc in &self.crtcs {
+                    if atomic.legacy_gamma.contains(&crtc.info.handle()) {
+                        result = result.and(self.restore_gamma(crtc).map(|()| Restored::Complete));
+                    }
+                }
+                result
+            }
+            None => self.restore_legacy(),
+        }
+    }
+
+    fn restore_legacy(&self) -> io::Result<Restored> {
+        let mut result = Ok(());
+        let mut restored = Restored::Complete;
+
+        for crtc in &self.crtcs {
+            let info = &crtc.info;
+            result = result.and(match info.mode() {
+                Some(mode) => self.device.set_crtc(
+                    info.handle(),
+                    info.framebuffer(),
+                    info.position(),
+                    &crtc.connectors,
+                    Some(mode),
+                ),
+                None => self.device.set_crtc(info.handle(), None, (0, 0), &[], None),
+            });
+            result = result.and(self.restore_gamma(crtc));
+        }
+
+        for plane in &self.planes {
+            let handle = plane.info.handle();
+            if let (Some(crtc), Some(fb)) = (plane.info.crtc(), plane.info.framebuffer()) {
+                match plane.position {
+                    // Restored along with the CRTC
+                    _ if plane.primary => {}
+                    Some((crtc_rect, src_rect)) => {
+                        result = result.and(self.device.set_plane(
+                            handle,
+                            crtc,
+                            Some(fb),
+                            0,
+                            crtc_rect,
+                            src_rect,
+                        ));
+                    }
+                    None => restored = Restored::Partial,
+                }
+                continue;
+            }
+            let crtc = match self.device.get_plane(handle) {
+                Ok(info) if info.framebuffer().is_some() => info.crtc(),
+                Ok(_) => None,
+                Err(err) => {
+                    result = result.and(Err(err));
+                    None
+                }
+            };
+            if let Some(crtc) = crtc {
+                result = result.and(self.device.set_plane(
+                    handle,
+                    crtc,
+                    None,
+                    0,
+                    (0, 0, 0, 0),
+                    (0, 0, 0, 0),
+                ));
+            }
+        }
+
+        result.map(|()| restored)
+    }
+
+    fn restore_gamma(&self, crtc: &SavedCrtc) -> io::Result<()> {
+        match &crtc.gamma {
+            Some([red, green, blue]) => self.device.set_gamma(crtc.info.handle(), red, green, blue),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SavedPlane {
+    fn capture<D: Device + ?Sized>(device: &D, handle: plane::Handle) -> io::Result<Self> {
+        const POSITION: [&[u8]; 8] = [
+            b"CRTC_X", b"CRTC_Y", b"CRTC_W", b"CRTC_H", b"SRC_X", b"SRC_Y", b"SRC_W", b"SRC_H",
+        ];
+
+        let mut primary = false;
+        let mut values = [None; 8];
+        for (&prop, &value) in device.get_properties(handle)?.iter() {
+            let info = device.get_property(prop)?;
+            let name = info.name().to_bytes();
+            if name == b"type" {
+                primary = value == ffi::DRM_PLANE_TYPE_PRIMARY as u64;
+            } else if let Some(i) = POSITION.iter().position(|n| *n == name) {
+                values[i] = Some(value);
+            }
+        }
+
+        let position = match values {
+            [Some(x), Some(y), Some(w), Some(h), Some(src_x), Some(src_y), Some(src_w), Some(src_h)] => {
+                Some((
+                    (x as i32, y as i32, w as u32, h as u32),
+                    (src_x as u32, src_y as u32, src_w as u32, src_h as u32),
+                ))
+            }
+            _ => None,
+        };
+        Ok(SavedPlane {
+            info: device.get_plane(handle)?,
+            primary,
+            position,
+        })
+    }
+}
+
+impl<D: Device> Drop for SavedState<D> {
+    fn drop(&mut self) {
+        if self.restore_on_drop {
+            let _ = self.restore();
+        }
+    }
+}
+
+impl SavedProperties {
+    /// Saves the mutable properties of an object, returning whether it has a
+    /// `GAMMA_LUT` property.
+    fn save<D, H>(&mut self, device: &D, handle: H) -> io::Result<bool>
+    where
+        D: Device + ?Sized,
+        H: ResourceHandle,
+    {
+        let mut gamma_lut = false;
+        for (&prop, &value) in device.get_properties(handle)?.iter() {
+            let info = device.get_property(prop)?;
+            let name = info.name().to_bytes();
+            gamma_lut |= name == b"GAMMA_LUT";
+            // DPMS can only be set with the legacy API
+            if !info.mutable() || name == b"DPMS" {
+                continue;
+            }
+            let blob = matches!(info.value_type(), property::ValueType::Blob);
+            if blob && value != 0 && !self.blobs.contains_key(&value) {
+                self.blobs.insert(value, device.get_property_blob(value)?);
+            }
+            self.values.push((handle.into(), prop, value, blob));
+        }
+        Ok(gamma_lut)
+    }
+
+    fn is_atomic<D: Device + ?Sized>(&self, device: &D) -> bool {
+        self.values.iter().any(|&(_, prop, _, _)| {
+            device
+                .get_property(prop)
+                .map_or(false, |info| info.atomic())
+        })
+    }
+
+    fn restore<D: Device + ?Sized>(&self, device: &D) -> io::Result<()> {
+        // Recreate the blobs, which are referenced by the state once committed
+        let mut blobs = HashMap::new();
+        let mut result = Ok(());
+        for (&id, data) in &self.blobs {
+            match ffi::mode::create_property_blob(device.as_fd(), &mut data.clone()) {
+                Ok(blob) => {
+                    blobs.insert(id, u64::from(blob.blob_id));
+                }
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        if result.is_ok() {
+            let mut req = AtomicModeReq::new();
+            for &(object, prop, value, blob) in &self.values {
+                let value = match blobs.get(&value) {
+                    Some(&id) if blob => id,
+                    _ => value,
+                };
+                req.add_raw_property(object, prop, value);
+            }
+            result = device.atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req);
+        }
+
+        for &blob in blobs.values() {
+            let _ = device.destroy_property_blob(blob);
+        }
+        result
+    }
+}
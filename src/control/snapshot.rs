@@ -0,0 +1,570 @@
+//! # Snapshot
+//!
+//! Captures the complete modesetting state of a device at once, e.g. to attach
+//! it to a bug report.
+//!
+//! A [`DeviceSnapshot`] contains the information of every connector, encoder,
+//! CRTC, plane and framebuffer, all of their properties including the contents
+//! of blobs, and the capabilities of the driver. With the `serde` feature it
+//! can be serialized and deserialized.
+//!
+//! Planes other than overlays, as well as writeback connectors are only part of
+//! the snapshot if the corresponding [`ClientCapability`](crate::ClientCapability)
+//! was enabled beforehand.
+//!
+//! Two snapshots can be compared with [`DeviceSnapshot::diff()`], e.g. to find
+//! out what changed after a hotplug event.
+//!
+//! ```
+//! # #[cfg(feature = "emulator")] {
+//! use drm::control::snapshot::DeviceSnapshot;
+//! use drm::control::PlaneType;
+//! use drm::emulator::Emulator;
+//! use drm::{ClientCapability, Device};
+//! use drm_fourcc::DrmFourcc;
+//!
+//! let mut builder = Emulator::builder();
+//! let crtc = builder.crtc();
+//! builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+//! let (card, _emulator) = builder.build().unwrap();
+//! card.set_client_capability(ClientCapability::UniversalPlanes, true).unwrap();
+//!
+//! let snapshot = DeviceSnapshot::capture(&card).unwrap();
+//! assert_eq!(snapshot.crtcs[0].info.handle(), crtc);
+//! let plane = &snapshot.planes[0];
+//! assert!(plane.property("type").is_some());
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+
+use crate::control::{
+    connector, crtc, encoder, framebuffer, plane, property, Device, Mode, RawResourceHandle,
+    ResourceHandle, ResourceHandles,
+};
+use crate::DriverCapabilities;
+
+/// The modesetting state of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceSnapshot {
+    /// Version of the driver
+    pub driver: DriverVersion,
+    /// Capabilities of the driver
+    pub capabilities: DriverCapabilities,
+    /// Handles of all resources, except for planes
+    pub resources: ResourceHandles,
+    /// All connectors
+    pub connectors: Vec<ObjectSnapshot<connector::Info>>,
+    /// All encoders
+    pub encoders: Vec<encoder::Info>,
+    /// All CRTCs
+    pub crtcs: Vec<ObjectSnapshot<crtc::Info>>,
+    /// All planes
+    pub planes: Vec<ObjectSnapshot<plane::Info>>,
+    /// All framebuffers that are owned by the device or used by a CRTC or plane
+    pub framebuffers: Vec<FramebufferSnapshot>,
+}
+
+/// Version of the driver of a device, see [`Driver`](crate::Driver).
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DriverVersion {
+    /// Name of the driver
+    pub name: String,
+    /// Date the driver was published
+    pub date: String,
+    /// Description of the driver
+    pub description: String,
+}
+
+/// The information and properties of a modesetting object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ObjectSnapshot<T> {
+    /// Information about the object
+    pub info: T,
+    /// Properties of the object
+    pub properties: Vec<PropertySnapshot>,
+}
+
+impl<T> ObjectSnapshot<T> {
+    /// Returns the property with the given name.
+    pub fn property(&self, name: &str) -> Option<&PropertySnapshot> {
+        self.properties
+            .iter()
+            .find(|property| property.name().to_bytes() == name.as_bytes())
+    }
+}
+
+/// A property of a modesetting object and its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PropertySnapshot {
+    /// Information about the property
+    pub info: property::Info,
+    /// The raw value of the property
+    pub value: property::RawValue,
+    /// Contents of the blob, if the property is a blob that is set
+    pub blob: Option<Vec<u8>>,
+}
+
+impl PropertySnapshot {
+    /// Returns the handle of the property.
+    pub fn handle(&self) -> property::Handle {
+        self.info.handle()
+    }
+
+    /// Returns the name of the property.
+    pub fn name(&self) -> &CStr {
+        self.info.name()
+    }
+
+    /// Returns the decoded value of the property.
+    pub fn value(&self) -> property::Value<'_> {
+        self.info.val_type.convert_value(self.value)
+    }
+}
+
+/// The information of a framebuffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FramebufferSnapshot {
+    /// Information about the framebuffer
+    pub info: framebuffer::Info,
+    /// Information about the planes of the framebuffer, if the driver supports
+    /// querying them
+    pub planar: Option<framebuffer::PlanarInfo>,
+}
+
+impl DeviceSnapshot {
+    /// Captures the modesetting state of a device.
+    ///
+    /// Framebuffers that cannot be queried, e.g. because they were removed
+    /// concurrently, are left out.
+    pub fn capture<D: Device + ?Sized>(device: &D) -> io::Result<Self> {
+        let driver = device.get_driver()?;
+        let resources = device.resource_handles()?;
+        let mut properties = PropertyCache::default();
+
+        let connectors = resources
+            .connectors()
+            .iter()
+            .map(|&handle| {
+                let info = device.get_connector(handle, false)?;
+                properties.object(device, handle, info)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let encoders = resources
+            .encoders()
+            .iter()
+            .map(|&handle| device.get_encoder(handle))
+            .collect::<io::Result<Vec<_>>>()?;
+        let crtcs = resources
+            .crtcs()
+            .iter()
+            .map(|&handle| {
+                let info = device.get_crtc(handle)?;
+                properties.object(device, handle, info)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let planes = device
+            .plane_handles()?
+            .into_iter()
+            .map(|handle| {
+                let info = device.get_plane(handle)?;
+                properties.object(device, handle, info)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut fbs = resources.framebuffers().to_vec();
+        fbs.extend(crtcs.iter().filter_map(|crtc| crtc.info.framebuffer()));
+        fbs.extend(planes.iter().filter_map(|plane| plane.info.framebuffer()));
+        fbs.sort_unstable_by_key(|fb| u32::from(*fb));
+        fbs.dedup();
+        let framebuffers = fbs
+            .into_iter()
+            .filter_map(|handle| {
+                Some(FramebufferSnapshot {
+                    info: device.get_framebuffer(handle).ok()?,
+                    planar: device.get_planar_framebuffer(handle).ok(),
+                })
+            })
+            .collect();
+
+        Ok(DeviceSnapshot {
+            driver: DriverVersion {
+                name: driver.name().to_string_lossy().into_owned(),
+                date: driver.date().to_string_lossy().into_owned(),
+                description: driver.description().to_string_lossy().into_owned(),
+            },
+            capabilities: device.get_driver_capabilities()?,
+            resources,
+            connectors,
+            encoders,
+            crtcs,
+            planes,
+            framebuffers,
+        })
+    }
+
+    /// Returns the snapshot of a connector.
+    pub fn connector(&self, handle: connector::Handle) -> Option<&ObjectSnapshot<connector::Info>> {
+        self.connectors.iter().find(|c| c.info.handle() == handle)
+    }
+
+    /// Returns the information of an encoder.
+    pub fn encoder(&self, handle: encoder::Handle) -> Option<&encoder::Info> {
+        self.encoders.iter().find(|e| e.handle() == handle)
+    }
+
+    /// Returns the snapshot of a CRTC.
+    pub fn crtc(&self, handle: crtc::Handle) -> Option<&ObjectSnapshot<crtc::Info>> {
+        self.crtcs.iter().find(|c| c.info.handle() == handle)
+    }
+
+    /// Returns the snapshot of a plane.
+    pub fn plane(&self, handle: plane::Handle) -> Option<&ObjectSnapshot<plane::Info>> {
+        self.planes.iter().find(|p| p.info.handle() == handle)
+    }
+
+    /// Returns the snapshot of a framebuffer.
+    pub fn framebuffer(&self, handle: framebuffer::Handle) -> Option<&FramebufferSnapshot> {
+        self.framebuffers
+            .iter()
+            .find(|fb| fb.info.handle() == handle)
+    }
+
+    /// Compares this snapshot to a newer one.
+    ///
+    /// ```
+    /// # #[cfg(feature = "emulator")] {
+    /// use drm::control::snapshot::DeviceSnapshot;
+    /// use drm::control::{connector, encoder};
+    /// use drm::emulator::{self, Emulator};
+    ///
+    /// let mut builder = Emulator::builder();
+    /// let crtc = builder.crtc();
+    /// let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+    /// let mode = emulator::mode(1920, 1080, 60);
+    /// let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &[mode]);
+    /// let (card, emulator) = builder.build().unwrap();
+    ///
+    /// let before = DeviceSnapshot::capture(&card).unwrap();
+    /// emulator.set_connector_state(connector, connector::State::Disconnected);
+    /// let after = DeviceSnapshot::capture(&card).unwrap();
+    ///
+    /// let diff = before.diff(&after);
+    /// let change = &diff.connector_changes[0];
+    /// assert_eq!(change.handle, connector);
+    /// assert_eq!(
+    ///     change.state,
+    ///     Some((connector::State::Connected, connector::State::Disconnected))
+    /// );
+    /// println!("{}", diff);
+    /// # }
+    /// ```
+    pub fn diff(&self, newer: &DeviceSnapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+
+        for old in &self.connectors {
+            let handle = old.info.handle();
+            match newer.connector(handle) {
+                Some(new) => {
+                    let change = ConnectorChange {
+                        handle,
+                        state: Some((old.info.state(), new.info.state()))
+                            .filter(|(old, new)| old != new),
+                        modes_added: missing_modes(new.info.modes(), old.info.modes()),
+                        modes_removed: missing_modes(old.info.modes(), new.info.modes()),
+                    };
+                    if !change.is_empty() {
+                        diff.connector_changes.push(change);
+                    }
+                    diff_properties(&mut diff, handle.into(), old, new);
+                }
+                None => diff.connectors_removed.push(handle),
+            }
+        }
+        diff.connectors_added = newer
+            .connectors
+            .iter()
+            .map(|new| new.info.handle())
+            .filter(|&handle| self.connector(handle).is_none())
+            .collect();
+
+        for old in &self.crtcs {
+            if let Some(new) = newer.crtc(old.info.handle()) {
+                diff_properties(&mut diff, old.info.handle().into(), old, new);
+            }
+        }
+        for old in &self.planes {
+            if let Some(new) = newer.plane(old.info.handle()) {
+                diff_properties(&mut diff, old.info.handle().into(), old, new);
+            }
+        }
+
+        diff.framebuffers_removed = self
+            .framebuffers
+            .iter()
+            .map(|fb| fb.info.handle())
+            .filter(|&handle| newer.framebuffer(handle).is_none())
+            .collect();
+        diff.framebuffers_added = newer
+            .framebuffers
+            .iter()
+            .map(|fb| fb.info.handle())
+            .filter(|&handle| self.framebuffer(handle).is_none())
+            .collect();
+
+        diff
+    }
+}
+
+/// The differences between two [`DeviceSnapshot`]s, see
+/// [`DeviceSnapshot::diff()`].
+///
+/// The [`Display`](fmt::Display) implementation lists one change per line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotDiff {
+    /// Connectors that appeared, e.g. DisplayPort MST connectors
+    pub connectors_added: Vec<connector::Handle>,
+    /// Connectors that disappeared
+    pub connectors_removed: Vec<connector::Handle>,
+    /// Connectors whose state or modes changed
+    pub connector_changes: Vec<ConnectorChange>,
+    /// Properties whose value changed, of objects that are part of both
+    /// snapshots
+    pub property_changes: Vec<PropertyChange>,
+    /// Framebuffers that were added
+    pub framebuffers_added: Vec<framebuffer::Handle>,
+    /// Framebuffers that were removed
+    pub framebuffers_removed: Vec<framebuffer::Handle>,
+}
+
+impl SnapshotDiff {
+    /// Returns whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.connectors_added.is_empty()
+            && self.connectors_removed.is_empty()
+            && self.connector_changes.is_empty()
+            && self.property_changes.is_empty()
+            && self.framebuffers_added.is_empty()
+            && self.framebuffers_removed.is_empty()
+    }
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for handle in &self.connectors_added {
+            writeln!(f, "{:?} added", handle)?;
+        }
+        for handle in &self.connectors_removed {
+            writeln!(f, "{:?} removed", handle)?;
+        }
+        for change in &self.connector_changes {
+            if let Some((old, new)) = change.state {
+                writeln!(f, "{:?} state: {:?} -> {:?}", change.handle, old, new)?;
+            }
+            for mode in &change.modes_added {
+                writeln!(f, "{:?} mode added: {:?}", change.handle, mode)?;
+            }
+            for mode in &change.modes_removed {
+                writeln!(f, "{:?} mode removed: {:?}", change.handle, mode)?;
+            }
+        }
+        for change in &self.property_changes {
+            write!(f, "Object {} property {:?}: ", change.object, change.name())?;
+            match &change.old {
+                Some(old) => write!(f, "{:?}", old.value())?,
+                None => write!(f, "unset")?,
+            }
+            match &change.new {
+                Some(new) => writeln!(f, " -> {:?}", new.value())?,
+                None => writeln!(f, " -> unset")?,
+            }
+        }
+        for handle in &self.framebuffers_added {
+            writeln!(f, "{:?} added", handle)?;
+        }
+        for handle in &self.framebuffers_removed {
+            writeln!(f, "{:?} removed", handle)?;
+        }
+        Ok(())
+    }
+}
+
+/// Changes of a connector that is part of both snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectorChange {
+    /// The connector
+    pub handle: connector::Handle,
+    /// The old and new state, if it changed
+    pub state: Option<(connector::State, connector::State)>,
+    /// Modes that were added
+    pub modes_added: Vec<Mode>,
+    /// Modes that were removed
+    pub modes_removed: Vec<Mode>,
+}
+
+impl ConnectorChange {
+    fn is_empty(&self) -> bool {
+        self.state.is_none() && self.modes_added.is_empty() && self.modes_removed.is_empty()
+    }
+}
+
+/// A property of an object whose value changed.
+///
+/// Blob properties are also considered changed if the contents of the blob
+/// changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PropertyChange {
+    /// The object the property belongs to
+    pub object: RawResourceHandle,
+    /// The old property, if the object had it
+    pub old: Option<PropertySnapshot>,
+    /// The new property, if the object has it
+    pub new: Option<PropertySnapshot>,
+}
+
+impl PropertyChange {
+    /// Returns the name of the property.
+    pub fn name(&self) -> &CStr {
+        // One of them is always set
+        self.new.as_ref().or(self.old.as_ref()).unwrap().name()
+    }
+}
+
+fn missing_modes(modes: &[Mode], other: &[Mode]) -> Vec<Mode> {
+    modes
+        .iter()
+        .filter(|mode| !other.contains(mode))
+        .copied()
+        .collect()
+}
+
+fn diff_properties<T>(
+    diff: &mut SnapshotDiff,
+    object: RawResourceHandle,
+    old: &ObjectSnapshot<T>,
+    new: &ObjectSnapshot<T>,
+) {
+    let find = |snapshot: &ObjectSnapshot<T>, handle| {
+        snapshot
+            .properties
+            .iter()
+            .find(|property| property.handle() == handle)
+            .cloned()
+    };
+
+    for property in &old.properties {
+        let new = find(new, property.handle());
+        let changed = match &new {
+            Some(new) => new.value != property.value || new.blob != property.blob,
+            None => true,
+        };
+        if changed {
+            diff.property_changes.push(PropertyChange {
+                object,
+                old: Some(property.clone()),
+                new,
+            });
+        }
+    }
+    for property in &new.properties {
+        if find(old, property.handle()).is_none() {
+            diff.property_changes.push(PropertyChange {
+                object,
+                old: None,
+                new: Some(property.clone()),
+            });
+        }
+    }
+}
+
+/// Queries the information of every property only once.
+#[derive(Default)]
+struct PropertyCache {
+    infos: HashMap<property::Handle, property::Info>,
+}
+
+impl PropertyCache {
+    fn object<D, H, T>(&mut self, device: &D, handle: H, info: T) -> io::Result<ObjectSnapshot<T>>
+    where
+        D: Device + ?Sized,
+        H: ResourceHandle,
+    {
+        let values = device.get_properties(handle)?;
+        let (handles, raw_values) = values.as_props_and_values();
+
+        let mut properties = Vec::with_capacity(handles.len());
+        for (&handle, &value) in handles.iter().zip(raw_values) {
+            let info = match self.infos.get(&handle) {
+                Some(info) => info.clone(),
+                None => {
+                    let info = device.get_property(handle)?;
+                    self.infos.insert(handle, info.clone());
+                    info
+                }
+            };
+            let blob = match info.value_type() {
+                property::ValueType::Blob if value != 0 => device.get_property_blob(value).ok(),
+                _ => None,
+            };
+            properties.push(PropertySnapshot { info, value, blob });
+        }
+
+        Ok(ObjectSnapshot { info, properties })
+    }
+}
+
+#[cfg(all(test, feature = "serde", feature = "emulator"))]
+mod tests {
+    use super::*;
+    use crate::control::{connector, encoder, PlaneType};
+    use crate::emulator::{self, Emulator};
+    use crate::{ClientCapability, Device as _};
+    use drm_fourcc::DrmFourcc;
+
+    #[test]
+    fn serde_roundtrip() {
+        let mut builder = Emulator::builder();
+        let crtc = builder.crtc();
+        let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+        let mode = emulator::mode(1920, 1080, 60);
+        let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &[mode]);
+        builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+        let (card, _emulator) = builder.build().unwrap();
+        card.set_client_capability(ClientCapability::UniversalPlanes, true)
+            .unwrap();
+        card.set_client_capability(ClientCapability::Atomic, true)
+            .unwrap();
+
+        let buffer = card
+            .create_dumb_buffer((1920, 1080), DrmFourcc::Xrgb8888, 32)
+            .unwrap();
+        let fb = card.add_framebuffer(&buffer, 24, 32).unwrap();
+        card.set_crtc(crtc, Some(fb), (0, 0), &[connector], Some(mode))
+            .unwrap();
+
+        let snapshot = DeviceSnapshot::capture(&card).unwrap();
+        // Modes and the property infos are serialized through their own types
+        assert_eq!(snapshot.crtcs[0].info.mode(), Some(mode));
+        assert!(snapshot.crtcs[0]
+            .property("MODE_ID")
+            .unwrap()
+            .blob
+            .is_some());
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let decoded: DeviceSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snapshot);
+        assert!(snapshot.diff(&decoded).is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+//! # Timing
+//!
+//! Tracks the vblank timing of CRTCs from the events they send.
+//!
+//! A [`VblankTracker`] consumes vblank, page flip and CRTC sequence events,
+//! estimates the refresh interval of each CRTC and predicts its next vblank.
+//! For every event it produces a [`PresentationFeedback`] record modelled
+//! after the `wp_presentation` protocol of Wayland, which also reports missed
+//! frames and discontinuities of the vblank counter.
+//!
+//! All timestamps are in `CLOCK_MONOTONIC`, like the ones of the kernel. The
+//! tracker only ever looks at the events it is given, so it can also be driven
+//! by synthetic events:
+//!
+//! ```
+//! use drm::control::timing::{PresentationFlags, VblankTracker};
+//! use drm::control::{from_u32, PageFlipEvent};
+//! use std::time::Duration;
+//!
+//! let crtc = from_u32(1).unwrap();
+//! let frame = Duration::from_micros(16_667);
+//! let flip = |frame_nr: u32| PageFlipEvent {
+//!     frame: frame_nr,
+//!     duration: frame * frame_nr,
+//!     crtc,
+//!     user_data: 0,
+//! };
+//!
+//! let mut tracker = VblankTracker::new();
+//! tracker.record_page_flip(&flip(1), PresentationFlags::empty());
+//! tracker.record_page_flip(&flip(2), PresentationFlags::empty());
+//! assert_eq!(tracker.refresh(crtc), Some(frame));
+//!
+//! // Frame 3 was missed
+//! let feedback = tracker.record_page_flip(&flip(4), PresentationFlags::empty());
+//! assert_eq!(feedback.missed_frames, 1);
+//! assert_eq!(tracker.predict(crtc, frame * 4 + frame / 2), Some((5, frame * 5)));
+//! ```
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::control::{crtc, CrtcSequenceEvent, PageFlipEvent, VblankEvent};
+
+bitflags::bitflags! {
+    /// Flags describing how a frame was presented, matching the `kind` flags
+    /// of `wp_presentation_feedback`
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct PresentationFlags : u32 {
+        /// The presentation was synchronized to the vblank
+        const VSYNC = 0x1;
+        /// The timestamp comes from the display hardware
+        const HW_CLOCK = 0x2;
+        /// The display hardware signalled the completion of the presentation
+        const HW_COMPLETION = 0x4;
+        /// The client buffer was scanned out directly
+        const ZERO_COPY = 0x8;
+    }
+}
+
+/// Information about a presented frame, see [`VblankTracker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PresentationFeedback {
+    /// CRTC the frame was presented on
+    pub crtc: crtc::Handle,
+    /// Vblank sequence at which the frame was presented
+    pub sequence: u64,
+    /// Time at which the frame was presented
+    pub time: Duration,
+    /// Estimated refresh interval of the CRTC
+    pub refresh: Option<Duration>,
+    /// How the frame was presented
+    pub flags: PresentationFlags,
+    /// Number of vblanks between the previous recorded event of this CRTC and
+    /// this one that had no event recorded, i.e. frames missed by a client
+    /// that flips every frame, or 0 after a discontinuity or for the first event
+    pub missed_frames: u64,
+    /// Whether the sequence does not continue the previous one, e.g. because
+    /// the counter was reset or jumped while the CRTC was off
+    pub discontinuity: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CrtcTiming {
+    sequence: u64,
+    time: Duration,
+    refresh: Option<Duration>,
+}
+
+/// Tracks the vblank timing of CRTCs.
+#[derive(Debug, Clone, Default)]
+pub struct VblankTracker {
+    crtcs: HashMap<crtc::Handle, CrtcTiming>,
+    nominal: HashMap<crtc::Handle, Duration>,
+}
+
+/// Flags of every presentation reported by a kernel event.
+const KERNEL_FLAGS: PresentationFlags = PresentationFlags::HW_CLOCK
+    .union(PresentationFlags::HW_COMPLETION)
+    .union(PresentationFlags::VSYNC);
+
+impl VblankTracker {
+    /// Creates an empty tracker.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the nominal refresh interval of a CRTC, e.g. derived from its
+    /// mode, which is used until it can be estimated from events.
+    ///
+    /// Should be called again when the mode changes.
+    pub fn set_nominal_refresh(&mut self, crtc: crtc::Handle, refresh: Duration) {
+        self.nominal.insert(crtc, refresh);
+        if let Some(timing) = self.crtcs.get_mut(&crtc) {
+            timing.refresh = Some(refresh);
+        }
+    }
+
+    /// Forgets everything known about a CRTC, e.g. after it was disabled.
+    pub fn reset(&mut self, crtc: crtc::Handle) {
+        self.crtcs.remove(&crtc);
+    }
+
+    /// Records a vblank event.
+    pub fn record_vblank(&mut self, event: &VblankEvent) -> PresentationFeedback {
+        let sequence = self.extend_sequence(event.crtc, event.frame);
+        self.record(event.crtc, sequence, event.time, KERNEL_FLAGS)
+    }
+
+    /// Records a page flip event.
+    ///
+    /// `flags` are added to the flags every kernel event has, e.g.
+    /// [`PresentationFlags::ZERO_COPY`]. Asynchronous page flips should be
+    /// recorded with [`VblankTracker::record`] instead, as they are not
+    /// synchronized to the vblank.
+    pub fn record_page_flip(
+        &mut self,
+        event: &PageFlipEvent,
+        flags: PresentationFlags,
+    ) -> PresentationFeedback {
+        let sequence = self.extend_sequence(event.crtc, event.frame);
+        self.record(event.crtc, sequence, event.duration, KERNEL_FLAGS | flags)
+    }
+
+    /// Records a CRTC sequence event of a CRTC.
+    pub fn record_crtc_sequence(
+        &mut self,
+        crtc: crtc::Handle,
+        event: &CrtcSequenceEvent,
+    ) -> PresentationFeedback {
+        self.record(crtc, event.sequence, event.time, KERNEL_FLAGS)
+    }
+
+    /// Records a presentation at a vblank sequence of a CRTC.
+    ///
+    /// Recording the same sequence and time again, e.g. for the page flip
+    /// events of several planes completing on the same vblank, is ignored.
+    /// Only a sequence going backwards, or one which does not match the time
+    /// that passed, is reported as a discontinuity.
+    pub fn record(
+        &mut self,
+        crtc: crtc::Handle,
+        sequence: u64,
+        time: Duration,
+        flags: PresentationFlags,
+    ) -> PresentationFeedback {
+        let nominal = self.nominal.get(&crtc).copied();
+        let previous = self.crtcs.get(&crtc).copied();
+
+        let mut missed_frames = 0;
+        let mut discontinuity = false;
+        let mut refresh = previous.and_then(|timing| timing.refresh).or(nominal);
+
+        if let Some(previous) = previous {
+            if (previous.sequence, previous.time) == (sequence, time) {
+                return PresentationFeedback {
+                    crtc,
+                    sequence,
+                    time,
+                    refresh,
+                    flags,
+                    missed_frames,
+                    discontinuity,
+                };
+            }
+
+            let frames = sequence.checked_sub(previous.sequence).filter(|n| *n > 0);
+            let elapsed = time.checked_sub(previous.time);
+            match (frames, elapsed) {
+                (Some(frames), Some(elapsed)) if consistent(frames, elapsed, refresh) => {
+                    missed_frames = frames - 1;
+                    let sample = Duration::from_nanos((elapsed.as_nanos() / frames as u128) as u64);
+                    refresh = Some(match refresh {
+                        // Exponential moving average to smooth out jitter
+                        Some(refresh) => (refresh * 7 + sample) / 8,
+                        None => sample,
+                    });
+                }
+                _ => discontinuity = true,
+            }
+        }
+
+        self.crtcs.insert(
+            crtc,
+            CrtcTiming {
+                sequence,
+                time,
+                refresh,
+            },
+        );
+
+        PresentationFeedback {
+            crtc,
+            sequence,
+            time,
+            refresh,
+            flags,
+            missed_frames,
+            discontinuity,
+        }
+    }
+
+    /// Returns the estimated refresh interval of a CRTC.
+    pub fn refresh(&self, crtc: crtc::Handle) -> Option<Duration> {
+        self.crtcs
+            .get(&crtc)
+            .and_then(|timing| timing.refresh)
+            .or_else(|| self.nominal.get(&crtc).copied())
+    }
+
+    /// Returns the sequence and time of the last recorded vblank of a CRTC.
+    pub fn last_vblank(&self, crtc: crtc::Handle) -> Option<(u64, Duration)> {
+        self.crtcs
+            .get(&crtc)
+            .map(|timing| (timing.sequence, timing.time))
+    }
+
+    /// Predicts the sequence and time of the first vblank of a CRTC after
+    /// `now`.
+    pub fn predict(&self, crtc: crtc::Handle, now: Duration) -> Option<(u64, Duration)> {
+        let timing = self.crtcs.get(&crtc)?;
+        let refresh = timing.refresh.filter(|refresh| !refresh.is_zero())?;
+
+        let elapsed = now.saturating_sub(timing.time);
+        let frames = (elapsed.as_nanos() / refresh.as_nanos()) as u64 + 1;
+        let time = timing.time + Duration::from_nanos((refresh.as_nanos() * frames as u128) as u64);
+
+        Some((timing.sequence + frames, time))
+    }
+
+    /// Extends a 32 bit frame counter to the 64 bit sequence closest to the
+    /// last one recorded.
+    fn extend_sequence(&self, crtc: crtc::Handle, frame: u32) -> u64 {
+        let last = match self.crtcs.get(&crtc) {
+            Some(timing) => timing.sequence,
+            None => return frame as u64,
+        };
+
+        let delta = frame.wrapping_sub(last as u32) as i32;
+        let sequence = if delta >= 0 {
+            last.checked_add(delta as u64)
+        } else {
+            last.checked_sub(delta.unsigned_abs() as u64)
+        };
+        sequence.unwrap_or(frame as u64)
+    }
+}
+
+/// Checks whether a number of frames plausibly passed in the elapsed time.
+fn consistent(frames: u64, elapsed: Duration, refresh: Option<Duration>) -> bool {
+    let refresh = match refresh {
+        Some(refresh) if !refresh.is_zero() => refresh,
+        _ => return !elapsed.is_zero(),
+    };
+
+    let expected = (elapsed.as_nanos() + refresh.as_nanos() / 2) / refresh.as_nanos();
+    (expected as u64).abs_diff(frames) <= 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::from_u32;
+
+    const FRAME: Duration = Duration::from_micros(16_667);
+
+    fn record(tracker: &mut VblankTracker, sequence: u64) -> PresentationFeedback {
+        let crtc = from_u32(1).unwrap();
+        let time = FRAME * sequence as u32;
+        tracker.record(crtc, sequence, time, KERNEL_FLAGS)
+    }
+
+    #[test]
+    fn duplicates_are_ignored() {
+        let mut tracker = VblankTracker::new();
+        record(&mut tracker, 1);
+        record(&mut tracker, 2);
+
+        let feedback = record(&mut tracker, 2);
+        assert!(!feedback.discontinuity);
+        assert_eq!(feedback.missed_frames, 0);
+        assert_eq!(feedback.refresh, Some(FRAME));
+
+        let feedback = record(&mut tracker, 3);
+        assert!(!feedback.discontinuity);
+        assert_eq!(feedback.missed_frames, 0);
+    }
+
+    #[test]
+    fn discontinuities() {
+        let crtc = from_u32(1).unwrap();
+        let mut tracker = VblankTracker::new();
+        record(&mut tracker, 4);
+        record(&mut tracker, 5);
+
+        // Backwards
+        assert!(record(&mut tracker, 4).discontinuity);
+        // Same sequence at a different time
+        let feedback = tracker.record(crtc, 4, FRAME * 5, KERNEL_FLAGS);
+        assert!(feedback.discontinuity);
+        // Jumped ahead without the time passing
+        let feedback = tracker.record(crtc, 100, FRAME * 6, KERNEL_FLAGS);
+        assert!(feedback.discontinuity);
+    }
+}
@@ -0,0 +1,143 @@
+//! # User Data
+//!
+//! Page flips and atomic commits carry a `u64` of user data, which is returned
+//! in their [`PageFlipEvent`]s. A [`UserDataRegistry`] hands out such tokens
+//! for arbitrary payloads and returns the payloads once the events arrive.
+//!
+//! An atomic commit affecting several CRTCs produces one event per CRTC, so the
+//! registry tracks which CRTCs are still pending and only returns ownership of
+//! the payload with the last event.
+//!
+//! ```
+//! use drm::control::user_data::{Completion, UserDataRegistry};
+//! use drm::control::{from_u32, PageFlipEvent};
+//! use std::time::Duration;
+//!
+//! let (crtc_a, crtc_b) = (from_u32(10).unwrap(), from_u32(11).unwrap());
+//! let mut registry = UserDataRegistry::new();
+//! let token = registry.insert([crtc_a, crtc_b], "frame 1");
+//! // card.atomic_commit_with_user_data(flags, req, token)?;
+//!
+//! let event = |crtc| PageFlipEvent {
+//!     frame: 1,
+//!     duration: Duration::ZERO,
+//!     crtc,
+//!     user_data: token,
+//! };
+//! assert!(matches!(
+//!     registry.handle_event(&event(crtc_a)),
+//!     Some(Completion::Pending(&"frame 1"))
+//! ));
+//! assert!(matches!(
+//!     registry.handle_event(&event(crtc_b)),
+//!     Some(Completion::Complete("frame 1"))
+//! ));
+//! assert!(registry.is_empty());
+//! ```
+
+use std::collections::HashMap;
+
+use crate::control::{crtc, PageFlipEvent};
+
+/// The first token handed out.
+///
+/// Zero is the user data of
+/// [`Device::atomic_commit()`](crate::control::Device::atomic_commit), so it
+/// is never a token. Tokens never reach the highest bit, which marks the user
+/// data of [`Device::page_flip()`](crate::control::Device::page_flip).
+const FIRST_TOKEN: u64 = 1;
+
+/// Maps user data tokens of page flips and atomic commits to payloads.
+#[derive(Debug, Clone)]
+pub struct UserDataRegistry<T> {
+    next_token: u64,
+    pending: HashMap<u64, Pending<T>>,
+}
+
+#[derive(Debug, Clone)]
+struct Pending<T> {
+    crtcs: Vec<crtc::Handle>,
+    payload: T,
+}
+
+/// The payload of a [`PageFlipEvent`], see [`UserDataRegistry::handle_event()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Completion<'a, T> {
+    /// Events of other CRTCs are still pending
+    Pending(&'a T),
+    /// This was the last pending event, the payload is removed from the registry
+    Complete(T),
+}
+
+impl<T> UserDataRegistry<T> {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        UserDataRegistry {
+            next_token: FIRST_TOKEN,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Registers a payload for a page flip or atomic commit, returning the
+    /// token to pass as its user data.
+    ///
+    /// `crtcs` are the CRTCs that will send a page flip event, e.g. every CRTC
+    /// affected by an atomic commit.
+    pub fn insert<I>(&mut self, crtcs: I, payload: T) -> u64
+    where
+        I: IntoIterator<Item = crtc::Handle>,
+    {
+        let token = self.next_token;
+        self.next_token += 1;
+
+        let mut crtcs: Vec<_> = crtcs.into_iter().collect();
+        crtcs.sort_unstable_by_key(|crtc| u32::from(*crtc));
+        crtcs.dedup();
+        self.pending.insert(token, Pending { crtcs, payload });
+
+        token
+    }
+
+    /// Returns the payload of a token.
+    pub fn get(&self, token: u64) -> Option<&T> {
+        self.pending.get(&token).map(|pending| &pending.payload)
+    }
+
+    /// Removes the payload of a token, e.g. because the commit failed.
+    pub fn remove(&mut self, token: u64) -> Option<T> {
+        self.pending.remove(&token).map(|pending| pending.payload)
+    }
+
+    /// Returns the payload of a page flip event.
+    ///
+    /// Returns `None` for events that do not belong to a registered token, or
+    /// that come from a CRTC that was not registered or already handled.
+    pub fn handle_event(&mut self, event: &PageFlipEvent) -> Option<Completion<'_, T>> {
+        let token = event.user_data;
+        let pending = self.pending.get_mut(&token)?;
+        let index = pending.crtcs.iter().position(|crtc| *crtc == event.crtc)?;
+        pending.crtcs.remove(index);
+
+        if pending.crtcs.is_empty() {
+            self.remove(token).map(Completion::Complete)
+        } else {
+            self.get(token).map(Completion::Pending)
+        }
+    }
+
+    /// Returns the number of pending payloads.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns whether there are no pending payloads.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<T> Default for UserDataRegistry<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,316 @@
+//! # Device
+//!
+//! A ready-made owned device type.
+//!
+//! [`OwnedDevice`] wraps an open device node and implements both
+//! [`crate::Device`] and [`crate::control::Device`], so there is no need to
+//! write a wrapper type. Since these traits are also implemented for `&T` and
+//! [`Arc<T>`](std::sync::Arc), a single device can be shared between several
+//! subsystems.
+
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+
+use drm_ffi::transport::{self, Transport};
+
+use rustix::fs::{Mode, OFlags};
+
+use crate::node::{CreateDrmNodeError, DeviceNodes, DrmNode, NodeType};
+
+/// Options used to open a device node.
+///
+/// By default nodes are opened for reading and writing with `O_CLOEXEC` set.
+///
+/// # Example
+///
+/// ```no_run
+/// use drm::device::OpenOptions;
+///
+/// let device = OpenOptions::new()
+///     .nonblock(true)
+///     .open("/dev/dri/card0")
+///     .unwrap();
+/// ```
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    cloexec: bool,
+    nonblock: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions {
+            read: true,
+            write: true,
+            cloexec: true,
+            nonblock: false,
+        }
+    }
+}
+
+impl OpenOptions {
+    /// Creates the default set of options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the node is opened for reading.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Sets whether the node is opened for writing.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Sets whether the file descriptor is closed on `exec`.
+    pub fn cloexec(&mut self, cloexec: bool) -> &mut Self {
+        self.cloexec = cloexec;
+        self
+    }
+
+    /// Sets whether the file descriptor is non-blocking.
+    ///
+    /// This affects reading events, see
+    /// [`crate::control::Device::receive_events`].
+    pub fn nonblock(&mut self, nonblock: bool) -> &mut Self {
+        self.nonblock = nonblock;
+        self
+    }
+
+    fn flags(&self) -> OFlags {
+        let mut flags = match (self.read, self.write) {
+            (true, true) => OFlags::RDWR,
+            (false, true) => OFlags::WRONLY,
+            _ => OFlags::RDONLY,
+        };
+        if self.cloexec {
+            flags |= OFlags::CLOEXEC;
+        }
+        if self.nonblock {
+            flags |= OFlags::NONBLOCK;
+        }
+        flags
+    }
+
+    /// Opens the device node at the given path.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<OwnedDevice> {
+        let fd = rustix::fs::open(path.as_ref(), self.flags(), Mode::empty())?;
+        Ok(OwnedDevice::from(fd))
+    }
+
+    /// Opens the given node.
+    pub fn open_node(&self, node: &DrmNode) -> io::Result<OwnedDevice> {
+        let path = node
+            .dev_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "device node not found"))?;
+        self.open(path)
+    }
+
+    /// Opens the node of the given type of an enumerated device.
+    pub fn open_device(&self, device: &DeviceNodes, ty: NodeType) -> io::Result<OwnedDevice> {
+        let path = device
+            .path(ty)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "device node not found"))?;
+        self.open(path)
+    }
+}
+
+/// An open DRM device node.
+///
+/// # Example
+///
+/// ```no_run
+/// use drm::control::Device as ControlDevice;
+/// use drm::device::OwnedDevice;
+/// use std::sync::Arc;
+///
+/// let device = Arc::new(OwnedDevice::open("/dev/dri/card0").unwrap());
+///
+/// // Both the device and shared handles to it implement the device traits.
+/// let resources = device.resource_handles().unwrap();
+/// let shared = device.clone();
+/// std::thread::spawn(move || shared.receive_events());
+/// ```
+#[derive(Debug)]
+pub struct OwnedDevice {
+    fd: OwnedFd,
+    /// Whether the transport of the fd was registered by this device, and has
+    /// to be unregistered with it
+    owns_transport: bool,
+}
+
+impl OwnedDevice {
+    /// Opens the device node at the given path with the default
+    /// [`OpenOptions`].
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<OwnedDevice> {
+        OpenOptions::new().open(path)
+    }
+
+    /// Opens the given node with the default [`OpenOptions`].
+    pub fn open_node(node: &DrmNode) -> io::Result<OwnedDevice> {
+        OpenOptions::new().open_node(node)
+    }
+
+    /// Returns the node this device was opened from.
+    pub fn node(&self) -> Result<DrmNode, CreateDrmNodeError> {
+        DrmNode::from_file(self)
+    }
+
+    /// Wraps a file descriptor, answering all ioctls issued on it with the
+    /// given transport instead of the kernel.
+    ///
+    /// The transport stays registered for as long as the device is open. This
+    /// allows running code built on the device traits against a fake device,
+    /// see [`drm_ffi::transport`] for details.
+    ///
+    /// Devices created from a file descriptor in any other way leave the
+    /// transports registered for it alone.
+    ///
+    /// Fails with [`io::ErrorKind::AlreadyExists`] if another transport is
+    /// registered for the file descriptor already.
+    pub fn with_transport(fd: OwnedFd, transport: Arc<dyn Transport>) -> io::Result<OwnedDevice> {
+        transport::try_register(fd.as_fd(), transport)?;
+        Ok(OwnedDevice {
+            fd,
+            owns_transport: true,
+        })
+    }
+
+    /// Returns the transport answering the ioctls of this device, if any.
+    pub fn transport(&self) -> Option<Arc<dyn Transport>> {
+        transport::get(self.as_fd())
+    }
+
+    /// Creates a new device sharing the same open file description.
+    ///
+    /// If this device was created with [`OwnedDevice::with_transport`], the
+    /// transport is registered for the new file descriptor as well.
+    pub fn try_clone(&self) -> io::Result<OwnedDevice> {
+        let fd = self.fd.try_clone()?;
+        match self.transport().filter(|_| self.owns_transport) {
+            Some(transport) => OwnedDevice::with_transport(fd, transport),
+            None => Ok(OwnedDevice::from(fd)),
+        }
+    }
+}
+
+impl Drop for OwnedDevice {
+    fn drop(&mut self) {
+        if self.owns_transport {
+            transport::unregister(self.fd.as_fd());
+        }
+    }
+}
+
+impl AsFd for OwnedDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for OwnedDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<OwnedFd> for OwnedDevice {
+    fn from(fd: OwnedFd) -> Self {
+        OwnedDevice {
+            fd,
+            owns_transport: false,
+        }
+    }
+}
+
+impl From<OwnedDevice> for OwnedFd {
+    /// Unwraps the file descriptor, unregistering the transport if it was
+    /// registered by [`OwnedDevice::with_transport`].
+    fn from(device: OwnedDevice) -> Self {
+        let device = ManuallyDrop::new(device);
+        if device.owns_transport {
+            transport::unregister(device.fd.as_fd());
+        }
+        // SAFETY: `device` is never dropped, so the fd is only owned once.
+        unsafe { ptr::read(&device.fd) }
+    }
+}
+
+impl crate::Device for OwnedDevice {}
+impl crate::control::Device for OwnedDevice {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::c_void;
+
+    struct Passthrough;
+
+    impl Transport for Passthrough {
+        unsafe fn ioctl(
+            &self,
+            _: BorrowedFd<'_>,
+            _: u32,
+            _: *mut c_void,
+        ) -> Option<io::Result<()>> {
+            None
+        }
+    }
+
+    fn null() -> OwnedFd {
+        std::fs::File::open("/dev/null").unwrap().into()
+    }
+
+    #[test]
+    fn external_transport_survives_device() {
+        let fd = null();
+        let raw = fd.as_raw_fd();
+        transport::register(fd.as_fd(), Arc::new(Passthrough));
+
+        let device = OwnedDevice::from(fd);
+        let fd = OwnedFd::from(device);
+        assert!(transport::get(fd.as_fd()).is_some());
+        assert_eq!(fd.as_raw_fd(), raw);
+
+        drop(OwnedDevice::from(fd.try_clone().unwrap()));
+        assert!(transport::get(fd.as_fd()).is_some());
+        transport::unregister(fd.as_fd());
+    }
+
+    #[test]
+    fn owned_transport_follows_clones() {
+        let device = OwnedDevice::with_transport(null(), Arc::new(Passthrough)).unwrap();
+        let clone = device.try_clone().unwrap();
+        assert!(clone.transport().is_some());
+
+        let raw = clone.as_raw_fd();
+        drop(clone);
+        assert!(device.transport().is_some());
+
+        // The number of the closed clone must not keep its transport
+        let reused = null();
+        if reused.as_raw_fd() == raw {
+            assert!(transport::get(reused.as_fd()).is_none());
+        }
+    }
+
+    #[test]
+    fn transports_are_not_replaced() {
+        let device = OwnedDevice::with_transport(null(), Arc::new(Passthrough)).unwrap();
+        let transport = device.transport().unwrap();
+
+        let err = transport::try_register(device.as_fd(), Arc::new(Passthrough)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(Arc::ptr_eq(&device.transport().unwrap(), &transport));
+    }
+}
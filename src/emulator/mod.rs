@@ -0,0 +1,458 @@
+//! # Emulator
+//!
+//! An in-process software KMS device.
+//!
+//! The emulator answers the ioctls of a device with a configurable set of
+//! connectors, encoders, CRTCs, planes and properties, which allows testing
+//! modesetting code end to end without access to `/dev/dri`. It is built on
+//! top of the [ioctl transport](drm_ffi::transport) and is available with the
+//! `emulator` feature.
+//!
+//! The following is supported:
+//!
+//! * Enumerating resources, connectors, encoders, CRTCs, planes and properties
+//! * Property blobs
+//! * Dumb buffers, which can be mapped like those of a real device and shared
+//!   by GEM name or as PRIME file descriptor
+//! * Adding and removing framebuffers
+//! * Legacy modesetting, page flips and plane updates
+//! * Atomic commits, including `TEST_ONLY` validation
+//! * Vblank, page flip and CRTC sequence events, readable with
+//!   [`receive_events`](crate::control::Device::receive_events)
+//! * The DRM Master lock, which can be revoked to emulate a session switch
+//!
+//! Time is emulated: every update completes on the next vblank of the CRTCs it
+//! affects, which happens immediately, and the timestamps of events follow the
+//! refresh rate of the current mode. This makes tests deterministic, but also
+//! means waiting for events is pointless, so the device is in non-blocking
+//! mode and reading without pending events fails with
+//! [`WouldBlock`](io::ErrorKind::WouldBlock). Like a real device, it can be
+//! polled for events by an event loop.
+//!
+//! Unlike the kernel, the emulator cannot keep events intact across reads, so
+//! buffers used to read events should be a multiple of 32 bytes, the size of
+//! the vblank and page flip events it emits.
+//!
+//! # Example
+//!
+//! ```
+//! use drm::control::{connector, encoder, Device as ControlDevice, PlaneType};
+//! use drm::emulator::{self, Emulator};
+//! use drm_fourcc::DrmFourcc;
+//!
+//! let mut builder = Emulator::builder();
+//! let crtc = builder.crtc();
+//! let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+//! let connector = builder.connector(
+//!     connector::Interface::HDMIA,
+//!     &[encoder],
+//!     &[emulator::mode(1920, 1080, 60)],
+//! );
+//! builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+//! let (device, emulator) = builder.build().unwrap();
+//!
+//! let resources = device.resource_handles().unwrap();
+//! assert_eq!(resources.connectors(), &[connector]);
+//!
+//! let info = device.get_connector(connector, false).unwrap();
+//! let mode = info.modes()[0];
+//! let buffer = device
+//!     .create_dumb_buffer((1920, 1080), DrmFourcc::Xrgb8888, 32)
+//!     .unwrap();
+//! let fb = device.add_framebuffer(&buffer, 24, 32).unwrap();
+//! device
+//!     .set_crtc(crtc, Some(fb), (0, 0), &[connector], Some(mode))
+//!     .unwrap();
+//!
+//! assert!(emulator.is_active(crtc));
+//! assert_eq!(emulator.crtc_mode(crtc), Some(mode));
+//! ```
+
+use std::ffi::c_void;
+use std::io;
+use std::os::unix::io::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use drm_ffi as ffi;
+use drm_ffi::transport::Transport;
+use drm_fourcc::DrmFourcc;
+use rustix::fs::MemfdFlags;
+
+use crate::control::{
+    connector, crtc, encoder, framebuffer, plane, property, Mode, PlaneType, RawResourceHandle,
+    ResourceHandle,
+};
+use crate::device::OwnedDevice;
+use crate::DriverCapability;
+
+mod state;
+#[cfg(test)]
+mod tests;
+
+use self::state::{Files, State};
+
+/// The type of a property of the emulated device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyKind {
+    /// An unsigned value within an inclusive range
+    Range(u64, u64),
+    /// A signed value within an inclusive range
+    SignedRange(i64, i64),
+    /// One of a set of named values
+    Enum(Vec<(u64, String)>),
+    /// A combination of named bits, given as their bit index
+    Bitmask(Vec<(u64, String)>),
+    /// The ID of a property blob
+    Blob,
+    /// The ID of an object of the given type, e.g. [`crtc::Handle::FFI_TYPE`]
+    Object(u32),
+}
+
+/// Configures the resources of an emulated device.
+pub struct Builder {
+    state: State,
+}
+
+impl Builder {
+    /// Adds a CRTC, with a gamma ramp of 256 entries.
+    pub fn crtc(&mut self) -> crtc::Handle {
+        handle(self.state.add_crtc())
+    }
+
+    /// Adds an encoder which can drive the given CRTCs.
+    pub fn encoder(&mut self, kind: encoder::Kind, crtcs: &[crtc::Handle]) -> encoder::Handle {
+        let possible_crtcs = self.crtc_mask(crtcs);
+        handle(self.state.add_encoder(kind.into(), possible_crtcs))
+    }
+
+    /// Allows the given encoders to be driven by the same CRTC at the same
+    /// time. Encoders can only clone themselves by default.
+    pub fn encoder_clones(&mut self, encoders: &[encoder::Handle]) {
+        let mask = self
+            .state
+            .encoders
+            .iter()
+            .enumerate()
+            .filter(|(_, encoder)| encoders.iter().any(|h| u32::from(*h) == encoder.id))
+            .fold(0, |mask, (i, _)| mask | state::bit(i));
+        for (i, encoder) in self.state.encoders.iter_mut().enumerate() {
+            if mask & state::bit(i) != 0 {
+                encoder.possible_clones |= mask;
+            }
+        }
+    }
+
+    /// Adds a connected connector, reachable through the given encoders and
+    /// offering the given modes.
+    pub fn connector(
+        &mut self,
+        interface: connector::Interface,
+        encoders: &[encoder::Handle],
+        modes: &[Mode],
+    ) -> connector::Handle {
+        let encoders = encoders.iter().map(|e| (*e).into()).collect();
+        let modes = modes.iter().map(|m| (*m).into()).collect();
+        handle(self.state.add_connector(interface.into(), encoders, modes))
+    }
+
+    /// Sets the physical size of a connector's display in millimeters.
+    pub fn connector_size(&mut self, connector: connector::Handle, size: (u32, u32)) {
+        if let Some(c) = self
+            .state
+            .connectors
+            .iter_mut()
+            .find(|c| c.id == u32::from(connector))
+        {
+            c.size = size;
+        }
+    }
+
+    /// Overrides the value of a driver capability, or makes it unknown to the
+    /// device with `None`, like it is to older kernels.
+    pub fn driver_capability(&mut self, cap: DriverCapability, value: Option<u64>) {
+        self.state.capabilities.insert(cap as u64, value);
+    }
+
+    /// Adds a plane which can be used with the given CRTCs and formats.
+    pub fn plane(
+        &mut self,
+        kind: PlaneType,
+        crtcs: &[crtc::Handle],
+        formats: &[DrmFourcc],
+    ) -> plane::Handle {
+        let possible_crtcs = self.crtc_mask(crtcs);
+        let formats = formats.iter().map(|f| *f as u32).collect();
+        handle(self.state.add_plane(kind, possible_crtcs, formats))
+    }
+
+    /// Attaches a property to an object.
+    ///
+    /// Properties with the same name and type share their ID, like they do on
+    /// real devices.
+    pub fn property<H: ResourceHandle>(
+        &mut self,
+        object: H,
+        name: &str,
+        kind: PropertyKind,
+        value: u64,
+    ) -> property::Handle {
+        self.add_property(object.into(), name, kind, 0, value)
+    }
+
+    /// Attaches an immutable property to an object.
+    pub fn immutable_property<H: ResourceHandle>(
+        &mut self,
+        object: H,
+        name: &str,
+        kind: PropertyKind,
+        value: u64,
+    ) -> property::Handle {
+        let flags = ffi::DRM_MODE_PROP_IMMUTABLE;
+        self.add_property(object.into(), name, kind, flags, value)
+    }
+
+    /// Creates a property blob, e.g. for the value of an immutable blob
+    /// property, returning its ID.
+    pub fn blob(&mut self, data: &[u8]) -> u64 {
+        self.state.create_blob(data.to_vec()).into()
+    }
+
+    fn add_property(
+        &mut self,
+        object: RawResourceHandle,
+        name: &str,
+        kind: PropertyKind,
+        flags: u32,
+        value: u64,
+    ) -> property::Handle {
+        let (ty, values, enums) = match kind {
+            PropertyKind::Range(min, max) => (ffi::DRM_MODE_PROP_RANGE, vec![min, max], Vec::new()),
+            PropertyKind::SignedRange(min, max) => (
+                ffi::DRM_MODE_PROP_SIGNED_RANGE,
+                vec![min as u64, max as u64],
+                Vec::new(),
+            ),
+            PropertyKind::Enum(enums) => (ffi::DRM_MODE_PROP_ENUM, Vec::new(), enums),
+            PropertyKind::Bitmask(bits) => (ffi::DRM_MODE_PROP_BITMASK, Vec::new(), bits),
+            PropertyKind::Blob => (ffi::DRM_MODE_PROP_BLOB, Vec::new(), Vec::new()),
+            PropertyKind::Object(ty) => (ffi::DRM_MODE_PROP_OBJECT, vec![ty as u64], Vec::new()),
+        };
+
+        let id = self.state.create_property(name, ty | flags, values, enums);
+        self.state.attach_property(object.get(), id, value);
+        handle(id)
+    }
+
+    /// Creates the device.
+    ///
+    /// The returned [`Emulator`] can be used to inspect and change the state
+    /// of the device while it is in use.
+    pub fn build(self) -> io::Result<(OwnedDevice, Emulator)> {
+        let pool = rustix::fs::memfd_create("drm-emulator", MemfdFlags::CLOEXEC)?;
+        rustix::fs::ftruncate(&pool, state::POOL_SIZE)?;
+        // Events are sent through a socket, which unlike the memfd can be
+        // polled by event loops
+        let (fd, events) = UnixStream::pair()?;
+        fd.set_nonblocking(true)?;
+        events.set_nonblocking(true)?;
+
+        let emulator = Emulator {
+            state: Arc::new(Mutex::new(self.state)),
+        };
+        let transport = Arc::new(EmulatorTransport {
+            emulator: emulator.clone(),
+            files: Files { pool, events },
+        });
+        Ok((OwnedDevice::with_transport(fd.into(), transport)?, emulator))
+    }
+
+    fn crtc_mask(&self, crtcs: &[crtc::Handle]) -> u32 {
+        self.state
+            .crtcs
+            .iter()
+            .enumerate()
+            .filter(|(_, crtc)| crtcs.iter().any(|h| u32::from(*h) == crtc.id))
+            .fold(0, |mask, (i, _)| mask | state::bit(i))
+    }
+}
+
+/// A handle to the state of an emulated device.
+#[derive(Clone)]
+pub struct Emulator {
+    state: Arc<Mutex<State>>,
+}
+
+impl Emulator {
+    /// Starts configuring a new emulated device.
+    pub fn builder() -> Builder {
+        Builder {
+            state: State::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Changes the connection state of a connector, e.g. to emulate a hotplug.
+    pub fn set_connector_state(&self, connector: connector::Handle, state: connector::State) {
+        if let Some(c) = self
+            .state()
+            .connectors
+            .iter_mut()
+            .find(|c| c.id == u32::from(connector))
+        {
+            c.connection = state.into();
+        }
+    }
+
+    /// Hands the DRM Master lock to another client, e.g. to emulate a session
+    /// switch.
+    ///
+    /// Until [`Emulator::restore_master`] is called, acquiring the lock fails
+    /// with `EBUSY`.
+    ///
+    /// ```
+    /// use drm::control::Device as ControlDevice;
+    /// use drm::emulator::Emulator;
+    /// use drm::{Device, MasterLost};
+    ///
+    /// let mut builder = Emulator::builder();
+    /// let crtc = builder.crtc();
+    /// let (device, emulator) = builder.build().unwrap();
+    /// assert!(device.is_master());
+    ///
+    /// emulator.revoke_master();
+    /// assert!(!device.is_master());
+    /// let err = device.set_crtc(crtc, None, (0, 0), &[], None).unwrap_err();
+    /// assert!(MasterLost::is(&err));
+    /// assert!(device.lock_master().is_err());
+    ///
+    /// emulator.restore_master();
+    /// let master = device.lock_master().unwrap();
+    /// assert!(master.is_master());
+    /// drop(master);
+    /// assert!(!device.is_master());
+    /// ```
+    pub fn revoke_master(&self) {
+        let mut state = self.state();
+        state.master = false;
+        state.foreign_master = true;
+    }
+
+    /// Lets the other client drop the DRM Master lock again, so that it can be
+    /// reacquired.
+    pub fn restore_master(&self) {
+        self.state().foreign_master = false;
+    }
+
+    /// Returns whether a CRTC is active.
+    pub fn is_active(&self, crtc: crtc::Handle) -> bool {
+        let state = self.state();
+        state.value(crtc.into(), state.std.active) == Some(1)
+    }
+
+    /// Returns the mode a CRTC is configured with.
+    pub fn crtc_mode(&self, crtc: crtc::Handle) -> Option<Mode> {
+        let state = self.state();
+        state.mode(&state.values, crtc.into()).map(Mode::from)
+    }
+
+    /// Returns the CRTC a connector or plane is attached to.
+    pub fn crtc_of<H: ResourceHandle>(&self, object: H) -> Option<crtc::Handle> {
+        let state = self.state();
+        let object: RawResourceHandle = object.into();
+        crate::control::from_u32(state.value(object.get(), state.std.crtc_id)? as u32)
+    }
+
+    /// Returns the framebuffer a plane scans out.
+    pub fn framebuffer(&self, plane: plane::Handle) -> Option<framebuffer::Handle> {
+        let state = self.state();
+        crate::control::from_u32(state.value(plane.into(), state.std.fb_id)? as u32)
+    }
+
+    /// Returns the current value of a property.
+    pub fn property_value<H: ResourceHandle>(
+        &self,
+        object: H,
+        property: property::Handle,
+    ) -> Option<u64> {
+        let object: RawResourceHandle = object.into();
+        self.state().value(object.get(), property.into())
+    }
+
+    /// Returns the number of vblanks a CRTC has gone through.
+    pub fn vblank_count(&self, crtc: crtc::Handle) -> u64 {
+        let state = self.state();
+        state
+            .crtcs
+            .iter()
+            .find(|c| c.id == u32::from(crtc))
+            .map_or(0, |c| c.sequence)
+    }
+}
+
+struct EmulatorTransport {
+    emulator: Emulator,
+    files: Files,
+}
+
+impl Transport for EmulatorTransport {
+    unsafe fn ioctl(
+        &self,
+        _fd: BorrowedFd<'_>,
+        request: u32,
+        arg: *mut c_void,
+    ) -> Option<io::Result<()>> {
+        Some(self.emulator.state().ioctl(&self.files, request, arg))
+    }
+
+    fn map_fd(&self, _fd: BorrowedFd<'_>) -> Option<BorrowedFd<'_>> {
+        Some(self.files.pool.as_fd())
+    }
+}
+
+/// Creates a mode with the given resolution and refresh rate, using reduced
+/// blanking timings.
+///
+/// # Panics
+///
+/// Panics if the blanking intervals do not fit into the 16 bit timings of a
+/// mode, i.e. if `width` is above 65375 or `height` above 65505.
+pub fn mode(width: u16, height: u16, refresh: u32) -> Mode {
+    let timing = |size: u16, offset: u16| {
+        size.checked_add(offset)
+            .expect("mode size exceeds the range of its timings")
+    };
+    let htotal = timing(width, 160);
+    let vtotal = timing(height, 30);
+    let clock = htotal as u64 * vtotal as u64 * refresh as u64 / 1000;
+
+    let mut name = [0; 32];
+    for (dst, src) in name.iter_mut().zip(format!("{}x{}", width, height).bytes()) {
+        *dst = src as _;
+    }
+
+    Mode::from(ffi::drm_mode_modeinfo {
+        clock: u32::try_from(clock).unwrap_or(u32::MAX),
+        hdisplay: width,
+        hsync_start: timing(width, 48),
+        hsync_end: timing(width, 80),
+        htotal,
+        hskew: 0,
+        vdisplay: height,
+        vsync_start: timing(height, 3),
+        vsync_end: timing(height, 8),
+        vtotal,
+        vscan: 0,
+        vrefresh: refresh,
+        flags: ffi::DRM_MODE_FLAG_PHSYNC | ffi::DRM_MODE_FLAG_NVSYNC,
+        type_: ffi::DRM_MODE_TYPE_DRIVER,
+        name,
+    })
+}
+
+fn handle<T: From<RawResourceHandle>>(id: u32) -> T {
+    crate::control::from_u32(id).expect("object IDs are never zero")
+}
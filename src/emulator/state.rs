@@ -0,0 +1,1777 @@
+//! Emulated device state and ioctl handling.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::{c_char, c_void};
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::io::{BorrowedFd, IntoRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+
+use drm_ffi as ffi;
+use drm_ffi::transport;
+use drm_fourcc::DrmFourcc;
+use rustix::fs::{FallocateFlags, MemfdFlags};
+use rustix::io::Errno;
+
+use crate::control::{connector, PlaneType};
+
+/// Size of the file that backs dumb buffers.
+pub(super) const POOL_SIZE: u64 = 1 << 30;
+
+const PAGE_SIZE: u64 = 4096;
+const DEFAULT_FRAME_NS: u64 = 16_666_667;
+/// Ioctls that fail with `EACCES` without the DRM Master lock.
+const MASTER_ONLY: &[u8] = &[
+    0x07, 0x11, 0xA2, 0xA3, 0xA5, 0xAB, 0xB0, 0xB1, 0xB7, 0xBA, 0xBB, 0xBC, 0xC6, 0xC7, 0xC8, 0xC9,
+];
+
+/// The files behind an emulated device.
+pub(super) struct Files {
+    /// Backs the memory of dumb buffers, at the offsets returned by
+    /// `MAP_DUMB`
+    pub pool: OwnedFd,
+    /// The peer of the socket returned as the device, events are written to
+    /// it
+    pub events: UnixStream,
+}
+
+/// Property values of all objects, in the order they were attached.
+type Values = HashMap<u32, Vec<(u32, u64)>>;
+
+pub(super) struct Property {
+    pub id: u32,
+    pub name: String,
+    pub flags: u32,
+    pub values: Vec<u64>,
+    pub enums: Vec<(u64, String)>,
+}
+
+impl Property {
+    fn is_atomic(&self) -> bool {
+        self.flags & ffi::DRM_MODE_PROP_ATOMIC != 0
+    }
+
+    fn is_immutable(&self) -> bool {
+        self.flags & ffi::DRM_MODE_PROP_IMMUTABLE != 0
+    }
+
+    fn accepts(&self, state: &State, value: u64) -> bool {
+        let legacy = self.flags & ffi::DRM_MODE_PROP_LEGACY_TYPE;
+        let extended = self.flags & ffi::DRM_MODE_PROP_EXTENDED_TYPE;
+
+        if legacy & ffi::DRM_MODE_PROP_RANGE != 0 {
+            (self.values[0]..=self.values[1]).contains(&value)
+        } else if legacy & ffi::DRM_MODE_PROP_ENUM != 0 {
+            self.enums.iter().any(|(v, _)| *v == value)
+        } else if legacy & ffi::DRM_MODE_PROP_BITMASK != 0 {
+            let mask = self.enums.iter().fold(0u64, |mask, (bit, _)| {
+                mask | 1u64.checked_shl(*bit as u32).unwrap_or(0)
+            });
+            value & !mask == 0
+        } else if legacy & ffi::DRM_MODE_PROP_BLOB != 0 {
+            value == 0 || state.blobs.contains_key(&(value as u32))
+        } else if extended == ffi::DRM_MODE_PROP_SIGNED_RANGE {
+            (self.values[0] as i64..=self.values[1] as i64).contains(&(value as i64))
+        } else if extended == ffi::DRM_MODE_PROP_OBJECT {
+            value == 0 || state.object_type(value as u32) == Some(self.values[0] as u32)
+        } else {
+            false
+        }
+    }
+}
+
+pub(super) struct Crtc {
+    pub id: u32,
+    pub gamma: Vec<u16>,
+    pub sequence: u64,
+    pub time_ns: u64,
+}
+
+pub(super) struct Encoder {
+    pub id: u32,
+    pub kind: u32,
+    pub possible_crtcs: u32,
+    pub possible_clones: u32,
+}
+
+pub(super) struct Connector {
+    pub id: u32,
+    pub interface: u32,
+    pub interface_id: u32,
+    pub connection: u32,
+    pub encoders: Vec<u32>,
+    pub modes: Vec<ffi::drm_mode_modeinfo>,
+    pub size: (u32, u32),
+}
+
+pub(super) struct Plane {
+    pub id: u32,
+    pub kind: u32,
+    pub possible_crtcs: u32,
+    pub formats: Vec<u32>,
+}
+
+struct Framebuffer {
+    cmd: ffi::drm_mode_fb_cmd2,
+    depth: u32,
+    bpp: u32,
+    /// Closed framebuffers stay alive while planes scan them out, but can no
+    /// longer be looked up or referenced
+    closed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct DumbBuffer {
+    offset: u64,
+    size: u64,
+}
+
+/// A dumb buffer exported as PRIME file descriptor.
+///
+/// The file only identifies the buffer, its contents are not shared.
+struct Export {
+    file: OwnedFd,
+    inode: (u64, u64),
+    buffer: DumbBuffer,
+}
+
+/// IDs of the properties the emulator itself interprets.
+pub(super) struct StandardProperties {
+    pub active: u32,
+    pub mode_id: u32,
+    pub crtc_id: u32,
+    pub fb_id: u32,
+    pub plane_type: u32,
+    pub src: [u32; 4],
+    pub dst: [u32; 4],
+}
+
+/// A vblank or page flip event to be delivered.
+struct PendingEvent {
+    kind: u32,
+    crtc: usize,
+    user_data: u64,
+}
+
+pub(super) struct State {
+    next_id: u32,
+    next_handle: u32,
+    pub crtcs: Vec<Crtc>,
+    pub encoders: Vec<Encoder>,
+    pub connectors: Vec<Connector>,
+    pub planes: Vec<Plane>,
+    pub properties: Vec<Property>,
+    pub values: Values,
+    pub std: StandardProperties,
+    framebuffers: BTreeMap<u32, Framebuffer>,
+    blobs: BTreeMap<u32, Vec<u8>>,
+    destroyed_blobs: HashSet<u32>,
+    dumb_buffers: BTreeMap<u32, DumbBuffer>,
+    exports: Vec<Export>,
+    /// Global names of buffers published with `GEM_FLINK`
+    names: BTreeMap<u32, DumbBuffer>,
+    next_name: u32,
+    /// Driver capabilities which differ from the defaults, `None` if unknown
+    pub capabilities: HashMap<u64, Option<u64>>,
+    pool_end: u64,
+    universal_planes: bool,
+    atomic: bool,
+    pub master: bool,
+    pub foreign_master: bool,
+}
+
+impl State {
+    pub fn new() -> State {
+        let mut state = State {
+            next_id: 1,
+            next_handle: 1,
+            crtcs: Vec::new(),
+            encoders: Vec::new(),
+            connectors: Vec::new(),
+            planes: Vec::new(),
+            properties: Vec::new(),
+            values: Values::new(),
+            std: StandardProperties {
+                active: 0,
+                mode_id: 0,
+                crtc_id: 0,
+                fb_id: 0,
+                plane_type: 0,
+                src: [0; 4],
+                dst: [0; 4],
+            },
+            framebuffers: BTreeMap::new(),
+            blobs: BTreeMap::new(),
+            destroyed_blobs: HashSet::new(),
+            dumb_buffers: BTreeMap::new(),
+            exports: Vec::new(),
+            names: BTreeMap::new(),
+            next_name: 1,
+            capabilities: HashMap::new(),
+            pool_end: 0,
+            universal_planes: false,
+            atomic: false,
+            master: true,
+            foreign_master: false,
+        };
+
+        let atomic = ffi::DRM_MODE_PROP_ATOMIC;
+        let range = |max: u64| (atomic | ffi::DRM_MODE_PROP_RANGE, vec![0, max]);
+        let signed = (
+            atomic | ffi::DRM_MODE_PROP_SIGNED_RANGE,
+            vec![i32::MIN as i64 as u64, i32::MAX as u64],
+        );
+        let object = |ty: u32| (atomic | ffi::DRM_MODE_PROP_OBJECT, vec![ty as u64]);
+
+        let (flags, values) = range(1);
+        let std = StandardProperties {
+            active: state.create_property("ACTIVE", flags, values, Vec::new()),
+            mode_id: state.create_property(
+                "MODE_ID",
+                atomic | ffi::DRM_MODE_PROP_BLOB,
+                Vec::new(),
+                Vec::new(),
+            ),
+            crtc_id: {
+                let (flags, values) = object(ffi::DRM_MODE_OBJECT_CRTC);
+                state.create_property("CRTC_ID", flags, values, Vec::new())
+            },
+            fb_id: {
+                let (flags, values) = object(ffi::DRM_MODE_OBJECT_FB);
+                state.create_property("FB_ID", flags, values, Vec::new())
+            },
+            plane_type: state.create_property(
+                "type",
+                ffi::DRM_MODE_PROP_IMMUTABLE | ffi::DRM_MODE_PROP_ENUM,
+                Vec::new(),
+                vec![
+                    (ffi::DRM_PLANE_TYPE_OVERLAY as u64, "Overlay".into()),
+                    (ffi::DRM_PLANE_TYPE_PRIMARY as u64, "Primary".into()),
+                    (ffi::DRM_PLANE_TYPE_CURSOR as u64, "Cursor".into()),
+                ],
+            ),
+            src: ["SRC_X", "SRC_Y", "SRC_W", "SRC_H"].map(|name| {
+                let (flags, values) = range(u32::MAX as u64);
+                state.create_property(name, flags, values, Vec::new())
+            }),
+            dst: ["CRTC_X", "CRTC_Y", "CRTC_W", "CRTC_H"].map(|name| {
+                let (flags, values) = if name.ends_with('X') || name.ends_with('Y') {
+                    signed.clone()
+                } else {
+                    range(i32::MAX as u64)
+                };
+                state.create_property(name, flags, values, Vec::new())
+            }),
+        };
+        state.std = std;
+
+        state
+    }
+
+    fn alloc_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn create_property(
+        &mut self,
+        name: &str,
+        flags: u32,
+        values: Vec<u64>,
+        enums: Vec<(u64, String)>,
+    ) -> u32 {
+        if let Some(prop) = self
+            .properties
+            .iter()
+            .find(|p| p.name == name && p.flags == flags && p.values == values && p.enums == enums)
+        {
+            return prop.id;
+        }
+
+        let id = self.alloc_id();
+        self.properties.push(Property {
+            id,
+            name: name.to_owned(),
+            flags,
+            values,
+            enums,
+        });
+        id
+    }
+
+    pub fn attach_property(&mut self, object: u32, prop: u32, value: u64) {
+        let values = self.values.entry(object).or_default();
+        match values.iter_mut().find(|(id, _)| *id == prop) {
+            Some((_, v)) => *v = value,
+            None => values.push((prop, value)),
+        }
+    }
+
+    pub fn add_crtc(&mut self) -> u32 {
+        let id = self.alloc_id();
+        let ramp = (0..256u32).map(|i| (i * 0x101) as u16);
+        self.crtcs.push(Crtc {
+            id,
+            gamma: ramp.clone().chain(ramp.clone()).chain(ramp).collect(),
+            sequence: 0,
+            time_ns: 1_000_000_000,
+        });
+        self.values.insert(id, Vec::new());
+        self.attach_property(id, self.std.active, 0);
+        self.attach_property(id, self.std.mode_id, 0);
+        id
+    }
+
+    pub fn add_encoder(&mut self, kind: u32, possible_crtcs: u32) -> u32 {
+        let id = self.alloc_id();
+        self.encoders.push(Encoder {
+            id,
+            kind,
+            possible_crtcs,
+            possible_clones: bit(self.encoders.len()),
+        });
+        id
+    }
+
+    pub fn add_connector(
+        &mut self,
+        interface: u32,
+        encoders: Vec<u32>,
+        modes: Vec<ffi::drm_mode_modeinfo>,
+    ) -> u32 {
+        let id = self.alloc_id();
+        let interface_id = self
+            .connectors
+            .iter()
+            .filter(|c| c.interface == interface)
+            .count() as u32
+            + 1;
+        self.connectors.push(Connector {
+            id,
+            interface,
+            interface_id,
+            connection: u32::from(connector::State::Connected),
+            encoders,
+            modes,
+            size: (0, 0),
+        });
+        self.values.insert(id, Vec::new());
+        self.attach_property(id, self.std.crtc_id, 0);
+        id
+    }
+
+    pub fn add_plane(&mut self, kind: PlaneType, possible_crtcs: u32, formats: Vec<u32>) -> u32 {
+        let id = self.alloc_id();
+        self.planes.push(Plane {
+            id,
+            kind: kind as u32,
+            possible_crtcs,
+            formats,
+        });
+        self.values.insert(id, Vec::new());
+        self.attach_property(id, self.std.plane_type, kind as u64);
+        self.attach_property(id, self.std.fb_id, 0);
+        self.attach_property(id, self.std.crtc_id, 0);
+        for prop in self.std.src.into_iter().chain(self.std.dst) {
+            self.attach_property(id, prop, 0);
+        }
+        id
+    }
+
+    pub fn object_type(&self, id: u32) -> Option<u32> {
+        if self.crtcs.iter().any(|c| c.id == id) {
+            Some(ffi::DRM_MODE_OBJECT_CRTC)
+        } else if self.connectors.iter().any(|c| c.id == id) {
+            Some(ffi::DRM_MODE_OBJECT_CONNECTOR)
+        } else if self.encoders.iter().any(|e| e.id == id) {
+            Some(ffi::DRM_MODE_OBJECT_ENCODER)
+        } else if self.planes.iter().any(|p| p.id == id) {
+            Some(ffi::DRM_MODE_OBJECT_PLANE)
+        } else if self.framebuffer(id).is_some() {
+            Some(ffi::DRM_MODE_OBJECT_FB)
+        } else if self.blobs.contains_key(&id) {
+            Some(ffi::DRM_MODE_OBJECT_BLOB)
+        } else if self.properties.iter().any(|p| p.id == id) {
+            Some(ffi::DRM_MODE_OBJECT_PROPERTY)
+        } else {
+            None
+        }
+    }
+
+    /// Returns a framebuffer which has not been closed.
+    fn framebuffer(&self, id: u32) -> Option<&Framebuffer> {
+        self.framebuffers.get(&id).filter(|fb| !fb.closed)
+    }
+
+    fn property(&self, id: u32) -> Option<&Property> {
+        self.properties.iter().find(|p| p.id == id)
+    }
+
+    fn crtc_index(&self, id: u32) -> Option<usize> {
+        self.crtcs.iter().position(|c| c.id == id)
+    }
+
+    pub fn value(&self, object: u32, prop: u32) -> Option<u64> {
+        value_in(&self.values, object, prop)
+    }
+
+    pub fn mode(&self, values: &Values, crtc: u32) -> Option<ffi::drm_mode_modeinfo> {
+        let blob = self
+            .blobs
+            .get(&(get(values, crtc, self.std.mode_id) as u32))?;
+        (blob.len() == mem::size_of::<ffi::drm_mode_modeinfo>())
+            .then(|| unsafe { ptr::read_unaligned(blob.as_ptr() as *const ffi::drm_mode_modeinfo) })
+    }
+
+    fn primary_plane(&self, crtc: usize) -> Option<u32> {
+        self.planes
+            .iter()
+            .find(|p| p.kind == ffi::DRM_PLANE_TYPE_PRIMARY && p.possible_crtcs & bit(crtc) != 0)
+            .map(|p| p.id)
+    }
+
+    /// The encoder a connector is currently routed through.
+    fn connector_encoder(&self, values: &Values, connector: &Connector) -> u32 {
+        let crtc = get(values, connector.id, self.std.crtc_id) as u32;
+        let index = match self.crtc_index(crtc) {
+            Some(index) => index,
+            None => return 0,
+        };
+        connector
+            .encoders
+            .iter()
+            .copied()
+            .find(|id| {
+                self.encoders
+                    .iter()
+                    .any(|e| e.id == *id && e.possible_crtcs & bit(index) != 0)
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn create_blob(&mut self, data: Vec<u8>) -> u32 {
+        let id = self.alloc_id();
+        self.blobs.insert(id, data);
+        id
+    }
+
+    /// Drops destroyed blobs which are no longer referenced by any property.
+    fn collect_blobs(&mut self) {
+        let blob_props: Vec<u32> = self
+            .properties
+            .iter()
+            .filter(|p| p.flags & ffi::DRM_MODE_PROP_BLOB != 0)
+            .map(|p| p.id)
+            .collect();
+        let values = &self.values;
+        let blobs = &mut self.blobs;
+        self.destroyed_blobs.retain(|blob| {
+            let used = values
+                .values()
+                .flatten()
+                .any(|(prop, value)| blob_props.contains(prop) && *value == *blob as u64);
+            if !used {
+                blobs.remove(blob);
+            }
+            used
+        });
+    }
+
+    /// Drops closed framebuffers which are no longer scanned out by any plane.
+    fn collect_framebuffers(&mut self) {
+        let values = &self.values;
+        let (planes, fb_id) = (&self.planes, self.std.fb_id);
+        self.framebuffers.retain(|id, fb| {
+            !fb.closed
+                || planes
+                    .iter()
+                    .any(|plane| get(values, plane.id, fb_id) == *id as u64)
+        });
+    }
+
+    /// Validates a complete set of property values.
+    fn check(&self, values: &Values) -> io::Result<()> {
+        let std = &self.std;
+
+        for crtc in &self.crtcs {
+            let mode_id = get(values, crtc.id, std.mode_id);
+            let active = get(values, crtc.id, std.active) != 0;
+            let enabled = mode_id != 0;
+
+            if enabled && self.mode(values, crtc.id).is_none() {
+                return Err(Errno::INVAL.into());
+            }
+            if active && !enabled {
+                return Err(Errno::INVAL.into());
+            }
+
+            let has_connectors = self
+                .connectors
+                .iter()
+                .any(|c| get(values, c.id, std.crtc_id) == crtc.id as u64);
+            if enabled != has_connectors {
+                return Err(Errno::INVAL.into());
+            }
+        }
+
+        for plane in &self.planes {
+            let fb = get(values, plane.id, std.fb_id) as u32;
+            let crtc = get(values, plane.id, std.crtc_id) as u32;
+            if (fb == 0) != (crtc == 0) {
+                return Err(Errno::INVAL.into());
+            }
+            if crtc == 0 {
+                continue;
+            }
+
+            let index = self.crtc_index(crtc).ok_or(Errno::INVAL)?;
+            if plane.possible_crtcs & bit(index) == 0 || get(values, crtc, std.mode_id) == 0 {
+                return Err(Errno::INVAL.into());
+            }
+
+            let fb = self.framebuffers.get(&fb).ok_or(Errno::INVAL)?;
+            if !plane.formats.contains(&fb.cmd.pixel_format) {
+                return Err(Errno::INVAL.into());
+            }
+
+            let [x, y, w, h] = std.src.map(|prop| get(values, plane.id, prop));
+            if x + w > (fb.cmd.width as u64) << 16 || y + h > (fb.cmd.height as u64) << 16 {
+                return Err(Errno::NOSPC.into());
+            }
+        }
+
+        for connector in &self.connectors {
+            let crtc = get(values, connector.id, std.crtc_id) as u32;
+            if crtc != 0 && self.connector_encoder(values, connector) == 0 {
+                return Err(Errno::INVAL.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether going from the current to the given values requires a full
+    /// modeset.
+    fn needs_modeset(&self, values: &Values) -> bool {
+        let std = &self.std;
+        let crtcs = self.crtcs.iter().any(|crtc| {
+            get(&self.values, crtc.id, std.active) != get(values, crtc.id, std.active)
+                || self.mode(&self.values, crtc.id).map(ModeKey::from)
+                    != self.mode(values, crtc.id).map(ModeKey::from)
+        });
+        let connectors = self.connectors.iter().any(|connector| {
+            get(&self.values, connector.id, std.crtc_id) != get(values, connector.id, std.crtc_id)
+        });
+        crtcs || connectors
+    }
+
+    /// Returns the CRTCs whose state may differ between the current and the
+    /// given values.
+    fn affected_crtcs(&self, values: &Values, objects: &[u32]) -> Vec<usize> {
+        let mut crtcs = Vec::new();
+        for object in objects {
+            let mut ids = vec![*object];
+            for values in [&self.values, values] {
+                ids.push(get(values, *object, self.std.crtc_id) as u32);
+            }
+            for id in ids {
+                if let Some(index) = self.crtc_index(id) {
+                    if !crtcs.contains(&index) {
+                        crtcs.push(index);
+                    }
+                }
+            }
+        }
+        crtcs
+    }
+
+    /// Validates and applies new property values, completing the update on
+    /// the next vblank of every affected CRTC.
+    fn commit(
+        &mut self,
+        files: &Files,
+        values: Values,
+        objects: &[u32],
+        allow_modeset: bool,
+        event: Option<u64>,
+        test_only: bool,
+    ) -> io::Result<()> {
+        self.check(&values)?;
+        if !allow_modeset && self.needs_modeset(&values) {
+            return Err(Errno::INVAL.into());
+        }
+
+        let crtcs = self.affected_crtcs(&values, objects);
+        let active = |values: &Values, crtc: &Crtc| get(values, crtc.id, self.std.active) != 0;
+        let updated: Vec<usize> = crtcs
+            .into_iter()
+            .filter(|i| active(&self.values, &self.crtcs[*i]) || active(&values, &self.crtcs[*i]))
+            .collect();
+        if event.is_some() && updated.is_empty() {
+            return Err(Errno::INVAL.into());
+        }
+        if test_only {
+            return Ok(());
+        }
+
+        self.values = values;
+        self.collect_blobs();
+        self.collect_framebuffers();
+
+        let mut events = Vec::new();
+        for crtc in updated {
+            self.advance(crtc, 1);
+            if let Some(user_data) = event {
+                events.push(PendingEvent {
+                    kind: ffi::DRM_EVENT_FLIP_COMPLETE,
+                    crtc,
+                    user_data,
+                });
+            }
+        }
+        self.send_events(files, events)
+    }
+
+    /// Advances the virtual clock of a CRTC by a number of vblanks.
+    ///
+    /// The clock stops at the largest sequence and the largest timestamp the
+    /// kernel can report instead of overflowing.
+    pub fn advance(&mut self, crtc: usize, count: u64) {
+        let frame_ns = self
+            .mode(&self.values, self.crtcs[crtc].id)
+            .filter(|mode| mode.clock != 0)
+            .map(|mode| mode.htotal as u64 * mode.vtotal as u64 * 1_000_000 / mode.clock as u64)
+            .unwrap_or(DEFAULT_FRAME_NS);
+
+        let crtc = &mut self.crtcs[crtc];
+        crtc.sequence = crtc.sequence.saturating_add(count);
+        crtc.time_ns = crtc
+            .time_ns
+            .saturating_add(count.saturating_mul(frame_ns))
+            .min(i64::MAX as u64);
+    }
+
+    fn send_events(&mut self, files: &Files, events: Vec<PendingEvent>) -> io::Result<()> {
+        for event in events {
+            let crtc = &self.crtcs[event.crtc];
+            if event.kind == ffi::DRM_EVENT_CRTC_SEQUENCE {
+                let data = ffi::drm_event_crtc_sequence {
+                    base: ffi::drm_event {
+                        type_: event.kind,
+                        length: mem::size_of::<ffi::drm_event_crtc_sequence>() as u32,
+                    },
+                    user_data: event.user_data,
+                    time_ns: crtc.time_ns as i64,
+                    sequence: crtc.sequence,
+                };
+                self.write_event(files, &data)?;
+            } else {
+                let data = ffi::drm_event_vblank {
+                    base: ffi::drm_event {
+                        type_: event.kind,
+                        length: mem::size_of::<ffi::drm_event_vblank>() as u32,
+                    },
+                    user_data: event.user_data,
+                    tv_sec: (crtc.time_ns / 1_000_000_000) as u32,
+                    tv_usec: (crtc.time_ns % 1_000_000_000 / 1000) as u32,
+                    sequence: crtc.sequence as u32,
+                    crtc_id: crtc.id,
+                };
+                self.write_event(files, &data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_event<T>(&mut self, files: &Files, event: &T) -> io::Result<()> {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(event as *const T as *const u8, mem::size_of::<T>())
+        };
+        (&files.events).write_all(bytes)?;
+        Ok(())
+    }
+
+    pub unsafe fn ioctl(
+        &mut self,
+        files: &Files,
+        request: u32,
+        arg: *mut c_void,
+    ) -> io::Result<()> {
+        let nr = transport::request_nr(request);
+        if !self.master && MASTER_ONLY.contains(&nr) {
+            return Err(Errno::ACCESS.into());
+        }
+
+        match nr {
+            0x00 => self.get_version(arg_mut(request, arg)?),
+            0x01 => {
+                arg_mut::<ffi::drm_unique>(request, arg)?.unique_len = 0;
+                Ok(())
+            }
+            0x02 => {
+                arg_mut::<ffi::drm_auth>(request, arg)?.magic = 1;
+                Ok(())
+            }
+            0x05 => get_client(arg_mut(request, arg)?),
+            0x07 => Ok(()),
+            0x11 => match arg_read::<ffi::drm_auth>(request, arg)?.magic {
+                0 => Err(Errno::INVAL.into()),
+                _ => Ok(()),
+            },
+            0x1e if self.foreign_master => Err(Errno::BUSY.into()),
+            0x1e => {
+                self.master = true;
+                Ok(())
+            }
+            0x1f if !self.master => Err(Errno::INVAL.into()),
+            0x1f => {
+                self.master = false;
+                Ok(())
+            }
+            0x09 => {
+                let close: ffi::drm_gem_close = arg_read(request, arg)?;
+                self.destroy_dumb(files, close.handle)
+            }
+            0x0a => self.gem_flink(arg_mut(request, arg)?),
+            0x0b => self.gem_open(arg_mut(request, arg)?),
+            0x2d => self.prime_handle_to_fd(arg_mut(request, arg)?),
+            0x2e => self.prime_fd_to_handle(arg_mut(request, arg)?),
+            0x0c => self.get_cap(arg_mut(request, arg)?),
+            0x0d => self.set_client_cap(arg_read(request, arg)?),
+            0x3a => self.wait_vblank(files, arg_mut(request, arg)?),
+            0x3b => self.get_sequence(arg_mut(request, arg)?),
+            0x3c => self.queue_sequence(files, arg_mut(request, arg)?),
+            0xA0 => self.get_resources(arg_mut(request, arg)?),
+            0xA1 => self.get_crtc(arg_mut(request, arg)?),
+            0xA2 => self.set_crtc(files, arg_mut(request, arg)?),
+            0xA4 => self.gamma(arg_mut(request, arg)?, false),
+            0xA5 => self.gamma(arg_mut(request, arg)?, true),
+            0xA6 => self.get_encoder(arg_mut(request, arg)?),
+            0xA7 => self.get_connector(arg_mut(request, arg)?),
+            0xAA => self.get_property(arg_mut(request, arg)?),
+            0xAB => {
+                let set: &mut ffi::drm_mode_connector_set_property = arg_mut(request, arg)?;
+                let mut obj = ffi::drm_mode_obj_set_property {
+                    value: set.value,
+                    prop_id: set.prop_id,
+                    obj_id: set.connector_id,
+                    obj_type: ffi::DRM_MODE_OBJECT_CONNECTOR,
+                };
+                self.set_property(files, &mut obj)
+            }
+            0xAC => self.get_blob(arg_mut(request, arg)?),
+            0xAD => self.get_fb(arg_mut(request, arg)?),
+            0xAE => self.add_fb(arg_mut(request, arg)?),
+            0xAF => self.rm_fb(*arg_mut::<u32>(request, arg)?),
+            0xD0 => {
+                let close: &mut ffi::drm_mode_closefb = arg_mut(request, arg)?;
+                self.close_fb(close.fb_id)
+            }
+            0xB0 => self.page_flip(files, arg_mut(request, arg)?),
+            0xB2 => self.create_dumb(arg_mut(request, arg)?),
+            0xB3 => self.map_dumb(arg_mut(request, arg)?),
+            0xB4 => {
+                let handle = arg_mut::<ffi::drm_mode_destroy_dumb>(request, arg)?.handle;
+                self.destroy_dumb(files, handle)
+            }
+            0xB5 => self.get_plane_resources(arg_mut(request, arg)?),
+            0xB6 => self.get_plane(arg_mut(request, arg)?),
+            0xB7 => self.set_plane(files, arg_mut(request, arg)?),
+            0xB8 => self.add_fb2(arg_mut(request, arg)?),
+            0xB9 => self.get_properties(arg_mut(request, arg)?),
+            0xBA => self.set_property(files, arg_mut(request, arg)?),
+            0xBC => self.atomic(files, arg_mut(request, arg)?),
+            0xBD => self.create_blob_ioctl(arg_mut(request, arg)?),
+            0xBE => self.destroy_blob(arg_mut(request, arg)?),
+            0xCE => self.get_fb2(arg_mut(request, arg)?),
+            _ => Err(Errno::INVAL.into()),
+        }
+    }
+
+    fn get_cap(&self, cap: &mut ffi::drm_get_cap) -> io::Result<()> {
+        match self.capabilities.get(&cap.capability) {
+            Some(Some(value)) => {
+                cap.value = *value;
+                Ok(())
+            }
+            Some(None) => Err(Errno::INVAL.into()),
+            None => default_cap(cap),
+        }
+    }
+
+    unsafe fn get_version(&self, version: &mut ffi::drm_version) -> io::Result<()> {
+        version.version_major = 1;
+        version.version_minor = 0;
+        version.version_patchlevel = 0;
+        copy_str(version.name, &mut version.name_len, "emulator")?;
+        copy_str(version.date, &mut version.date_len, "0")?;
+        copy_str(version.desc, &mut version.desc_len, "Software KMS emulator")
+    }
+
+    fn set_client_cap(&mut self, cap: ffi::drm_set_client_cap) -> io::Result<()> {
+        if cap.value > 1 {
+            return Err(Errno::INVAL.into());
+        }
+        match cap.capability as u32 {
+            ffi::DRM_CLIENT_CAP_UNIVERSAL_PLANES => self.universal_planes = cap.value == 1,
+            ffi::DRM_CLIENT_CAP_ATOMIC => {
+                self.atomic = cap.value == 1;
+                self.universal_planes |= self.atomic;
+            }
+            ffi::DRM_CLIENT_CAP_WRITEBACK_CONNECTORS if !self.atomic => {
+                return Err(Errno::INVAL.into())
+            }
+            ffi::DRM_CLIENT_CAP_STEREO_3D
+            | ffi::DRM_CLIENT_CAP_ASPECT_RATIO
+            | ffi::DRM_CLIENT_CAP_WRITEBACK_CONNECTORS => {}
+            _ => return Err(Errno::INVAL.into()),
+        }
+        Ok(())
+    }
+
+    fn wait_vblank(&mut self, files: &Files, vbl: &mut ffi::drm_wait_vblank) -> io::Result<()> {
+        use ffi::drm_vblank_seq_type::*;
+
+        let request = unsafe { vbl.request };
+        let ty = request.type_;
+        let index = if ty & _DRM_VBLANK_HIGH_CRTC_MASK != 0 {
+            ((ty & _DRM_VBLANK_HIGH_CRTC_MASK) >> ffi::_DRM_VBLANK_HIGH_CRTC_SHIFT) as usize
+        } else if ty & _DRM_VBLANK_SECONDARY != 0 {
+            1
+        } else {
+            0
+        };
+        let crtc = self.crtcs.get(index).ok_or(Errno::INVAL)?;
+        if get(&self.values, crtc.id, self.std.active) == 0 {
+            return Err(Errno::INVAL.into());
+        }
+
+        let current = crtc.sequence as u32;
+        let mut target = if ty & _DRM_VBLANK_RELATIVE != 0 {
+            current.wrapping_add(request.sequence)
+        } else {
+            request.sequence
+        };
+        if ty & _DRM_VBLANK_NEXTONMISS != 0 && target.wrapping_sub(current) as i32 <= 0 {
+            target = current.wrapping_add(1);
+        }
+
+        // The virtual clock runs ahead to the requested vblank right away
+        let delta = target.wrapping_sub(current) as i32;
+        if delta > 0 {
+            self.advance(index, delta as u64);
+        }
+
+        if ty & _DRM_VBLANK_EVENT != 0 {
+            self.send_events(
+                files,
+                vec![PendingEvent {
+                    kind: ffi::DRM_EVENT_VBLANK,
+                    crtc: index,
+                    #[allow(clippy::unnecessary_cast)]
+                    user_data: request.signal as u64,
+                }],
+            )?;
+        }
+
+        let crtc = &self.crtcs[index];
+        vbl.reply = ffi::drm_wait_vblank_reply {
+            type_: ty,
+            sequence: crtc.sequence as u32,
+            tval_sec: (crtc.time_ns / 1_000_000_000) as _,
+            tval_usec: (crtc.time_ns % 1_000_000_000 / 1000) as _,
+        };
+        Ok(())
+    }
+
+    fn get_sequence(&mut self, seq: &mut ffi::drm_crtc_get_sequence) -> io::Result<()> {
+        let crtc = &self.crtcs[self.crtc_index(seq.crtc_id).ok_or(Errno::NOENT)?];
+        seq.active = get(&self.values, crtc.id, self.std.active) as u32;
+        seq.sequence = crtc.sequence;
+        seq.sequence_ns = crtc.time_ns as i64;
+        Ok(())
+    }
+
+    fn queue_sequence(
+        &mut self,
+        files: &Files,
+        queue: &mut ffi::drm_crtc_queue_sequence,
+    ) -> io::Result<()> {
+        let index = self.crtc_index(queue.crtc_id).ok_or(Errno::NOENT)?;
+        let crtc = &self.crtcs[index];
+        if get(&self.values, crtc.id, self.std.active) == 0 {
+            return Err(Errno::INVAL.into());
+        }
+        let supported = ffi::DRM_CRTC_SEQUENCE_RELATIVE | ffi::DRM_CRTC_SEQUENCE_NEXT_ON_MISS;
+        if queue.flags & !supported != 0 {
+            return Err(Errno::INVAL.into());
+        }
+
+        let current = crtc.sequence;
+        let mut target = if queue.flags & ffi::DRM_CRTC_SEQUENCE_RELATIVE != 0 {
+            current.wrapping_add(queue.sequence)
+        } else {
+            queue.sequence
+        };
+        if queue.flags & ffi::DRM_CRTC_SEQUENCE_NEXT_ON_MISS != 0 && target <= current {
+            target = current.saturating_add(1);
+        }
+
+        // The virtual clock runs ahead to the requested vblank right away
+        if target > current {
+            self.advance(index, target - current);
+        }
+        queue.sequence = target;
+
+        self.send_events(
+            files,
+            vec![PendingEvent {
+                kind: ffi::DRM_EVENT_CRTC_SEQUENCE,
+                crtc: index,
+                user_data: queue.user_data,
+            }],
+        )
+    }
+
+    unsafe fn get_resources(&self, res: &mut ffi::drm_mode_card_res) -> io::Result<()> {
+        let fbs: Vec<u32> = self
+            .framebuffers
+            .iter()
+            .filter(|(_, fb)| !fb.closed)
+            .map(|(id, _)| *id)
+            .collect();
+        let crtcs: Vec<u32> = self.crtcs.iter().map(|c| c.id).collect();
+        let connectors: Vec<u32> = self.connectors.iter().map(|c| c.id).collect();
+        let encoders: Vec<u32> = self.encoders.iter().map(|e| e.id).collect();
+
+        copy_out(res.fb_id_ptr, &mut res.count_fbs, &fbs)?;
+        copy_out(res.crtc_id_ptr, &mut res.count_crtcs, &crtcs)?;
+        copy_out(res.connector_id_ptr, &mut res.count_connectors, &connectors)?;
+        copy_out(res.encoder_id_ptr, &mut res.count_encoders, &encoders)?;
+        res.min_width = 1;
+        res.max_width = 16384;
+        res.min_height = 1;
+        res.max_height = 16384;
+        Ok(())
+    }
+
+    fn get_crtc(&self, info: &mut ffi::drm_mode_crtc) -> io::Result<()> {
+        let index = self.crtc_index(info.crtc_id).ok_or(Errno::NOENT)?;
+        let crtc = &self.crtcs[index];
+
+        let primary = self
+            .primary_plane(index)
+            .filter(|plane| self.value(*plane, self.std.crtc_id) == Some(crtc.id as u64));
+        info.fb_id = primary.map_or(0, |plane| get(&self.values, plane, self.std.fb_id) as u32);
+        info.x = primary.map_or(0, |plane| {
+            (get(&self.values, plane, self.std.src[0]) >> 16) as u32
+        });
+        info.y = primary.map_or(0, |plane| {
+            (get(&self.values, plane, self.std.src[1]) >> 16) as u32
+        });
+        info.gamma_size = (crtc.gamma.len() / 3) as u32;
+
+        match self.mode(&self.values, crtc.id) {
+            Some(mode) => {
+                info.mode_valid = 1;
+                info.mode = mode;
+            }
+            None => {
+                info.mode_valid = 0;
+                info.mode = ffi::drm_mode_modeinfo::default();
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn set_crtc(&mut self, files: &Files, info: &mut ffi::drm_mode_crtc) -> io::Result<()> {
+        let index = self.crtc_index(info.crtc_id).ok_or(Errno::NOENT)?;
+        let crtc = info.crtc_id;
+        let std = &self.std;
+        let primary = self.primary_plane(index);
+        let mut values = self.values.clone();
+
+        // Detach everything currently routed to this CRTC
+        for connector in &self.connectors {
+            if get(&values, connector.id, std.crtc_id) == crtc as u64 {
+                set(&mut values, connector.id, std.crtc_id, 0);
+            }
+        }
+
+        if info.mode_valid == 0 {
+            if info.fb_id != 0 || info.count_connectors != 0 {
+                return Err(Errno::INVAL.into());
+            }
+            set(&mut values, crtc, std.active, 0);
+            set(&mut values, crtc, std.mode_id, 0);
+            for plane in &self.planes {
+                if get(&values, plane.id, std.crtc_id) == crtc as u64 {
+                    set(&mut values, plane.id, std.crtc_id, 0);
+                    set(&mut values, plane.id, std.fb_id, 0);
+                }
+            }
+            return self.commit(files, values, &[crtc], true, None, false);
+        }
+
+        let connectors: Vec<u32> =
+            copy_in(info.set_connectors_ptr, info.count_connectors as usize)?;
+        for connector in &connectors {
+            if !self.connectors.iter().any(|c| c.id == *connector) {
+                return Err(Errno::NOENT.into());
+            }
+            set(&mut values, *connector, std.crtc_id, crtc as u64);
+        }
+
+        let fb = match info.fb_id {
+            // -1 keeps the current framebuffer, even if it was closed
+            u32::MAX => primary.map_or(0, |plane| get(&self.values, plane, std.fb_id) as u32),
+            fb if self.framebuffer(fb).is_none() => return Err(Errno::NOENT.into()),
+            fb => fb,
+        };
+        if !self.framebuffers.contains_key(&fb) {
+            return Err(Errno::NOENT.into());
+        }
+        let primary = primary.ok_or(Errno::INVAL)?;
+
+        let mode = info.mode;
+        let bytes = std::slice::from_raw_parts(
+            &mode as *const ffi::drm_mode_modeinfo as *const u8,
+            mem::size_of::<ffi::drm_mode_modeinfo>(),
+        )
+        .to_vec();
+        let blob = self.create_blob(bytes);
+        // Only referenced by the state, like blobs created by the kernel
+        self.destroyed_blobs.insert(blob);
+
+        let std = &self.std;
+        set(&mut values, crtc, std.active, 1);
+        set(&mut values, crtc, std.mode_id, blob as u64);
+        set(&mut values, primary, std.crtc_id, crtc as u64);
+        set(&mut values, primary, std.fb_id, fb as u64);
+        let src = [info.x, info.y, mode.hdisplay as u32, mode.vdisplay as u32];
+        let dst = [0, 0, mode.hdisplay as u32, mode.vdisplay as u32];
+        for (prop, value) in std.src.into_iter().zip(src) {
+            set(&mut values, primary, prop, (value as u64) << 16);
+        }
+        for (prop, value) in std.dst.into_iter().zip(dst) {
+            set(&mut values, primary, prop, value as u64);
+        }
+
+        let result = self.commit(files, values, &[crtc], true, None, false);
+        self.collect_blobs();
+        result
+    }
+
+    unsafe fn gamma(&mut self, lut: &mut ffi::drm_mode_crtc_lut, write: bool) -> io::Result<()> {
+        let index = self.crtc_index(lut.crtc_id).ok_or(Errno::NOENT)?;
+        let crtc = &mut self.crtcs[index];
+        let size = crtc.gamma.len() / 3;
+        if lut.gamma_size as usize != size {
+            return Err(Errno::INVAL.into());
+        }
+
+        let channels = [lut.red, lut.green, lut.blue];
+        for (channel, ptr) in crtc.gamma.chunks_mut(size).zip(channels) {
+            if write {
+                channel.copy_from_slice(&copy_in(ptr, size)?);
+            } else {
+                let mut count = size as u32;
+                copy_out(ptr, &mut count, channel)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_encoder(&self, info: &mut ffi::drm_mode_get_encoder) -> io::Result<()> {
+        let encoder = self
+            .encoders
+            .iter()
+            .find(|e| e.id == info.encoder_id)
+            .ok_or(Errno::NOENT)?;
+
+        info.encoder_type = encoder.kind;
+        info.possible_crtcs = encoder.possible_crtcs;
+        info.possible_clones = encoder.possible_clones;
+        info.crtc_id = self
+            .connectors
+            .iter()
+            .find(|c| self.connector_encoder(&self.values, c) == encoder.id)
+            .map_or(0, |c| get(&self.values, c.id, self.std.crtc_id) as u32);
+        Ok(())
+    }
+
+    unsafe fn get_connector(&self, info: &mut ffi::drm_mode_get_connector) -> io::Result<()> {
+        let connector = self
+            .connectors
+            .iter()
+            .find(|c| c.id == info.connector_id)
+            .ok_or(Errno::NOENT)?;
+
+        let connected = connector.connection == u32::from(connector::State::Connected);
+        let modes: &[ffi::drm_mode_modeinfo] = if connected { &connector.modes } else { &[] };
+        let (props, values) = self.visible_properties(connector.id);
+
+        copy_out(
+            info.encoders_ptr,
+            &mut info.count_encoders,
+            &connector.encoders,
+        )?;
+        copy_out(info.modes_ptr, &mut info.count_modes, modes)?;
+        let mut count = info.count_props;
+        copy_out(info.props_ptr, &mut count, &props)?;
+        copy_out(info.prop_values_ptr, &mut info.count_props, &values)?;
+
+        info.encoder_id = self.connector_encoder(&self.values, connector);
+        info.connector_type = connector.interface;
+        info.connector_type_id = connector.interface_id;
+        info.connection = connector.connection;
+        info.mm_width = connector.size.0;
+        info.mm_height = connector.size.1;
+        info.subpixel = 0; // SubPixel::Unknown
+        Ok(())
+    }
+
+    fn visible_properties(&self, object: u32) -> (Vec<u32>, Vec<u64>) {
+        self.values
+            .get(&object)
+            .into_iter()
+            .flatten()
+            .filter(|(prop, _)| {
+                self.atomic || !self.property(*prop).map_or(true, Property::is_atomic)
+            })
+            .copied()
+            .unzip()
+    }
+
+    unsafe fn get_property(&self, info: &mut ffi::drm_mode_get_property) -> io::Result<()> {
+        let prop = self.property(info.prop_id).ok_or(Errno::NOENT)?;
+
+        info.flags = prop.flags;
+        info.name = [0; 32];
+        for (dst, src) in info.name.iter_mut().zip(prop.name.bytes().take(31)) {
+            *dst = src as c_char;
+        }
+
+        let (values, enums): (Vec<u64>, Vec<ffi::drm_mode_property_enum>) =
+            if prop.flags & (ffi::DRM_MODE_PROP_ENUM | ffi::DRM_MODE_PROP_BITMASK) != 0 {
+                prop.enums
+                    .iter()
+                    .map(|(value, name)| {
+                        let mut entry = ffi::drm_mode_property_enum {
+                            value: *value,
+                            name: [0; 32],
+                        };
+                        for (dst, src) in entry.name.iter_mut().zip(name.bytes().take(31)) {
+                            *dst = src as c_char;
+                        }
+                        (*value, entry)
+                    })
+                    .unzip()
+            } else {
+                (prop.values.clone(), Vec::new())
+            };
+
+        copy_out(info.values_ptr, &mut info.count_values, &values)?;
+        copy_out(info.enum_blob_ptr, &mut info.count_enum_blobs, &enums)
+    }
+
+    unsafe fn get_properties(&self, info: &mut ffi::drm_mode_obj_get_properties) -> io::Result<()> {
+        let ty = self.object_type(info.obj_id).ok_or(Errno::NOENT)?;
+        if info.obj_type != ffi::DRM_MODE_OBJECT_ANY && info.obj_type != ty {
+            return Err(Errno::NOENT.into());
+        }
+
+        let (props, values) = self.visible_properties(info.obj_id);
+        let mut count = info.count_props;
+        copy_out(info.props_ptr, &mut count, &props)?;
+        copy_out(info.prop_values_ptr, &mut info.count_props, &values)
+    }
+
+    fn set_property(
+        &mut self,
+        files: &Files,
+        set: &mut ffi::drm_mode_obj_set_property,
+    ) -> io::Result<()> {
+        let ty = self.object_type(set.obj_id).ok_or(Errno::NOENT)?;
+        if set.obj_type != ty || self.value(set.obj_id, set.prop_id).is_none() {
+            return Err(Errno::NOENT.into());
+        }
+        let prop = self.property(set.prop_id).ok_or(Errno::NOENT)?;
+        if prop.is_immutable() || prop.is_atomic() || !prop.accepts(self, set.value) {
+            return Err(Errno::INVAL.into());
+        }
+
+        let mut values = self.values.clone();
+        self::set(&mut values, set.obj_id, set.prop_id, set.value);
+        self.commit(files, values, &[set.obj_id], true, None, false)
+    }
+
+    unsafe fn get_blob(&self, info: &mut ffi::drm_mode_get_blob) -> io::Result<()> {
+        let blob = self.blobs.get(&info.blob_id).ok_or(Errno::NOENT)?;
+        if info.length as usize >= blob.len() {
+            let mut length = info.length;
+            copy_out(info.data, &mut length, blob)?;
+        }
+        info.length = blob.len() as u32;
+        Ok(())
+    }
+
+    unsafe fn create_blob_ioctl(&mut self, info: &mut ffi::drm_mode_create_blob) -> io::Result<()> {
+        if info.length == 0 {
+            return Err(Errno::INVAL.into());
+        }
+        let data = copy_in(info.data, info.length as usize)?;
+        info.blob_id = self.create_blob(data);
+        Ok(())
+    }
+
+    fn destroy_blob(&mut self, info: &mut ffi::drm_mode_destroy_blob) -> io::Result<()> {
+        if !self.blobs.contains_key(&info.blob_id) || self.destroyed_blobs.contains(&info.blob_id) {
+            return Err(Errno::NOENT.into());
+        }
+        // Blobs stay alive for as long as they are in use
+        self.destroyed_blobs.insert(info.blob_id);
+        self.collect_blobs();
+        Ok(())
+    }
+
+    fn get_fb(&self, info: &mut ffi::drm_mode_fb_cmd) -> io::Result<()> {
+        let fb = self.framebuffer(info.fb_id).ok_or(Errno::NOENT)?;
+        info.width = fb.cmd.width;
+        info.height = fb.cmd.height;
+        info.pitch = fb.cmd.pitches[0];
+        info.bpp = fb.bpp;
+        info.depth = fb.depth;
+        info.handle = fb.cmd.handles[0];
+        Ok(())
+    }
+
+    fn get_fb2(&self, info: &mut ffi::drm_mode_fb_cmd2) -> io::Result<()> {
+        let fb = self.framebuffer(info.fb_id).ok_or(Errno::NOENT)?;
+        *info = fb.cmd;
+        Ok(())
+    }
+
+    fn add_fb(&mut self, info: &mut ffi::drm_mode_fb_cmd) -> io::Result<()> {
+        let format = match (info.bpp, info.depth) {
+            (8, 8) => DrmFourcc::C8,
+            (16, 15) => DrmFourcc::Xrgb1555,
+            (16, 16) => DrmFourcc::Rgb565,
+            (24, 24) => DrmFourcc::Rgb888,
+            (32, 24) => DrmFourcc::Xrgb8888,
+            (32, 30) => DrmFourcc::Xrgb2101010,
+            (32, 32) => DrmFourcc::Argb8888,
+            _ => return Err(Errno::INVAL.into()),
+        };
+
+        let mut cmd = ffi::drm_mode_fb_cmd2 {
+            width: info.width,
+            height: info.height,
+            pixel_format: format as u32,
+            handles: [info.handle, 0, 0, 0],
+            pitches: [info.pitch, 0, 0, 0],
+            ..Default::default()
+        };
+        self.add_fb2(&mut cmd)?;
+        info.fb_id = cmd.fb_id;
+        Ok(())
+    }
+
+    fn add_fb2(&mut self, info: &mut ffi::drm_mode_fb_cmd2) -> io::Result<()> {
+        if info.width == 0 || info.height == 0 || info.width > 16384 || info.height > 16384 {
+            return Err(Errno::INVAL.into());
+        }
+        if !self
+            .planes
+            .iter()
+            .any(|p| p.formats.contains(&info.pixel_format))
+        {
+            return Err(Errno::INVAL.into());
+        }
+        let buffer = self
+            .dumb_buffers
+            .get(&info.handles[0])
+            .ok_or(Errno::NOENT)?;
+        let needed = info.offsets[0] as u64 + info.pitches[0] as u64 * info.height as u64;
+        if info.pitches[0] == 0 || needed > buffer.size {
+            return Err(Errno::INVAL.into());
+        }
+
+        let mut cmd = *info;
+        if cmd.flags & ffi::DRM_MODE_FB_MODIFIERS == 0 {
+            cmd.modifier = [0; 4];
+        }
+        let (depth, bpp) = match DrmFourcc::try_from(cmd.pixel_format) {
+            Ok(DrmFourcc::Xrgb8888 | DrmFourcc::Xbgr8888) => (24, 32),
+            Ok(DrmFourcc::Argb8888 | DrmFourcc::Abgr8888) => (32, 32),
+            Ok(DrmFourcc::Xrgb2101010 | DrmFourcc::Xbgr2101010) => (30, 32),
+            Ok(DrmFourcc::Rgb565) => (16, 16),
+            Ok(DrmFourcc::Rgb888) => (24, 24),
+            _ => (0, 0),
+        };
+
+        cmd.fb_id = self.alloc_id();
+        info.fb_id = cmd.fb_id;
+        self.framebuffers.insert(
+            cmd.fb_id,
+            Framebuffer {
+                cmd,
+                depth,
+                bpp,
+                closed: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn rm_fb(&mut self, fb: u32) -> io::Result<()> {
+        self.framebuffer(fb).ok_or(Errno::NOENT)?;
+        self.framebuffers.remove(&fb);
+
+        // Planes scanning out the framebuffer are disabled
+        for plane in &self.planes {
+            if get(&self.values, plane.id, self.std.fb_id) == fb as u64 {
+                set(&mut self.values, plane.id, self.std.fb_id, 0);
+                set(&mut self.values, plane.id, self.std.crtc_id, 0);
+            }
+        }
+        Ok(())
+    }
+
+    fn close_fb(&mut self, fb: u32) -> io::Result<()> {
+        self.framebuffer(fb).ok_or(Errno::NOENT)?;
+        // Planes keep scanning out the framebuffer until they are updated
+        if let Some(fb) = self.framebuffers.get_mut(&fb) {
+            fb.closed = true;
+        }
+        self.collect_framebuffers();
+        Ok(())
+    }
+
+    fn page_flip(
+        &mut self,
+        files: &Files,
+        flip: &mut ffi::drm_mode_crtc_page_flip,
+    ) -> io::Result<()> {
+        if flip.flags & !ffi::DRM_MODE_PAGE_FLIP_FLAGS != 0
+            || flip.flags & (ffi::DRM_MODE_PAGE_FLIP_ASYNC | ffi::DRM_MODE_PAGE_FLIP_TARGET) != 0
+        {
+            return Err(Errno::INVAL.into());
+        }
+        let index = self.crtc_index(flip.crtc_id).ok_or(Errno::NOENT)?;
+        if self.framebuffer(flip.fb_id).is_none() {
+            return Err(Errno::NOENT.into());
+        }
+        if get(&self.values, flip.crtc_id, self.std.active) == 0 {
+            return Err(Errno::INVAL.into());
+        }
+
+        let primary = self.primary_plane(index).ok_or(Errno::INVAL)?;
+        if get(&self.values, primary, self.std.crtc_id) != flip.crtc_id as u64 {
+            return Err(Errno::INVAL.into());
+        }
+
+        let mut values = self.values.clone();
+        set(&mut values, primary, self.std.fb_id, flip.fb_id as u64);
+        let event = (flip.flags & ffi::DRM_MODE_PAGE_FLIP_EVENT != 0).then_some(flip.user_data);
+        self.commit(files, values, &[flip.crtc_id], false, event, false)
+    }
+
+    fn create_dumb(&mut self, info: &mut ffi::drm_mode_create_dumb) -> io::Result<()> {
+        if info.width == 0 || info.height == 0 || info.bpp == 0 {
+            return Err(Errno::INVAL.into());
+        }
+
+        let pitch = (info.width as u64 * ((info.bpp as u64 + 7) / 8) + 63) & !63;
+        let size = pitch * info.height as u64;
+        let reserved = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if pitch > u32::MAX as u64 || self.pool_end + reserved > POOL_SIZE {
+            return Err(Errno::NOMEM.into());
+        }
+
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.dumb_buffers.insert(
+            handle,
+            DumbBuffer {
+                offset: self.pool_end,
+                size,
+            },
+        );
+        self.pool_end += reserved;
+
+        info.handle = handle;
+        info.pitch = pitch as u32;
+        info.size = size;
+        Ok(())
+    }
+
+    fn map_dumb(&self, info: &mut ffi::drm_mode_map_dumb) -> io::Result<()> {
+        let buffer = self.dumb_buffers.get(&info.handle).ok_or(Errno::NOENT)?;
+        info.offset = buffer.offset;
+        Ok(())
+    }
+
+    fn destroy_dumb(&mut self, files: &Files, handle: u32) -> io::Result<()> {
+        let buffer = self.dumb_buffers.remove(&handle).ok_or(Errno::NOENT)?;
+        // Buffers opened by name have several handles
+        if self.dumb_buffers.values().any(|b| *b == buffer) {
+            return Ok(());
+        }
+        // Exported buffers stay alive, they can still be imported again
+        if self.exports.iter().any(|export| export.buffer == buffer) {
+            return Ok(());
+        }
+        self.names.retain(|_, b| *b != buffer);
+        // Release the memory, the address range itself is not reused
+        let _ = rustix::fs::fallocate(
+            &files.pool,
+            FallocateFlags::PUNCH_HOLE | FallocateFlags::KEEP_SIZE,
+            buffer.offset,
+            buffer.size,
+        );
+        Ok(())
+    }
+
+    fn gem_flink(&mut self, flink: &mut ffi::drm_gem_flink) -> io::Result<()> {
+        let buffer = *self.dumb_buffers.get(&flink.handle).ok_or(Errno::NOENT)?;
+        // A buffer keeps its name once it has one
+        flink.name = match self.names.iter().find(|(_, b)| **b == buffer) {
+            Some((name, _)) => *name,
+            None => {
+                let name = self.next_name;
+                self.next_name += 1;
+                self.names.insert(name, buffer);
+                name
+            }
+        };
+        Ok(())
+    }
+
+    fn gem_open(&mut self, open: &mut ffi::drm_gem_open) -> io::Result<()> {
+        let buffer = *self.names.get(&open.name).ok_or(Errno::NOENT)?;
+        // Unlike PRIME imports, every open creates a new handle
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.dumb_buffers.insert(handle, buffer);
+        open.handle = handle;
+        open.size = buffer.size;
+        Ok(())
+    }
+
+    fn prime_handle_to_fd(&mut self, prime: &mut ffi::drm_prime_handle) -> io::Result<()> {
+        if prime.flags & !(ffi::DRM_CLOEXEC | ffi::DRM_RDWR) != 0 {
+            return Err(Errno::INVAL.into());
+        }
+        let buffer = *self.dumb_buffers.get(&prime.handle).ok_or(Errno::NOENT)?;
+
+        let index = match self
+            .exports
+            .iter()
+            .position(|export| export.buffer == buffer)
+        {
+            Some(index) => index,
+            None => {
+                let file = rustix::fs::memfd_create("drm-emulator-dmabuf", MemfdFlags::CLOEXEC)?;
+                let stat = rustix::fs::fstat(&file)?;
+                self.exports.push(Export {
+                    file,
+                    inode: (stat.st_dev as u64, stat.st_ino as u64),
+                    buffer,
+                });
+                self.exports.len() - 1
+            }
+        };
+
+        let file = &self.exports[index].file;
+        let file = if prime.flags & ffi::DRM_CLOEXEC != 0 {
+            rustix::io::fcntl_dupfd_cloexec(file, 0)?
+        } else {
+            rustix::io::dup(file)?
+        };
+        prime.fd = file.into_raw_fd();
+        Ok(())
+    }
+
+    fn prime_fd_to_handle(&mut self, prime: &mut ffi::drm_prime_handle) -> io::Result<()> {
+        if prime.fd < 0 {
+            return Err(Errno::BADF.into());
+        }
+        // SAFETY: The caller passed an open file descriptor
+        let file = unsafe { BorrowedFd::borrow_raw(prime.fd) };
+        let stat = rustix::fs::fstat(file)?;
+        let inode = (stat.st_dev as u64, stat.st_ino as u64);
+        let buffer = self
+            .exports
+            .iter()
+            .find(|export| export.inode == inode)
+            .ok_or(Errno::INVAL)?
+            .buffer;
+
+        // Importing a buffer again returns the handle it already has
+        prime.handle = match self.dumb_buffers.iter().find(|(_, b)| **b == buffer) {
+            Some((handle, _)) => *handle,
+            None => {
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.dumb_buffers.insert(handle, buffer);
+                handle
+            }
+        };
+        Ok(())
+    }
+
+    unsafe fn get_plane_resources(&self, res: &mut ffi::drm_mode_get_plane_res) -> io::Result<()> {
+        let planes: Vec<u32> = self
+            .planes
+            .iter()
+            .filter(|p| self.universal_planes || p.kind == ffi::DRM_PLANE_TYPE_OVERLAY)
+            .map(|p| p.id)
+            .collect();
+        copy_out(res.plane_id_ptr, &mut res.count_planes, &planes)
+    }
+
+    unsafe fn get_plane(&self, info: &mut ffi::drm_mode_get_plane) -> io::Result<()> {
+        let plane = self
+            .planes
+            .iter()
+            .find(|p| p.id == info.plane_id)
+            .ok_or(Errno::NOENT)?;
+
+        info.crtc_id = get(&self.values, plane.id, self.std.crtc_id) as u32;
+        info.fb_id = get(&self.values, plane.id, self.std.fb_id) as u32;
+        info.possible_crtcs = plane.possible_crtcs;
+        info.gamma_size = 0;
+        copy_out(
+            info.format_type_ptr,
+            &mut info.count_format_types,
+            &plane.formats,
+        )
+    }
+
+    fn set_plane(&mut self, files: &Files, info: &mut ffi::drm_mode_set_plane) -> io::Result<()> {
+        if !self.planes.iter().any(|p| p.id == info.plane_id) {
+            return Err(Errno::NOENT.into());
+        }
+        let std = &self.std;
+        let mut values = self.values.clone();
+
+        if info.fb_id == 0 {
+            set(&mut values, info.plane_id, std.fb_id, 0);
+            set(&mut values, info.plane_id, std.crtc_id, 0);
+        } else {
+            if self.crtc_index(info.crtc_id).is_none() || self.framebuffer(info.fb_id).is_none() {
+                return Err(Errno::NOENT.into());
+            }
+            let src = [info.src_x, info.src_y, info.src_w, info.src_h];
+            let dst = [
+                info.crtc_x as u32,
+                info.crtc_y as u32,
+                info.crtc_w,
+                info.crtc_h,
+            ];
+            set(&mut values, info.plane_id, std.fb_id, info.fb_id as u64);
+            set(&mut values, info.plane_id, std.crtc_id, info.crtc_id as u64);
+            for (prop, value) in std.src.into_iter().zip(src) {
+                set(&mut values, info.plane_id, prop, value as u64);
+            }
+            for (prop, value) in std.dst.into_iter().take(2).zip(&dst[..2]) {
+                set(
+                    &mut values,
+                    info.plane_id,
+                    prop,
+                    *value as i32 as i64 as u64,
+                );
+            }
+            for (prop, value) in std.dst.into_iter().skip(2).zip(&dst[2..]) {
+                set(&mut values, info.plane_id, prop, *value as u64);
+            }
+        }
+
+        self.commit(files, values, &[info.plane_id], false, None, false)
+    }
+
+    unsafe fn atomic(&mut self, files: &Files, req: &mut ffi::drm_mode_atomic) -> io::Result<()> {
+        if !self.atomic
+            || req.reserved != 0
+            || req.flags & !ffi::DRM_MODE_ATOMIC_FLAGS != 0
+            || req.flags & ffi::DRM_MODE_PAGE_FLIP_ASYNC != 0
+        {
+            return Err(Errno::INVAL.into());
+        }
+        let test_only = req.flags & ffi::DRM_MODE_ATOMIC_TEST_ONLY != 0;
+        let event = req.flags & ffi::DRM_MODE_PAGE_FLIP_EVENT != 0;
+        if test_only && event {
+            return Err(Errno::INVAL.into());
+        }
+
+        let objects: Vec<u32> = copy_in(req.objs_ptr, req.count_objs as usize)?;
+        let counts: Vec<u32> = copy_in(req.count_props_ptr, req.count_objs as usize)?;
+        let total = counts.iter().map(|c| *c as usize).sum();
+        let props: Vec<u32> = copy_in(req.props_ptr, total)?;
+        let prop_values: Vec<u64> = copy_in(req.prop_values_ptr, total)?;
+
+        let mut values = self.values.clone();
+        let mut props = props.into_iter().zip(prop_values);
+        for (object, count) in objects.iter().zip(counts) {
+            self.object_type(*object).ok_or(Errno::NOENT)?;
+            for (prop, value) in props.by_ref().take(count as usize) {
+                if self.value(*object, prop).is_none() {
+                    return Err(Errno::NOENT.into());
+                }
+                let property = self.property(prop).ok_or(Errno::NOENT)?;
+                if property.is_immutable() || !property.accepts(self, value) {
+                    return Err(Errno::INVAL.into());
+                }
+                set(&mut values, *object, prop, value);
+            }
+        }
+
+        let allow_modeset = req.flags & ffi::DRM_MODE_ATOMIC_ALLOW_MODESET != 0;
+        let event = event.then_some(req.user_data);
+        self.commit(files, values, &objects, allow_modeset, event, test_only)
+    }
+}
+
+fn get_client(client: &mut ffi::drm_client) -> io::Result<()> {
+    if client.idx != 0 {
+        return Err(Errno::INVAL.into());
+    }
+    client.auth = 1;
+    client.pid = std::process::id() as _;
+    client.uid = 0;
+    client.magic = 0;
+    client.iocs = 0;
+    Ok(())
+}
+
+fn default_cap(cap: &mut ffi::drm_get_cap) -> io::Result<()> {
+    cap.value = match cap.capability as u32 {
+        ffi::DRM_CAP_DUMB_BUFFER => 1,
+        ffi::DRM_CAP_VBLANK_HIGH_CRTC => 1,
+        ffi::DRM_CAP_DUMB_PREFERRED_DEPTH => 24,
+        ffi::DRM_CAP_DUMB_PREFER_SHADOW => 0,
+        ffi::DRM_CAP_PRIME => (ffi::DRM_PRIME_CAP_IMPORT | ffi::DRM_PRIME_CAP_EXPORT) as u64,
+        ffi::DRM_CAP_TIMESTAMP_MONOTONIC => 1,
+        ffi::DRM_CAP_ASYNC_PAGE_FLIP => 0,
+        ffi::DRM_CAP_CURSOR_WIDTH | ffi::DRM_CAP_CURSOR_HEIGHT => 64,
+        ffi::DRM_CAP_ADDFB2_MODIFIERS => 1,
+        ffi::DRM_CAP_PAGE_FLIP_TARGET => 0,
+        ffi::DRM_CAP_CRTC_IN_VBLANK_EVENT => 1,
+        ffi::DRM_CAP_SYNCOBJ | ffi::DRM_CAP_SYNCOBJ_TIMELINE => 0,
+        ffi::DRM_CAP_ATOMIC_ASYNC_PAGE_FLIP => 0,
+        _ => return Err(Errno::INVAL.into()),
+    };
+    Ok(())
+}
+
+/// The parts of a mode which require a modeset when changed.
+#[derive(PartialEq)]
+struct ModeKey([u32; 12]);
+
+impl From<ffi::drm_mode_modeinfo> for ModeKey {
+    fn from(mode: ffi::drm_mode_modeinfo) -> Self {
+        ModeKey([
+            mode.clock,
+            mode.hdisplay as u32,
+            mode.hsync_start as u32,
+            mode.hsync_end as u32,
+            mode.htotal as u32,
+            mode.hskew as u32,
+            mode.vdisplay as u32,
+            mode.vsync_start as u32,
+            mode.vsync_end as u32,
+            mode.vtotal as u32,
+            mode.vscan as u32,
+            mode.flags,
+        ])
+    }
+}
+
+/// Returns the bit of an object index in a mask like `possible_crtcs`, which
+/// can only hold the first 32 objects.
+pub(super) fn bit(index: usize) -> u32 {
+    u32::try_from(index)
+        .ok()
+        .and_then(|index| 1u32.checked_shl(index))
+        .unwrap_or(0)
+}
+
+fn value_in(values: &Values, object: u32, prop: u32) -> Option<u64> {
+    values
+        .get(&object)?
+        .iter()
+        .find(|(id, _)| *id == prop)
+        .map(|(_, value)| *value)
+}
+
+fn get(values: &Values, object: u32, prop: u32) -> u64 {
+    value_in(values, object, prop).unwrap_or(0)
+}
+
+fn set(values: &mut Values, object: u32, prop: u32, value: u64) {
+    if let Some((_, v)) = values
+        .get_mut(&object)
+        .and_then(|values| values.iter_mut().find(|(id, _)| *id == prop))
+    {
+        *v = value;
+    }
+}
+
+unsafe fn arg_mut<'a, T>(request: u32, arg: *mut c_void) -> io::Result<&'a mut T> {
+    if transport::request_size(request) != mem::size_of::<T>() {
+        return Err(Errno::INVAL.into());
+    }
+    (arg as *mut T).as_mut().ok_or_else(|| Errno::FAULT.into())
+}
+
+unsafe fn arg_read<T: Copy>(request: u32, arg: *mut c_void) -> io::Result<T> {
+    if transport::request_size(request) != mem::size_of::<T>() {
+        return Err(Errno::INVAL.into());
+    }
+    if arg.is_null() {
+        return Err(Errno::FAULT.into());
+    }
+    Ok(ptr::read_unaligned(arg as *const T))
+}
+
+/// Copies as many items as fit into a userspace array, updating its count to
+/// the number of available items.
+unsafe fn copy_out<T: Copy>(ptr: u64, count: &mut u32, items: &[T]) -> io::Result<()> {
+    let n = (*count as usize).min(items.len());
+    if n > 0 {
+        if ptr == 0 {
+            return Err(Errno::FAULT.into());
+        }
+        let dst = ptr as usize as *mut T;
+        for (i, item) in items[..n].iter().enumerate() {
+            ptr::write_unaligned(dst.add(i), *item);
+        }
+    }
+    *count = items.len() as u32;
+    Ok(())
+}
+
+unsafe fn copy_in<T: Copy>(ptr: u64, count: usize) -> io::Result<Vec<T>> {
+    if count > 0 && ptr == 0 {
+        return Err(Errno::FAULT.into());
+    }
+    let src = ptr as usize as *const T;
+    Ok((0..count)
+        .map(|i| ptr::read_unaligned(src.add(i)))
+        .collect())
+}
+
+unsafe fn copy_str(ptr: *mut c_char, len: &mut ffi::__kernel_size_t, s: &str) -> io::Result<()> {
+    let n = (*len as usize).min(s.len());
+    if n > 0 {
+        if ptr.is_null() {
+            return Err(Errno::FAULT.into());
+        }
+        ptr::copy_nonoverlapping(s.as_ptr() as *const c_char, ptr, n);
+    }
+    *len = s.len() as _;
+    Ok(())
+}
@@ -0,0 +1,829 @@
+use std::io;
+
+use drm_fourcc::DrmFourcc;
+use rustix::io::Errno;
+
+use super::*;
+use crate::buffer::Buffer;
+use crate::control::routing::Router;
+use crate::control::saved_state::{Restored, SavedState};
+use crate::control::{
+    atomic::AtomicModeReq, connector, encoder, AtomicCommitFlags, CrtcSequenceFlags,
+    Device as ControlDevice, Event, PageFlipFlags, ResourceHandles,
+};
+use crate::{ClientCapability, Device, MasterLost};
+
+/// A device with one CRTC, encoder, connector and primary plane.
+struct Setup {
+    device: OwnedDevice,
+    emulator: Emulator,
+    crtc: crtc::Handle,
+    connector: connector::Handle,
+    plane: plane::Handle,
+    mode: Mode,
+}
+
+fn setup() -> Setup {
+    let mut builder = Emulator::builder();
+    let crtc = builder.crtc();
+    let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+    let mode = mode(1920, 1080, 60);
+    let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &[mode]);
+    let plane = builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+    let (device, emulator) = builder.build().unwrap();
+
+    Setup {
+        device,
+        emulator,
+        crtc,
+        connector,
+        plane,
+        mode,
+    }
+}
+
+fn errno(result: io::Result<impl std::fmt::Debug>) -> Errno {
+    Errno::from_io_error(&result.unwrap_err()).expect("not an OS error")
+}
+
+fn framebuffer(device: &OwnedDevice) -> framebuffer::Handle {
+    let buffer = device
+        .create_dumb_buffer((1920, 1080), DrmFourcc::Xrgb8888, 32)
+        .unwrap();
+    device.add_framebuffer(&buffer, 24, 32).unwrap()
+}
+
+fn find_property<H: ResourceHandle>(
+    device: &OwnedDevice,
+    object: H,
+    name: &str,
+) -> property::Handle {
+    let props = device.get_properties(object).unwrap();
+    props.as_hashmap(device).unwrap()[name].handle()
+}
+
+#[test]
+fn resources() {
+    let s = setup();
+    let resources: ResourceHandles = s.device.resource_handles().unwrap();
+    assert_eq!(resources.crtcs(), &[s.crtc]);
+    assert_eq!(resources.connectors(), &[s.connector]);
+
+    let connector = s.device.get_connector(s.connector, false).unwrap();
+    assert_eq!(connector.state(), connector::State::Connected);
+    assert_eq!(connector.modes(), &[s.mode]);
+    assert_eq!(connector.current_encoder(), None);
+
+    let encoder = s.device.get_encoder(connector.encoders()[0]).unwrap();
+    assert_eq!(
+        resources.filter_crtcs(encoder.possible_crtcs()),
+        vec![s.crtc]
+    );
+}
+
+#[test]
+fn universal_planes_hide_primary_planes() {
+    let s = setup();
+    assert!(s.device.plane_handles().unwrap().is_empty());
+    s.device
+        .set_client_capability(ClientCapability::UniversalPlanes, true)
+        .unwrap();
+    assert_eq!(s.device.plane_handles().unwrap(), vec![s.plane]);
+}
+
+#[test]
+fn framebuffers_need_a_plane_with_the_format() {
+    let s = setup();
+    let buffer = s
+        .device
+        .create_dumb_buffer((64, 64), DrmFourcc::Rgb565, 16)
+        .unwrap();
+    assert_eq!(
+        errno(s.device.add_framebuffer(&buffer, 16, 16)),
+        Errno::INVAL
+    );
+
+    let fb = framebuffer(&s.device);
+    assert_eq!(s.device.get_framebuffer(fb).unwrap().size(), (1920, 1080));
+    s.device.destroy_framebuffer(fb).unwrap();
+    assert_eq!(errno(s.device.get_framebuffer(fb)), Errno::NOENT);
+}
+
+#[test]
+fn legacy_modeset_and_page_flip() {
+    let s = setup();
+    let fb = framebuffer(&s.device);
+    s.device
+        .set_crtc(s.crtc, Some(fb), (0, 0), &[s.connector], Some(s.mode))
+        .unwrap();
+    assert!(s.emulator.is_active(s.crtc));
+    assert_eq!(s.emulator.crtc_of(s.connector), Some(s.crtc));
+    assert_eq!(s.device.get_crtc(s.crtc).unwrap().mode(), Some(s.mode));
+
+    let count = s.emulator.vblank_count(s.crtc);
+    s.device
+        .page_flip(s.crtc, fb, PageFlipFlags::EVENT, None)
+        .unwrap();
+    assert_eq!(s.emulator.vblank_count(s.crtc), count + 1);
+
+    let events: Vec<_> = s.device.receive_events().unwrap().collect();
+    assert!(matches!(&events[..], [Event::PageFlip(e)] if e.crtc == s.crtc));
+
+    let flags = PageFlipFlags::EVENT;
+    let flip = s
+        .device
+        .page_flip_with_user_data(s.crtc, fb, flags, None, 1 << 63);
+    assert_eq!(errno(flip), Errno::INVAL);
+    s.device
+        .page_flip_with_user_data(s.crtc, fb, flags, None, 42)
+        .unwrap();
+    let events: Vec<_> = s.device.receive_events().unwrap().collect();
+    assert!(matches!(&events[..], [Event::PageFlip(e)] if e.user_data == 42));
+
+    // Disabling the CRTC detaches the connector
+    s.device.set_crtc(s.crtc, None, (0, 0), &[], None).unwrap();
+    assert!(!s.emulator.is_active(s.crtc));
+    assert_eq!(s.emulator.crtc_of(s.connector), None);
+}
+
+#[test]
+fn closed_framebuffers_stay_on_screen() {
+    let mut builder = Emulator::builder();
+    let mode = mode(1920, 1080, 60);
+    let mut outputs = Vec::new();
+    for _ in 0..2 {
+        let crtc = builder.crtc();
+        let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+        let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &[mode]);
+        let plane = builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+        outputs.push((crtc, connector, plane));
+    }
+    let (device, emulator) = builder.build().unwrap();
+    let [(crtc, connector, plane), (other_crtc, other_connector, other_plane)] = outputs[..] else {
+        unreachable!()
+    };
+
+    let fb = framebuffer(&device);
+    device
+        .set_crtc(crtc, Some(fb), (0, 0), &[connector], Some(mode))
+        .unwrap();
+    device.close_framebuffer(fb).unwrap();
+    assert_eq!(emulator.framebuffer(plane), Some(fb));
+
+    // The closed framebuffer can no longer be looked up or referenced
+    assert_eq!(errno(device.get_framebuffer(fb)), Errno::NOENT);
+    assert!(!device
+        .resource_handles()
+        .unwrap()
+        .framebuffers()
+        .contains(&fb));
+    assert_eq!(errno(device.destroy_framebuffer(fb)), Errno::NOENT);
+    assert_eq!(errno(device.close_framebuffer(fb)), Errno::NOENT);
+    let flip = device.page_flip(crtc, fb, PageFlipFlags::empty(), None);
+    assert_eq!(errno(flip), Errno::NOENT);
+
+    // An unrelated modeset still succeeds while it is scanned out
+    let other_fb = framebuffer(&device);
+    device
+        .set_crtc(
+            other_crtc,
+            Some(other_fb),
+            (0, 0),
+            &[other_connector],
+            Some(mode),
+        )
+        .unwrap();
+    assert_eq!(emulator.framebuffer(plane), Some(fb));
+    assert_eq!(emulator.framebuffer(other_plane), Some(other_fb));
+
+    // Replacing it releases the closed framebuffer
+    device
+        .page_flip(crtc, other_fb, PageFlipFlags::empty(), None)
+        .unwrap();
+    assert_eq!(emulator.framebuffer(plane), Some(other_fb));
+    assert_eq!(errno(device.get_framebuffer(fb)), Errno::NOENT);
+}
+
+#[test]
+fn crtc_sequences_near_the_end_of_the_counter() {
+    let s = setup();
+    let fb = framebuffer(&s.device);
+    s.device
+        .set_crtc(s.crtc, Some(fb), (0, 0), &[s.connector], Some(s.mode))
+        .unwrap();
+
+    let flags = CrtcSequenceFlags::empty();
+    let target = s.device.queue_crtc_sequence(s.crtc, u64::MAX - 1, flags, 1);
+    assert_eq!(target.unwrap(), u64::MAX - 1);
+    let sequence = s.device.get_crtc_sequence(s.crtc).unwrap();
+    assert_eq!(sequence.sequence, u64::MAX - 1);
+    assert_eq!(sequence.time.as_nanos(), i64::MAX as u128);
+
+    // The clock stops at the end instead of overflowing
+    let target = s
+        .device
+        .queue_crtc_sequence(s.crtc, 5, CrtcSequenceFlags::RELATIVE, 2);
+    assert_eq!(target.unwrap(), 3);
+    let target = s
+        .device
+        .queue_crtc_sequence(s.crtc, u64::MAX, flags, 3)
+        .unwrap();
+    assert_eq!(target, u64::MAX);
+    let target = s
+        .device
+        .queue_crtc_sequence(s.crtc, 0, CrtcSequenceFlags::NEXT_ON_MISS, 4)
+        .unwrap();
+    assert_eq!(target, u64::MAX);
+    assert_eq!(s.device.receive_events().unwrap().count(), 4);
+}
+
+#[test]
+fn page_flip_needs_an_active_crtc() {
+    let s = setup();
+    let fb = framebuffer(&s.device);
+    let flip = s.device.page_flip(s.crtc, fb, PageFlipFlags::EVENT, None);
+    assert_eq!(errno(flip), Errno::INVAL);
+}
+
+#[test]
+fn gamma_roundtrip() {
+    let s = setup();
+    let length = s.device.get_crtc(s.crtc).unwrap().gamma_length() as usize;
+    assert_eq!(length, 256);
+
+    let ramp: Vec<u16> = (0..length as u16).map(|i| i * 256).collect();
+    s.device.set_gamma(s.crtc, &ramp, &ramp, &ramp).unwrap();
+    let (mut red, mut green, mut blue) = (vec![0; length], vec![0; length], vec![0; length]);
+    s.device
+        .get_gamma(s.crtc, &mut red, &mut green, &mut blue)
+        .unwrap();
+    assert_eq!(red, ramp);
+    assert_eq!(blue, ramp);
+}
+
+#[test]
+fn master_only_ioctls() {
+    let s = setup();
+    s.emulator.revoke_master();
+    let err = s
+        .device
+        .set_crtc(s.crtc, None, (0, 0), &[], None)
+        .unwrap_err();
+    assert!(MasterLost::is(&err));
+    assert!(MasterLost::check(&s.device, &err));
+    assert_eq!(err.raw_os_error(), Some(Errno::ACCESS.raw_os_error()));
+    // Queries still work without the lock
+    assert!(s.device.get_crtc(s.crtc).is_ok());
+    assert_eq!(errno(s.device.acquire_master_lock()), Errno::BUSY);
+
+    s.emulator.restore_master();
+    s.device.acquire_master_lock().unwrap();
+    s.device.set_crtc(s.crtc, None, (0, 0), &[], None).unwrap();
+}
+
+#[test]
+fn nested_master_guards() {
+    let s = setup();
+    s.device.release_master_lock().unwrap();
+
+    let outer = s.device.lock_master().unwrap();
+    assert!(outer.acquired());
+    let inner = s.device.lock_master().unwrap();
+    assert!(!inner.acquired());
+    drop(inner);
+    assert!(s.device.is_master());
+    s.device.lock_master().unwrap().release().unwrap();
+    assert!(s.device.is_master());
+
+    drop(outer);
+    assert!(!s.device.is_master());
+}
+
+#[test]
+fn master_revoked_while_locked() {
+    let s = setup();
+    s.device.release_master_lock().unwrap();
+
+    let master = s.device.lock_master().unwrap();
+    assert!(master.acquired());
+    s.emulator.revoke_master();
+    let err = master
+        .set_crtc(s.crtc, None, (0, 0), &[], None)
+        .unwrap_err();
+    assert!(MasterLost::check(&*master, &err));
+    // The lock is gone, so there is nothing left to release
+    assert_eq!(errno(master.release()), Errno::INVAL);
+    assert!(!s.device.is_master());
+
+    // Dropping a guard of a revoked lock ignores the error
+    s.emulator.restore_master();
+    let master = s.device.lock_master().unwrap();
+    s.emulator.revoke_master();
+    drop(master);
+    s.emulator.restore_master();
+    assert!(s.device.lock_master().unwrap().acquired());
+}
+
+#[test]
+fn property_blobs() {
+    let s = setup();
+    let data = [1u8, 2, 3, 4];
+    let blob = match s.device.create_property_blob(&data).unwrap() {
+        property::Value::Blob(id) => id,
+        value => panic!("unexpected value {:?}", value),
+    };
+    assert_eq!(s.device.get_property_blob(blob).unwrap(), data);
+    s.device.destroy_property_blob(blob).unwrap();
+    assert_eq!(errno(s.device.get_property_blob(blob)), Errno::NOENT);
+}
+
+#[test]
+fn dumb_buffers_are_mappable() {
+    let s = setup();
+    let mut buffer = s
+        .device
+        .create_dumb_buffer((64, 64), DrmFourcc::Xrgb8888, 32)
+        .unwrap();
+    s.device.map_dumb_buffer(&mut buffer).unwrap().as_mut()[..4].copy_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(
+        &s.device.map_dumb_buffer(&mut buffer).unwrap().as_ref()[..4],
+        &[1, 2, 3, 4]
+    );
+    s.device.destroy_dumb_buffer(buffer).unwrap();
+}
+
+#[test]
+fn prime_export_and_import() {
+    let s = setup();
+    assert_eq!(
+        s.device
+            .get_driver_capability(DriverCapability::Prime)
+            .unwrap(),
+        (ffi::DRM_PRIME_CAP_IMPORT | ffi::DRM_PRIME_CAP_EXPORT) as u64
+    );
+
+    let buffer = s
+        .device
+        .create_dumb_buffer((64, 64), DrmFourcc::Xrgb8888, 32)
+        .unwrap();
+    let handle = buffer.handle();
+    let dmabuf = s.device.buffer_to_prime_fd(handle, crate::CLOEXEC).unwrap();
+    assert_eq!(s.device.prime_fd_to_buffer(dmabuf.as_fd()).unwrap(), handle);
+
+    // The dma-buf outlives the handle
+    s.device.destroy_dumb_buffer(buffer).unwrap();
+    let imported = s.device.prime_fd_to_buffer(dmabuf.as_fd()).unwrap();
+    assert_ne!(imported, handle);
+    assert_eq!(
+        s.device.prime_fd_to_buffer(dmabuf.as_fd()).unwrap(),
+        imported
+    );
+
+    // Files that are not exported buffers are rejected
+    assert_eq!(
+        errno(s.device.prime_fd_to_buffer(s.device.as_fd())),
+        Errno::INVAL
+    );
+}
+
+#[test]
+fn gem_names() {
+    let s = setup();
+    let buffer = s
+        .device
+        .create_dumb_buffer((64, 64), DrmFourcc::Xrgb8888, 32)
+        .unwrap();
+    let name = s.device.flink_buffer(buffer.handle()).unwrap();
+    assert_eq!(s.device.flink_buffer(buffer.handle()).unwrap(), name);
+
+    let (handle, size) = s.device.open_buffer(name).unwrap();
+    assert_ne!(handle, buffer.handle());
+    assert_eq!(size, 64 * 64 * 4);
+    let (other, _) = s.device.open_buffer(name).unwrap();
+    assert_ne!(other, handle);
+
+    // The name stays valid while any handle is open
+    s.device.destroy_dumb_buffer(buffer).unwrap();
+    s.device.close_buffer(other).unwrap();
+    let (other, _) = s.device.open_buffer(name).unwrap();
+    s.device.close_buffer(other).unwrap();
+    s.device.close_buffer(handle).unwrap();
+    assert_eq!(errno(s.device.open_buffer(name)), Errno::NOENT);
+}
+
+#[test]
+fn driver_capabilities() {
+    let s = setup();
+    let caps = s.device.get_driver_capabilities().unwrap();
+    assert_eq!(caps.dumb_buffer, Some(true));
+    assert_eq!(caps.dumb_preferred_depth, Some(24));
+    assert_eq!(caps.prime_import, Some(true));
+    assert_eq!(caps.prime_export, Some(true));
+    assert_eq!(caps.cursor_size, Some((64, 64)));
+    assert_eq!(caps.atomic_async_page_flip, Some(false));
+
+    // Capabilities unknown to the kernel are reported as `None`
+    let mut builder = Emulator::builder();
+    builder.driver_capability(DriverCapability::AtomicASyncPageFlip, None);
+    builder.driver_capability(DriverCapability::CursorHeight, None);
+    builder.driver_capability(DriverCapability::Prime, Some(1));
+    let (device, _emulator) = builder.build().unwrap();
+    let caps = device.get_driver_capabilities().unwrap();
+    assert_eq!(caps.atomic_async_page_flip, None);
+    assert_eq!(caps.cursor_size, None);
+    assert_eq!(caps.prime_import, Some(true));
+    assert_eq!(caps.prime_export, Some(false));
+    assert_eq!(caps.dumb_buffer, Some(true));
+}
+
+#[test]
+fn atomic_properties_need_the_client_cap() {
+    let s = setup();
+    let props = s.device.get_properties(s.crtc).unwrap();
+    assert!(!props.as_hashmap(&s.device).unwrap().contains_key("ACTIVE"));
+
+    s.device
+        .set_client_capability(ClientCapability::Atomic, true)
+        .unwrap();
+    let active = find_property(&s.device, s.crtc, "ACTIVE");
+    s.device
+        .set_client_capability(ClientCapability::Atomic, false)
+        .unwrap();
+
+    let mut req = AtomicModeReq::new();
+    req.add_property(s.crtc, active, property::Value::Boolean(false));
+    let commit = s
+        .device
+        .atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req);
+    assert_eq!(errno(commit), Errno::INVAL);
+}
+
+#[test]
+fn atomic_modeset() {
+    let s = setup();
+    s.device
+        .set_client_capability(ClientCapability::UniversalPlanes, true)
+        .unwrap();
+    s.device
+        .set_client_capability(ClientCapability::Atomic, true)
+        .unwrap();
+    let fb = framebuffer(&s.device);
+    let blob = s.device.create_property_blob(&s.mode).unwrap();
+
+    let mut req = AtomicModeReq::new();
+    let crtc_id = find_property(&s.device, s.connector, "CRTC_ID");
+    req.add_property(s.connector, crtc_id, property::Value::CRTC(Some(s.crtc)));
+    let mode_id = find_property(&s.device, s.crtc, "MODE_ID");
+    req.add_property(s.crtc, mode_id, blob);
+    let active = find_property(&s.device, s.crtc, "ACTIVE");
+    req.add_property(s.crtc, active, property::Value::Boolean(true));
+    let fb_id = find_property(&s.device, s.plane, "FB_ID");
+    req.add_property(s.plane, fb_id, property::Value::Framebuffer(Some(fb)));
+    let plane_crtc = find_property(&s.device, s.plane, "CRTC_ID");
+    req.add_property(s.plane, plane_crtc, property::Value::CRTC(Some(s.crtc)));
+    for (name, value) in [("SRC_W", 1920 << 16), ("SRC_H", 1080 << 16)] {
+        let prop = find_property(&s.device, s.plane, name);
+        req.add_property(s.plane, prop, property::Value::UnsignedRange(value));
+    }
+
+    // Modesets must be allowed explicitly
+    let commit = s
+        .device
+        .atomic_commit(AtomicCommitFlags::empty(), req.clone());
+    assert_eq!(errno(commit), Errno::INVAL);
+
+    // Test commits leave the state alone
+    let flags = AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY;
+    s.device.atomic_commit(flags, req.clone()).unwrap();
+    assert!(!s.emulator.is_active(s.crtc));
+
+    s.device
+        .atomic_commit(AtomicCommitFlags::ALLOW_MODESET, req)
+        .unwrap();
+    assert!(s.emulator.is_active(s.crtc));
+    assert_eq!(s.emulator.crtc_mode(s.crtc), Some(s.mode));
+    assert_eq!(s.emulator.framebuffer(s.plane), Some(fb));
+}
+
+#[test]
+fn atomic_rejects_a_connector_without_crtc_mode() {
+    let s = setup();
+    s.device
+        .set_client_capability(ClientCapability::Atomic, true)
+        .unwrap();
+
+    let mut req = AtomicModeReq::new();
+    let crtc_id = find_property(&s.device, s.connector, "CRTC_ID");
+    req.add_property(s.connector, crtc_id, property::Value::CRTC(Some(s.crtc)));
+    let flags = AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY;
+    assert_eq!(errno(s.device.atomic_commit(flags, req)), Errno::INVAL);
+}
+
+#[test]
+fn atomic_commit_checks_the_array_lengths() {
+    let s = setup();
+    let fd = s.device.as_fd();
+    let crtc = u32::from(s.crtc);
+    let flags = ffi::DRM_MODE_ATOMIC_TEST_ONLY;
+    let commit = |objs: &mut [u32], counts: &mut [u32], props: &mut [u32], values: &mut [u64]| {
+        ffi::mode::atomic_commit(fd, flags, objs, counts, props, values, 0)
+    };
+
+    // One more property than counted
+    let result = commit(&mut [crtc], &mut [1], &mut [1, 2], &mut [0, 0]);
+    assert_eq!(errno(result), Errno::INVAL);
+    // More values than properties
+    let result = commit(&mut [crtc], &mut [1], &mut [1], &mut [0, 0]);
+    assert_eq!(errno(result), Errno::INVAL);
+    // A count without an object
+    let result = commit(&mut [crtc], &mut [0, 1], &mut [1], &mut [0]);
+    assert_eq!(errno(result), Errno::INVAL);
+    // Counts adding up past the end of the arrays
+    let result = commit(&mut [crtc, crtc], &mut [u32::MAX, 2], &mut [1], &mut [0]);
+    assert_eq!(errno(result), Errno::INVAL);
+}
+
+#[test]
+fn more_than_32_crtcs() {
+    let mut builder = Emulator::builder();
+    let crtcs: Vec<_> = (0..33).map(|_| builder.crtc()).collect();
+    // The last CRTC cannot be represented in a mask
+    let encoder = builder.encoder(encoder::Kind::Virtual, &crtcs[31..]);
+    let (device, _emulator) = builder.build().unwrap();
+
+    let resources = device.resource_handles().unwrap();
+    let info = device.get_encoder(encoder).unwrap();
+    assert_eq!(
+        resources.filter_crtcs(info.possible_crtcs()),
+        vec![crtcs[31]]
+    );
+}
+
+#[test]
+fn more_than_32_encoders() {
+    let mut builder = Emulator::builder();
+    let crtc = builder.crtc();
+    let encoders: Vec<_> = (0..34)
+        .map(|_| builder.encoder(encoder::Kind::Virtual, &[crtc]))
+        .collect();
+    // Only the first of these encoders can be represented in a mask
+    builder.encoder_clones(&encoders[31..]);
+    let (device, _emulator) = builder.build().unwrap();
+
+    let resources = device.resource_handles().unwrap();
+    let clones = device.get_encoder(encoders[31]).unwrap().possible_clones();
+    assert_eq!(clones.bits(), 1 << 31);
+    assert!(clones.contains_index(31) && !clones.contains_index(32));
+    assert_eq!(resources.filter_encoders(clones), vec![encoders[31]]);
+
+    let clones = device.get_encoder(encoders[33]).unwrap().possible_clones();
+    assert!(resources.filter_encoders(clones).is_empty());
+}
+
+#[test]
+fn plane_capabilities() {
+    use crate::control::plane::{BlendMode, Rotation, Zpos};
+    use crate::DriverCapability;
+
+    let mut builder = Emulator::builder();
+    let crtc = builder.crtc();
+    let formats = [DrmFourcc::Xrgb8888];
+    let primary = builder.plane(PlaneType::Primary, &[crtc], &formats);
+    let overlay = builder.plane(PlaneType::Overlay, &[crtc], &formats);
+    let cursor = builder.plane(PlaneType::Cursor, &[crtc], &formats);
+    let hinted_cursor = builder.plane(PlaneType::Cursor, &[crtc], &formats);
+    builder.driver_capability(DriverCapability::CursorWidth, Some(64));
+    builder.driver_capability(DriverCapability::CursorHeight, Some(128));
+
+    let rotations = vec![
+        (0, "rotate-0".into()),
+        (2, "rotate-180".into()),
+        (4, "reflect-x".into()),
+    ];
+    builder.property(primary, "rotation", PropertyKind::Bitmask(rotations), 1);
+    builder.immutable_property(primary, "zpos", PropertyKind::Range(0, 0), 0);
+    builder.property(overlay, "zpos", PropertyKind::Range(1, 3), 1);
+    let blend_modes = vec![(0, "None".into()), (1, "Pre-multiplied".into())];
+    builder.property(
+        overlay,
+        "pixel blend mode",
+        PropertyKind::Enum(blend_modes),
+        1,
+    );
+    // struct drm_plane_size_hint { __u16 width, height; }
+    let hints: Vec<u8> = [(64u16, 64u16), (256, 128)]
+        .iter()
+        .flat_map(|(w, h)| [w.to_ne_bytes(), h.to_ne_bytes()].concat())
+        .collect();
+    let hints = builder.blob(&hints);
+    builder.immutable_property(hinted_cursor, "SIZE_HINTS", PropertyKind::Blob, hints);
+    let (device, _emulator) = builder.build().unwrap();
+    device
+        .set_client_capability(ClientCapability::UniversalPlanes, true)
+        .unwrap();
+
+    let caps = device.get_plane_capabilities(primary).unwrap();
+    assert_eq!(caps.kind, PlaneType::Primary);
+    assert_eq!(caps.formats, [DrmFourcc::Xrgb8888 as u32]);
+    assert_eq!(
+        caps.rotations,
+        Rotation::ROTATE_0 | Rotation::ROTATE_180 | Rotation::REFLECT_X
+    );
+    let zpos = Zpos {
+        min: 0,
+        max: 0,
+        mutable: false,
+    };
+    assert_eq!(caps.zpos, Some(zpos));
+    assert!(caps.blend_modes.is_empty());
+
+    let caps = device.get_plane_capabilities(overlay).unwrap();
+    assert_eq!(caps.kind, PlaneType::Overlay);
+    assert_eq!(caps.rotations, Rotation::ROTATE_0);
+    let zpos = Zpos {
+        min: 1,
+        max: 3,
+        mutable: true,
+    };
+    assert_eq!(caps.zpos, Some(zpos));
+    assert_eq!(
+        caps.blend_modes,
+        [BlendMode::None, BlendMode::PreMultiplied]
+    );
+    assert!(caps.size_hints.is_empty());
+
+    let caps = device.get_plane_capabilities(hinted_cursor).unwrap();
+    assert_eq!(caps.kind, PlaneType::Cursor);
+    assert_eq!(caps.size_hints, [(64, 64), (256, 128)]);
+    // Cursor planes without hints fall back to the cursor size of the driver
+    let caps = device.get_plane_capabilities(cursor).unwrap();
+    assert_eq!(caps.size_hints, [(64, 128)]);
+    assert_eq!(caps.zpos, None);
+}
+
+#[test]
+fn mode_timings() {
+    let mode = mode(3840, 2160, 60);
+    assert_eq!(mode.size(), (3840, 2160));
+    assert_eq!(mode.vrefresh(), 60);
+
+    let largest = super::mode(u16::MAX - 160, u16::MAX - 30, 60);
+    assert_eq!(largest.size(), (u16::MAX - 160, u16::MAX - 30));
+}
+
+#[test]
+#[should_panic]
+fn mode_too_large() {
+    mode(u16::MAX, 1080, 60);
+}
+
+/// A device like [`setup()`] with two modes and a range property on the CRTC.
+fn saved_state_setup() -> (Setup, Mode, property::Handle) {
+    let mut builder = Emulator::builder();
+    let crtc = builder.crtc();
+    let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+    let modes = [mode(1920, 1080, 60), mode(1280, 720, 60)];
+    let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &modes);
+    let plane = builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+    let cookie = builder.property(crtc, "COOKIE", PropertyKind::Range(0, u32::MAX as u64), 0);
+    let (device, emulator) = builder.build().unwrap();
+
+    let setup = Setup {
+        device,
+        emulator,
+        crtc,
+        connector,
+        plane,
+        mode: modes[0],
+    };
+    (setup, modes[1], cookie)
+}
+
+#[test]
+fn saved_state_only_remaps_blob_values() {
+    let (s, other_mode, cookie) = saved_state_setup();
+    s.device
+        .set_client_capability(ClientCapability::Atomic, true)
+        .unwrap();
+    let fb = framebuffer(&s.device);
+    s.device
+        .set_crtc(s.crtc, Some(fb), (0, 0), &[s.connector], Some(s.mode))
+        .unwrap();
+
+    // A plain value which happens to match the ID of a saved blob
+    let mode_id = find_property(&s.device, s.crtc, "MODE_ID");
+    let blob = s.emulator.property_value(s.crtc, mode_id).unwrap();
+    s.device.set_property(s.crtc, cookie, blob).unwrap();
+
+    let saved = SavedState::capture(&s.device).unwrap();
+    assert!(saved.is_atomic());
+    s.device
+        .set_crtc(s.crtc, Some(fb), (0, 0), &[s.connector], Some(other_mode))
+        .unwrap();
+    saved.restore().unwrap();
+
+    assert_eq!(s.emulator.crtc_mode(s.crtc), Some(s.mode));
+    assert_ne!(s.emulator.property_value(s.crtc, mode_id), Some(blob));
+    assert_eq!(s.emulator.property_value(s.crtc, cookie), Some(blob));
+}
+
+#[test]
+fn saved_state_falls_back_to_legacy_restore() {
+    let (s, other_mode, _) = saved_state_setup();
+    s.device
+        .set_client_capability(ClientCapability::Atomic, true)
+        .unwrap();
+    let fb = framebuffer(&s.device);
+    s.device
+        .set_crtc(s.crtc, Some(fb), (0, 0), &[s.connector], Some(s.mode))
+        .unwrap();
+    let saved = SavedState::capture(&s.device).unwrap();
+    assert!(saved.is_atomic());
+
+    // The atomic commit fails without the client capability
+    s.device
+        .set_client_capability(ClientCapability::Atomic, false)
+        .unwrap();
+    s.device
+        .set_crtc(s.crtc, Some(fb), (0, 0), &[s.connector], Some(other_mode))
+        .unwrap();
+    assert_eq!(saved.restore().unwrap(), Restored::Complete);
+    assert_eq!(s.emulator.crtc_mode(s.crtc), Some(s.mode));
+}
+
+#[test]
+fn saved_state_restores_overlays_with_a_known_position() {
+    let mut builder = Emulator::builder();
+    let crtc = builder.crtc();
+    let encoder = builder.encoder(encoder::Kind::TMDS, &[crtc]);
+    let mode = mode(1920, 1080, 60);
+    let connector = builder.connector(connector::Interface::HDMIA, &[encoder], &[mode]);
+    builder.plane(PlaneType::Primary, &[crtc], &[DrmFourcc::Xrgb8888]);
+    let overlay = builder.plane(PlaneType::Overlay, &[crtc], &[DrmFourcc::Xrgb8888]);
+    let (device, emulator) = builder.build().unwrap();
+
+    let fb = framebuffer(&device);
+    device
+        .set_crtc(crtc, Some(fb), (0, 0), &[connector], Some(mode))
+        .unwrap();
+    let crtc_rect = (100, 50, 640, 480);
+    let src_rect = (0, 0, 640 << 16, 480 << 16);
+    device
+        .set_plane(overlay, crtc, Some(fb), 0, crtc_rect, src_rect)
+        .unwrap();
+    let disable = || {
+        device
+            .set_plane(overlay, crtc, None, 0, (0, 0, 0, 0), (0, 0, 0, 0))
+            .unwrap()
+    };
+
+    // Without the atomic properties the position of the overlay is unknown
+    let saved = SavedState::capture(&device).unwrap();
+    disable();
+    assert_eq!(saved.restore().unwrap(), Restored::Partial);
+    assert_eq!(emulator.framebuffer(overlay), None);
+    drop(saved);
+
+    device
+        .set_plane(overlay, crtc, Some(fb), 0, crtc_rect, src_rect)
+        .unwrap();
+    device
+        .set_client_capability(ClientCapability::Atomic, true)
+        .unwrap();
+    let crtc_x = find_property(&device, overlay, "CRTC_X");
+    let saved = SavedState::capture(&device).unwrap();
+    // Falls back to the legacy restore without the client capability
+    device
+        .set_client_capability(ClientCapability::Atomic, false)
+        .unwrap();
+    disable();
+    assert_eq!(saved.restore().unwrap(), Restored::Complete);
+    assert_eq!(emulator.framebuffer(overlay), Some(fb));
+    assert_eq!(emulator.property_value(overlay, crtc_x), Some(100));
+}
+
+#[test]
+fn router_tests_routes_with_a_primary_plane() {
+    let s = setup();
+    s.device
+        .set_client_capability(ClientCapability::Atomic, true)
+        .unwrap();
+    let router = Router::new(&s.device).unwrap().test_only(true);
+
+    // Without a framebuffer the primary plane cannot be tested
+    let err = router.route(&[s.connector]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    let buffer = s
+        .device
+        .create_dumb_buffer((640, 480), DrmFourcc::Xrgb8888, 32)
+        .unwrap();
+    let small = s.device.add_framebuffer(&buffer, 24, 32).unwrap();
+    let router = router.test_framebuffer(Some(small));
+    assert_eq!(router.route(&[s.connector]).unwrap(), None);
+
+    let router = router.test_framebuffer(Some(framebuffer(&s.device)));
+    let routes = router.route(&[s.connector]).unwrap().unwrap();
+    assert_eq!(routes[0].crtc, s.crtc);
+    assert!(!s.emulator.is_active(s.crtc));
+}
@@ -21,9 +21,13 @@
 //!
 //! ## Usage
 //!
-//! To begin using this crate, the [`Device`] trait must be
-//! implemented. See the trait's [example section](trait@Device#example) for
-//! details on how to implement it.
+//! To begin using this crate, a type implementing the [`Device`] trait is
+//! needed. The crate provides [`device::OwnedDevice`] for this purpose, but
+//! the trait can also be implemented on a custom type. See the trait's
+//! [example section](trait@Device#example) for details on how to implement it.
+//!
+//! The device nodes available on the system can be discovered with the
+//! [`node`] module.
 //!
 
 #![warn(missing_docs)]
@@ -32,8 +36,15 @@ pub(crate) mod util;
 
 pub mod buffer;
 pub mod control;
+pub mod device;
+#[cfg(feature = "emulator")]
+pub mod emulator;
+pub mod node;
 
 use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::os::unix::io::OwnedFd;
+use std::sync::Arc;
 use std::time::Duration;
 use std::{
     io,
@@ -42,6 +53,8 @@ use std::{
 
 use rustix::io::Errno;
 
+use crate::node::bus::{BusInfo, PciBusInfo};
+use crate::node::{CreateDrmNodeError, DrmNode};
 use crate::util::*;
 
 pub use drm_ffi::{DRM_CLOEXEC as CLOEXEC, DRM_RDWR as RDWR};
@@ -49,10 +62,10 @@ pub use drm_ffi::{DRM_CLOEXEC as CLOEXEC, DRM_RDWR as RDWR};
 /// This trait should be implemented by any object that acts as a DRM device. It
 /// is a prerequisite for using any DRM functionality.
 ///
-/// This crate does not provide a concrete device object due to the various ways
-/// it can be implemented. The user of this crate is expected to implement it
-/// themselves and derive this trait as necessary. The example below
-/// demonstrates how to do this using a small wrapper.
+/// This crate provides [`device::OwnedDevice`] as a concrete device object, and
+/// implements this trait for [`OwnedFd`], [`File`], as well as for references
+/// and [`Arc`]s of other devices. A custom type can implement it as well, the
+/// example below demonstrates how to do this using a small wrapper.
 ///
 /// # Example
 ///
@@ -115,6 +128,34 @@ pub trait Device: AsFd {
         Ok(())
     }
 
+    /// Acquires the DRM Master lock until the returned guard is dropped.
+    ///
+    /// If the lock is already held, e.g. by an outer guard, it is left alone
+    /// and the returned guard does not release it.
+    ///
+    /// See [`Device::acquire_master_lock`] for the requirements.
+    fn lock_master(&self) -> io::Result<MasterGuard<'_, Self>> {
+        let acquired = !self.is_master();
+        if acquired {
+            self.acquire_master_lock()?;
+        }
+        Ok(MasterGuard {
+            device: self,
+            acquired,
+        })
+    }
+
+    /// Checks whether this file descriptor currently holds the DRM Master lock.
+    ///
+    /// Like libdrm's `drmIsMaster`, this authenticates the invalid token `0`,
+    /// which only fails with `EACCES` for clients that are not master.
+    fn is_master(&self) -> bool {
+        match drm_ffi::auth::auth_magic_token(self.as_fd(), 0) {
+            Ok(_) => true,
+            Err(err) => err.raw_os_error() != Some(Errno::ACCESS.raw_os_error()),
+        }
+    }
+
     /// Generates an [`AuthToken`] for this process.
     #[deprecated(note = "Consider opening a render node instead.")]
     fn generate_auth_token(&self) -> io::Result<AuthToken> {
@@ -144,6 +185,28 @@ pub trait Device: AsFd {
         Ok(bus_id)
     }
 
+    /// Gets structured information about the bus this device is attached to.
+    ///
+    /// The information is read from sysfs. If that is not available, the bus
+    /// ID of PCI devices is parsed instead, without any device information.
+    fn get_bus_info(&self) -> io::Result<BusInfo> {
+        let sysfs_err = match DrmNode::from_file(self.as_fd()) {
+            Ok(node) => match node.bus_info() {
+                Ok(info) => return Ok(info),
+                Err(err) => err,
+            },
+            Err(CreateDrmNodeError::Io(err)) => err,
+            Err(CreateDrmNodeError::NotDrmNode) => Errno::NODEV.into(),
+        };
+
+        let bus_id = self.get_bus_id()?;
+        bus_id
+            .to_str()
+            .and_then(PciBusInfo::from_unique)
+            .map(|bus| BusInfo::Pci(bus, None))
+            .ok_or(sysfs_err)
+    }
+
     /// Check to see if our [`AuthToken`] has been authenticated
     /// by the DRM Master
     fn authenticated(&self) -> io::Result<bool> {
@@ -157,6 +220,45 @@ pub trait Device: AsFd {
         Ok(cap.value)
     }
 
+    /// Queries all driver capabilities at once.
+    ///
+    /// Capabilities unknown to the kernel are reported as `None`.
+    fn get_driver_capabilities(&self) -> io::Result<DriverCapabilities> {
+        let cap = |cap: DriverCapability| match self.get_driver_capability(cap) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if err.raw_os_error() == Some(rustix::io::Errno::INVAL.raw_os_error()) => {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        };
+        let flag = |c: DriverCapability| cap(c).map(|value| value.map(|value| value != 0));
+
+        let prime = cap(DriverCapability::Prime)?;
+        let cursor_width = cap(DriverCapability::CursorWidth)?;
+        let cursor_height = cap(DriverCapability::CursorHeight)?;
+
+        Ok(DriverCapabilities {
+            dumb_buffer: flag(DriverCapability::DumbBuffer)?,
+            dumb_preferred_depth: cap(DriverCapability::DumbPreferredDepth)?
+                .map(|depth| depth as u32),
+            dumb_prefer_shadow: flag(DriverCapability::DumbPreferShadow)?,
+            prime_import: prime.map(|prime| prime & drm_ffi::DRM_PRIME_CAP_IMPORT as u64 != 0),
+            prime_export: prime.map(|prime| prime & drm_ffi::DRM_PRIME_CAP_EXPORT as u64 != 0),
+            vblank_high_crtc: flag(DriverCapability::VBlankHighCRTC)?,
+            monotonic_timestamp: flag(DriverCapability::MonotonicTimestamp)?,
+            async_page_flip: flag(DriverCapability::ASyncPageFlip)?,
+            atomic_async_page_flip: flag(DriverCapability::AtomicASyncPageFlip)?,
+            cursor_size: cursor_width
+                .zip(cursor_height)
+                .map(|(w, h)| (w as u32, h as u32)),
+            addfb2_modifiers: flag(DriverCapability::AddFB2Modifiers)?,
+            page_flip_target: flag(DriverCapability::PageFlipTarget)?,
+            crtc_in_vblank_event: flag(DriverCapability::CRTCInVBlankEvent)?,
+            syncobj: flag(DriverCapability::SyncObj)?,
+            timeline_syncobj: flag(DriverCapability::TimelineSyncObj)?,
+        })
+    }
+
     /// # Possible errors:
     ///   - `EFAULT`: Kernel could not copy fields into userspace
     #[allow(missing_docs)]
@@ -182,6 +284,13 @@ pub trait Device: AsFd {
     }
 
     /// Waits for a vblank.
+    ///
+    /// `high_crtc` is the index of the CRTC in
+    /// [`ResourceHandles::crtcs`](control::ResourceHandles::crtcs), see
+    /// [`ResourceHandles::crtc_index`](control::ResourceHandles::crtc_index),
+    /// and the returned frame counter is only 32 bits wide. Prefer
+    /// [`control::Device::get_crtc_sequence`] and
+    /// [`control::Device::queue_crtc_sequence`] where available.
     fn wait_vblank(
         &self,
         target_sequence: VblankWaitTarget,
@@ -221,6 +330,11 @@ pub trait Device: AsFd {
     }
 }
 
+impl Device for OwnedFd {}
+impl Device for File {}
+impl<T: Device + ?Sized> Device for &T {}
+impl<T: Device + ?Sized> Device for Arc<T> {}
+
 /// An authentication token, unique to the file descriptor of the device.
 ///
 /// This token can be sent to another process that owns the DRM Master lock to
@@ -235,6 +349,102 @@ pub trait Device: AsFd {
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct AuthToken(u32);
 
+/// Holds the DRM Master lock of a device and releases it when dropped.
+///
+/// Returned by [`Device::lock_master`]. Only the guard that acquired the lock
+/// releases it, guards created while it was already held do nothing. Errors
+/// when releasing the lock on drop are ignored, use [`MasterGuard::release`]
+/// to observe them.
+#[derive(Debug)]
+#[must_use = "the DRM Master lock is released when the guard is dropped"]
+pub struct MasterGuard<'a, D: Device + ?Sized> {
+    device: &'a D,
+    acquired: bool,
+}
+
+impl<'a, D: Device + ?Sized> MasterGuard<'a, D> {
+    /// Returns whether this guard acquired the lock.
+    pub fn acquired(&self) -> bool {
+        self.acquired
+    }
+
+    /// Releases the DRM Master lock, if this guard acquired it.
+    pub fn release(self) -> io::Result<()> {
+        let (device, acquired) = (self.device, self.acquired);
+        std::mem::forget(self);
+        if acquired {
+            device.release_master_lock()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a, D: Device + ?Sized> std::ops::Deref for MasterGuard<'a, D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        self.device
+    }
+}
+
+impl<'a, D: Device + ?Sized> Drop for MasterGuard<'a, D> {
+    fn drop(&mut self) {
+        if self.acquired {
+            let _ = self.device.release_master_lock();
+        }
+    }
+}
+
+/// Identifies errors of modesetting operations that require the DRM Master
+/// lock when it is not held, e.g. because the session was switched away.
+///
+/// These operations fail with the `EACCES` error of the kernel, which is
+/// passed on unchanged. Use [`MasterLost::check`] to tell it apart from other
+/// causes of `EACCES`:
+///
+/// ```
+/// # use std::io;
+/// # fn modeset(device: &impl drm::Device) -> io::Result<()> { Ok(()) }
+/// fn commit(device: &impl drm::Device) -> io::Result<()> {
+///     match modeset(device) {
+///         Err(err) if drm::MasterLost::check(device, &err) => { /* wait for the session to return */ }
+///         result => result?,
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct MasterLost;
+
+impl MasterLost {
+    /// Returns whether an error is the `EACCES` of a missing DRM Master lock.
+    ///
+    /// This only looks at the error code, so it also matches the `EACCES` of
+    /// unrelated causes, e.g. ioctls that need `CAP_SYS_ADMIN` or are not
+    /// allowed on render nodes. Prefer [`MasterLost::check`] when the device
+    /// is at hand.
+    pub fn is(err: &io::Error) -> bool {
+        err.raw_os_error() == Some(Errno::ACCESS.raw_os_error())
+    }
+
+    /// Returns whether an error of an operation on `device` was caused by not
+    /// holding the DRM Master lock.
+    ///
+    /// In addition to [`MasterLost::is`], this confirms that `device` is not
+    /// master anymore. It may still match operations which fail with `EACCES`
+    /// for other reasons while the lock is not held.
+    pub fn check<D: Device + ?Sized>(device: &D, err: &io::Error) -> bool {
+        Self::is(err) && !device.is_master()
+    }
+}
+
+impl From<MasterLost> for io::Error {
+    fn from(_: MasterLost) -> Self {
+        Errno::ACCESS.into()
+    }
+}
+
 /// Driver version of a device.
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Driver {
@@ -267,18 +477,19 @@ impl Driver {
 #[allow(clippy::upper_case_acronyms)]
 #[repr(u64)]
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DriverCapability {
     /// DumbBuffer support for scanout
     DumbBuffer = drm_ffi::DRM_CAP_DUMB_BUFFER as u64,
-    /// Unknown
+    /// Vblank requests can target CRTCs beyond the first two
     VBlankHighCRTC = drm_ffi::DRM_CAP_VBLANK_HIGH_CRTC as u64,
     /// Preferred depth to use for dumb buffers
     DumbPreferredDepth = drm_ffi::DRM_CAP_DUMB_PREFERRED_DEPTH as u64,
-    /// Unknown
+    /// Whether rendering into a shadow buffer is preferred over dumb buffers
     DumbPreferShadow = drm_ffi::DRM_CAP_DUMB_PREFER_SHADOW as u64,
-    /// PRIME handles are supported
+    /// PRIME handles are supported, a bitmask of import and export support
     Prime = drm_ffi::DRM_CAP_PRIME as u64,
-    /// Unknown
+    /// Event timestamps use the monotonic clock
     MonotonicTimestamp = drm_ffi::DRM_CAP_TIMESTAMP_MONOTONIC as u64,
     /// Asynchronous page flipping support
     ASyncPageFlip = drm_ffi::DRM_CAP_ASYNC_PAGE_FLIP as u64,
@@ -290,7 +501,7 @@ pub enum DriverCapability {
     CursorHeight = drm_ffi::DRM_CAP_CURSOR_HEIGHT as u64,
     /// Create framebuffers with modifiers
     AddFB2Modifiers = drm_ffi::DRM_CAP_ADDFB2_MODIFIERS as u64,
-    /// Unknown
+    /// Page flips can target a specific vblank sequence
     PageFlipTarget = drm_ffi::DRM_CAP_PAGE_FLIP_TARGET as u64,
     /// Uses the CRTC's ID in vblank events
     CRTCInVBlankEvent = drm_ffi::DRM_CAP_CRTC_IN_VBLANK_EVENT as u64,
@@ -300,6 +511,45 @@ pub enum DriverCapability {
     TimelineSyncObj = drm_ffi::DRM_CAP_SYNCOBJ_TIMELINE as u64,
 }
 
+/// A snapshot of all capabilities of a driver.
+///
+/// Each field is `None` if the kernel does not know about the capability.
+#[derive(Debug, Copy, Clone, Default, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DriverCapabilities {
+    /// Dumb buffers can be created and used for scanout
+    pub dumb_buffer: Option<bool>,
+    /// Preferred depth to use for dumb buffers, `0` if there is no preference
+    pub dumb_preferred_depth: Option<u32>,
+    /// Rendering into a shadow buffer is preferred over rendering directly
+    /// into dumb buffers, e.g. because they are slow to read back
+    pub dumb_prefer_shadow: Option<bool>,
+    /// Buffers can be imported from PRIME file descriptors
+    pub prime_import: Option<bool>,
+    /// Buffers can be exported as PRIME file descriptors
+    pub prime_export: Option<bool>,
+    /// Vblank requests can target CRTCs beyond the first two
+    pub vblank_high_crtc: Option<bool>,
+    /// Event timestamps use the monotonic clock instead of the realtime clock
+    pub monotonic_timestamp: Option<bool>,
+    /// Legacy page flips can be asynchronous
+    pub async_page_flip: Option<bool>,
+    /// Atomic commits can perform asynchronous page flips
+    pub atomic_async_page_flip: Option<bool>,
+    /// Size of cursor buffers as `(width, height)`
+    pub cursor_size: Option<(u32, u32)>,
+    /// Framebuffers can be created with format modifiers
+    pub addfb2_modifiers: Option<bool>,
+    /// Page flips can target a specific vblank sequence
+    pub page_flip_target: Option<bool>,
+    /// Vblank and page flip events carry the ID of their CRTC
+    pub crtc_in_vblank_event: Option<bool>,
+    /// Synchronization objects are supported
+    pub syncobj: Option<bool>,
+    /// Timeline synchronization objects are supported
+    pub timeline_syncobj: Option<bool>,
+}
+
 /// Used to enable/disable capabilities for the process.
 #[repr(u64)]
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
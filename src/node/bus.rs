@@ -0,0 +1,275 @@
+//! # Bus
+//!
+//! Structured information about the bus a DRM device is attached to.
+//!
+//! This is the equivalent of the bus and device information that libdrm's
+//! `drmGetDevice2` reports. It is gathered from sysfs, with the device's
+//! unique string (see [`crate::Device::get_bus_id`]) serving as a fallback for
+//! PCI devices when sysfs is not available.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Information about the bus a device is attached to.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum BusInfo {
+    /// A PCI device, with its device information if it could be read
+    Pci(PciBusInfo, Option<PciDeviceInfo>),
+    /// A USB device, with its device information if it could be read
+    Usb(UsbBusInfo, Option<UsbDeviceInfo>),
+    /// A platform device
+    Platform(PlatformBusInfo, PlatformDeviceInfo),
+    /// A device on NVIDIA's host1x bus
+    Host1x(PlatformBusInfo, PlatformDeviceInfo),
+}
+
+impl BusInfo {
+    /// Reads the bus information of a device from its sysfs directory.
+    ///
+    /// `device_dir` is the physical device, i.e. the target of the `device`
+    /// link of a node in `/sys/class/drm`.
+    pub fn from_sysfs<P: AsRef<Path>>(device_dir: P) -> io::Result<BusInfo> {
+        let mut dir = fs::canonicalize(device_dir.as_ref())?;
+
+        loop {
+            let subsystem = fs::read_link(dir.join("subsystem"))?;
+            match subsystem.file_name().and_then(|name| name.to_str()) {
+                Some("pci") => {
+                    let bus = file_name(&dir)
+                        .and_then(PciBusInfo::from_address)
+                        .ok_or_else(|| invalid_data("invalid PCI address"))?;
+                    return Ok(BusInfo::Pci(bus, PciDeviceInfo::from_sysfs(&dir).ok()));
+                }
+                Some("usb") => {
+                    // The DRM device may be bound to an interface of the USB
+                    // device, which does not carry the bus information itself.
+                    if !dir.join("busnum").exists() {
+                        if let Some(parent) = dir.parent().filter(|p| p.join("busnum").exists()) {
+                            dir = parent.to_path_buf();
+                        }
+                    }
+                    let bus = UsbBusInfo::from_sysfs(&dir)?;
+                    return Ok(BusInfo::Usb(bus, UsbDeviceInfo::from_sysfs(&dir).ok()));
+                }
+                Some("platform") => {
+                    let (bus, dev) = read_platform(&dir)?;
+                    return Ok(BusInfo::Platform(bus, dev));
+                }
+                Some("host1x") => {
+                    let (bus, dev) = read_platform(&dir)?;
+                    return Ok(BusInfo::Host1x(bus, dev));
+                }
+                // virtio devices report the bus of the underlying transport
+                Some("virtio") => {
+                    dir = dir
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .ok_or_else(|| invalid_data("virtio device without parent"))?;
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "unsupported bus type",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Returns the PCI device information, if this is a PCI device and it is
+    /// available.
+    pub fn pci_device(&self) -> Option<&PciDeviceInfo> {
+        match self {
+            BusInfo::Pci(_, dev) => dev.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this device was used as VGA device by the firmware at
+    /// boot.
+    pub fn is_boot_vga(&self) -> bool {
+        self.pci_device().map_or(false, |dev| dev.boot_vga)
+    }
+}
+
+/// The location of a device on the PCI bus.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PciBusInfo {
+    /// Domain (segment) number
+    pub domain: u16,
+    /// Bus number
+    pub bus: u8,
+    /// Device number
+    pub dev: u8,
+    /// Function number
+    pub func: u8,
+}
+
+impl PciBusInfo {
+    /// Parses a PCI address of the form `0000:01:00.0`.
+    pub fn from_address(address: &str) -> Option<PciBusInfo> {
+        let (domain, rest) = address.split_once(':')?;
+        let (bus, rest) = rest.split_once(':')?;
+        let (dev, func) = rest.split_once('.')?;
+
+        Some(PciBusInfo {
+            domain: u16::from_str_radix(domain, 16).ok()?,
+            bus: u8::from_str_radix(bus, 16).ok()?,
+            dev: u8::from_str_radix(dev, 16).ok()?,
+            func: func.parse().ok()?,
+        })
+    }
+
+    /// Parses the unique string of a PCI device, as returned by
+    /// [`crate::Device::get_bus_id`], e.g. `pci:0000:01:00.0`.
+    pub fn from_unique(unique: &str) -> Option<PciBusInfo> {
+        Self::from_address(unique.strip_prefix("pci:")?)
+    }
+}
+
+impl fmt::Display for PciBusInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{}",
+            self.domain, self.bus, self.dev, self.func
+        )
+    }
+}
+
+/// Identification of a PCI device.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct PciDeviceInfo {
+    /// Vendor ID
+    pub vendor_id: u16,
+    /// Device ID
+    pub device_id: u16,
+    /// Subsystem vendor ID
+    pub subvendor_id: u16,
+    /// Subsystem device ID
+    pub subdevice_id: u16,
+    /// Revision ID
+    pub revision_id: u8,
+    /// Whether the firmware used this device as VGA device at boot
+    pub boot_vga: bool,
+}
+
+impl PciDeviceInfo {
+    fn from_sysfs(dir: &Path) -> io::Result<PciDeviceInfo> {
+        Ok(PciDeviceInfo {
+            vendor_id: read_hex(&dir.join("vendor"))? as u16,
+            device_id: read_hex(&dir.join("device"))? as u16,
+            subvendor_id: read_hex(&dir.join("subsystem_vendor"))? as u16,
+            subdevice_id: read_hex(&dir.join("subsystem_device"))? as u16,
+            revision_id: read_hex(&dir.join("revision"))? as u8,
+            boot_vga: read_trimmed(&dir.join("boot_vga")).map_or(false, |v| v == "1"),
+        })
+    }
+}
+
+/// The location of a device on the USB bus.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct UsbBusInfo {
+    /// Bus number
+    pub bus: u8,
+    /// Device address on the bus
+    pub dev: u8,
+    /// Port path of the device, e.g. `1-2.3`
+    pub port: String,
+}
+
+impl UsbBusInfo {
+    fn from_sysfs(dir: &Path) -> io::Result<UsbBusInfo> {
+        Ok(UsbBusInfo {
+            bus: read_dec(&dir.join("busnum"))? as u8,
+            dev: read_dec(&dir.join("devnum"))? as u8,
+            port: file_name(dir).unwrap_or_default().to_owned(),
+        })
+    }
+}
+
+/// Identification of a USB device.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct UsbDeviceInfo {
+    /// Vendor ID
+    pub vendor: u16,
+    /// Product ID
+    pub product: u16,
+}
+
+impl UsbDeviceInfo {
+    fn from_sysfs(dir: &Path) -> io::Result<UsbDeviceInfo> {
+        Ok(UsbDeviceInfo {
+            vendor: read_hex(&dir.join("idVendor"))? as u16,
+            product: read_hex(&dir.join("idProduct"))? as u16,
+        })
+    }
+}
+
+/// The location of a platform or host1x device.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PlatformBusInfo {
+    /// Full name of the device, its devicetree path if available
+    pub fullname: String,
+}
+
+/// Identification of a platform or host1x device.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PlatformDeviceInfo {
+    /// Devicetree compatible strings, most specific first
+    pub compatible: Vec<String>,
+}
+
+fn read_platform(dir: &Path) -> io::Result<(PlatformBusInfo, PlatformDeviceInfo)> {
+    let uevent = fs::read_to_string(dir.join("uevent")).unwrap_or_default();
+    let value = |key: &str| {
+        uevent
+            .lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+    };
+
+    let fullname = match value("OF_FULLNAME") {
+        Some(name) => name.to_owned(),
+        None => file_name(dir)
+            .ok_or_else(|| invalid_data("device without name"))?
+            .to_owned(),
+    };
+
+    let count = value("OF_COMPATIBLE_N")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(0);
+    let compatible = (0..count)
+        .filter_map(|i| value(&format!("OF_COMPATIBLE_{}", i)).map(str::to_owned))
+        .collect();
+
+    Ok((
+        PlatformBusInfo { fullname },
+        PlatformDeviceInfo { compatible },
+    ))
+}
+
+fn file_name(path: &Path) -> Option<&str> {
+    path.file_name()?.to_str()
+}
+
+fn read_trimmed(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_owned())
+}
+
+fn read_hex(path: &Path) -> io::Result<u32> {
+    let value = read_trimmed(path)?;
+    let digits = value.strip_prefix("0x").unwrap_or(&value);
+    u32::from_str_radix(digits, 16).map_err(|_| invalid_data("invalid hexadecimal value"))
+}
+
+fn read_dec(path: &Path) -> io::Result<u32> {
+    read_trimmed(path)?
+        .parse()
+        .map_err(|_| invalid_data("invalid decimal value"))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
@@ -0,0 +1,762 @@
+//! Discovery of DRM device nodes.
+//!
+//! # Summary
+//!
+//! Every DRM device exposes one or more 'device nodes' to userspace, usually
+//! found in `/dev/dri`:
+//!
+//! * Primary - `cardN`, exposes the full modesetting and rendering interface.
+//!   Opening it may grant the DRM Master lock.
+//!
+//! * Control - `controlDN`, a legacy node type that is no longer created by
+//!   recent kernels.
+//!
+//! * Render - `renderDN`, exposes only unprivileged rendering functionality and
+//!   does not require authentication.
+//!
+//! Nodes belonging to the same physical device are correlated through their
+//! device number (`st_rdev`) and the device links found in sysfs. The
+//! [`NodeRoots`] type allows pointing this discovery at an alternate `/dev/dri`
+//! and `/sys` tree, for example a fake one created for testing.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::io::AsFd;
+use std::path::{Path, PathBuf};
+
+use rustix::fs::FileType;
+
+pub mod bus;
+
+use self::bus::BusInfo;
+
+/// The type of a DRM device node.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeType {
+    /// The primary node, named `cardN`
+    Primary,
+    /// The legacy control node, named `controlDN`
+    Control,
+    /// The render node, named `renderDN`
+    Render,
+}
+
+impl NodeType {
+    /// Returns the prefix of the file name of nodes of this type.
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            NodeType::Primary => "card",
+            NodeType::Control => "controlD",
+            NodeType::Render => "renderD",
+        }
+    }
+
+    /// Returns the first minor number used by nodes of this type.
+    pub fn minor_base(&self) -> u32 {
+        match self {
+            NodeType::Primary => 0,
+            NodeType::Control => 64,
+            NodeType::Render => 128,
+        }
+    }
+
+    /// Returns the node type a minor number belongs to.
+    pub fn from_minor(minor: u32) -> Option<NodeType> {
+        match minor {
+            0..=63 => Some(NodeType::Primary),
+            64..=127 => Some(NodeType::Control),
+            128..=255 => Some(NodeType::Render),
+            _ => None,
+        }
+    }
+
+    /// Splits a node file name like `renderD128` into its type and number.
+    pub fn from_name(name: &str) -> Option<(NodeType, u32)> {
+        [NodeType::Primary, NodeType::Control, NodeType::Render]
+            .into_iter()
+            .find_map(|ty| {
+                let num = name.strip_prefix(ty.prefix())?;
+                if num.is_empty() || !num.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                Some((ty, num.parse().ok()?))
+            })
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            NodeType::Primary => 0,
+            NodeType::Control => 1,
+            NodeType::Render => 2,
+        }
+    }
+}
+
+impl fmt::Display for NodeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            NodeType::Primary => "Primary",
+            NodeType::Control => "Control",
+            NodeType::Render => "Render",
+        })
+    }
+}
+
+/// Error from creating a [`DrmNode`]
+#[derive(Debug)]
+pub enum CreateDrmNodeError {
+    /// IO error
+    Io(io::Error),
+    /// The file or device number does not refer to a DRM node
+    NotDrmNode,
+}
+
+impl fmt::Display for CreateDrmNodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::NotDrmNode => write!(f, "the provided file is not a DRM node"),
+        }
+    }
+}
+
+impl error::Error for CreateDrmNodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::NotDrmNode => None,
+        }
+    }
+}
+
+impl From<io::Error> for CreateDrmNodeError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// A DRM device node, identified by its device number.
+///
+/// This does not hold the node open; it can be used to compare nodes, find
+/// related nodes of the same device and look up their paths.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+pub struct DrmNode {
+    dev: u64,
+    ty: NodeType,
+}
+
+impl DrmNode {
+    /// Creates a [`DrmNode`] from an open file descriptor.
+    pub fn from_file<A: AsFd>(file: A) -> Result<DrmNode, CreateDrmNodeError> {
+        NodeRoots::default().node_from_file(file)
+    }
+
+    /// Creates a [`DrmNode`] from the path of a device node.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<DrmNode, CreateDrmNodeError> {
+        NodeRoots::default().node_from_path(path)
+    }
+
+    /// Creates a [`DrmNode`] from a device number.
+    ///
+    /// On Linux this verifies through sysfs that the device number belongs to a
+    /// DRM device.
+    pub fn from_dev_id(dev: u64) -> Result<DrmNode, CreateDrmNodeError> {
+        NodeRoots::default().node_from_dev_id(dev)
+    }
+
+    /// Returns the device number of this node.
+    pub fn dev_id(&self) -> u64 {
+        self.dev
+    }
+
+    /// Returns the type of this node.
+    pub fn ty(&self) -> NodeType {
+        self.ty
+    }
+
+    /// Returns the major number of this node.
+    pub fn major(&self) -> u32 {
+        rustix::fs::major(self.dev as _)
+    }
+
+    /// Returns the minor number of this node.
+    pub fn minor(&self) -> u32 {
+        rustix::fs::minor(self.dev as _)
+    }
+
+    /// Returns the path of this node in `/dev/dri`, if it exists.
+    pub fn dev_path(&self) -> Option<PathBuf> {
+        NodeRoots::default().dev_path(self)
+    }
+
+    /// Returns the node of the given type belonging to the same device, if the
+    /// device exposes one.
+    ///
+    /// This can be used to find the render node of an opened primary node.
+    pub fn node_with_type(&self, ty: NodeType) -> io::Result<Option<DrmNode>> {
+        NodeRoots::default().node_with_type(self, ty)
+    }
+
+    /// Returns whether the device of this node exposes a render node.
+    pub fn has_render(&self) -> io::Result<bool> {
+        Ok(self.node_with_type(NodeType::Render)?.is_some())
+    }
+
+    /// Returns information about the bus the device of this node is attached
+    /// to.
+    pub fn bus_info(&self) -> io::Result<BusInfo> {
+        NodeRoots::default().bus_info(self)
+    }
+}
+
+impl fmt::Display for DrmNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.ty.prefix(), self.minor())
+    }
+}
+
+/// The set of nodes exposed by a single physical DRM device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceNodes {
+    sys_path: PathBuf,
+    nodes: [Option<(DrmNode, Option<PathBuf>)>; 3],
+}
+
+impl DeviceNodes {
+    /// Returns the sysfs path of the physical device.
+    pub fn sys_path(&self) -> &Path {
+        &self.sys_path
+    }
+
+    /// Returns the node of the given type, if the device exposes one.
+    pub fn node(&self, ty: NodeType) -> Option<&DrmNode> {
+        self.nodes[ty.index()].as_ref().map(|(node, _)| node)
+    }
+
+    /// Returns the path of the node of the given type, if it exists.
+    pub fn path(&self, ty: NodeType) -> Option<&Path> {
+        self.nodes[ty.index()]
+            .as_ref()
+            .and_then(|(_, path)| path.as_deref())
+    }
+
+    /// Returns the primary node of this device.
+    pub fn primary(&self) -> Option<&DrmNode> {
+        self.node(NodeType::Primary)
+    }
+
+    /// Returns the control node of this device.
+    pub fn control(&self) -> Option<&DrmNode> {
+        self.node(NodeType::Control)
+    }
+
+    /// Returns the render node of this device.
+    pub fn render(&self) -> Option<&DrmNode> {
+        self.node(NodeType::Render)
+    }
+
+    /// Returns an iterator over all nodes of this device.
+    pub fn nodes(&self) -> impl Iterator<Item = &DrmNode> {
+        self.nodes.iter().flatten().map(|(node, _)| node)
+    }
+
+    /// Returns whether the given node belongs to this device.
+    pub fn contains(&self, node: &DrmNode) -> bool {
+        self.node(node.ty()) == Some(node)
+    }
+
+    /// Returns information about the bus this device is attached to.
+    ///
+    /// # Example
+    ///
+    /// Picking the device the firmware used for its boot display:
+    ///
+    /// ```no_run
+    /// let boot_gpu = drm::node::enumerate()
+    ///     .unwrap()
+    ///     .into_iter()
+    ///     .find(|device| device.bus_info().map_or(false, |bus| bus.is_boot_vga()));
+    /// ```
+    pub fn bus_info(&self) -> io::Result<BusInfo> {
+        BusInfo::from_sysfs(&self.sys_path)
+    }
+}
+
+/// Enumerates all DRM devices found in `/dev/dri` and `/sys/class/drm`.
+///
+/// # Example
+///
+/// ```no_run
+/// use drm::node::{self, NodeType};
+///
+/// for device in node::enumerate().unwrap() {
+///     println!(
+///         "{}: primary {:?}, render {:?}",
+///         device.sys_path().display(),
+///         device.path(NodeType::Primary),
+///         device.path(NodeType::Render),
+///     );
+/// }
+/// ```
+pub fn enumerate() -> io::Result<Vec<DeviceNodes>> {
+    NodeRoots::default().enumerate()
+}
+
+/// The directories used to discover device nodes.
+///
+/// By default these are `/dev/dri` and `/sys`. The sysfs tree only needs to
+/// contain `class/drm/<node>/dev`, a file holding the `major:minor` device
+/// number, and `class/drm/<node>/device`, a link to the physical device.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct NodeRoots {
+    dev: PathBuf,
+    sys: PathBuf,
+}
+
+impl Default for NodeRoots {
+    fn default() -> Self {
+        NodeRoots {
+            dev: PathBuf::from("/dev/dri"),
+            sys: PathBuf::from("/sys"),
+        }
+    }
+}
+
+impl NodeRoots {
+    /// Creates a set of roots from the directory holding the device nodes and
+    /// the root of the sysfs tree.
+    pub fn new<D: Into<PathBuf>, S: Into<PathBuf>>(dev_dir: D, sys_dir: S) -> Self {
+        NodeRoots {
+            dev: dev_dir.into(),
+            sys: sys_dir.into(),
+        }
+    }
+
+    /// Returns the directory holding the device nodes.
+    pub fn dev_dir(&self) -> &Path {
+        &self.dev
+    }
+
+    /// Returns the root of the sysfs tree.
+    pub fn sys_dir(&self) -> &Path {
+        &self.sys
+    }
+
+    /// Enumerates all DRM devices, grouping the nodes of each physical device.
+    ///
+    /// Devices are sorted by their lowest node number.
+    pub fn enumerate(&self) -> io::Result<Vec<DeviceNodes>> {
+        let class = self.sys.join("class/drm");
+        let mut devices: Vec<DeviceNodes> = Vec::new();
+
+        let mut entries = fs::read_dir(&class)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let (ty, _) = NodeType::from_name(&name)?;
+                Some((name, ty))
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        for (name, ty) in entries {
+            let node_dir = class.join(&name);
+            let dev = match read_dev_file(&node_dir.join("dev")) {
+                Ok(dev) => dev,
+                Err(_) => continue,
+            };
+            let node = DrmNode { dev, ty };
+            let path = Some(self.dev.join(&name)).filter(|path| path.exists());
+
+            let device_link = node_dir.join("device");
+            let sys_path = fs::canonicalize(&device_link).unwrap_or(device_link);
+
+            match devices.iter_mut().find(|d| d.sys_path == sys_path) {
+                Some(device) => device.nodes[ty.index()] = Some((node, path)),
+                None => {
+                    let mut device = DeviceNodes {
+                        sys_path,
+                        nodes: [None, None, None],
+                    };
+                    device.nodes[ty.index()] = Some((node, path));
+                    devices.push(device);
+                }
+            }
+        }
+
+        devices.sort_by_key(|device| device.nodes().map(DrmNode::minor).min());
+        Ok(devices)
+    }
+
+    /// Returns the device that the given node belongs to.
+    pub fn device_of(&self, node: &DrmNode) -> io::Result<Option<DeviceNodes>> {
+        Ok(self
+            .enumerate()?
+            .into_iter()
+            .find(|device| device.contains(node)))
+    }
+
+    /// Returns the node of the given type belonging to the same device as
+    /// `node`, if the device exposes one.
+    pub fn node_with_type(&self, node: &DrmNode, ty: NodeType) -> io::Result<Option<DrmNode>> {
+        Ok(self
+            .device_of(node)?
+            .and_then(|device| device.node(ty).copied()))
+    }
+
+    /// Returns information about the bus the device of the given node is
+    /// attached to.
+    pub fn bus_info(&self, node: &DrmNode) -> io::Result<BusInfo> {
+        self.device_of(node)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "DRM node not found in sysfs"))?
+            .bus_info()
+    }
+
+    /// Returns the path of the given node, if it exists.
+    pub fn dev_path(&self, node: &DrmNode) -> Option<PathBuf> {
+        // The node name usually matches the minor number, but prefer the name
+        // sysfs reports in case it does not.
+        let name = self
+            .node_name(node.dev)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| node.to_string());
+        Some(self.dev.join(name)).filter(|path| path.exists())
+    }
+
+    /// Creates a [`DrmNode`] from an open file descriptor, see
+    /// [`DrmNode::from_file`].
+    pub fn node_from_file<A: AsFd>(&self, file: A) -> Result<DrmNode, CreateDrmNodeError> {
+        let stat = rustix::fs::fstat(file).map_err(io::Error::from)?;
+        self.node_from_stat(stat)
+    }
+
+    /// Creates a [`DrmNode`] from the path of a device node, see
+    /// [`DrmNode::from_path`].
+    pub fn node_from_path<P: AsRef<Path>>(&self, path: P) -> Result<DrmNode, CreateDrmNodeError> {
+        let stat = rustix::fs::stat(path.as_ref()).map_err(io::Error::from)?;
+        self.node_from_stat(stat)
+    }
+
+    /// Creates a [`DrmNode`] from a device number, verifying that it belongs
+    /// to a DRM device.
+    ///
+    /// On Linux the type of the node is taken from its name in sysfs,
+    /// otherwise it is derived from the minor number.
+    pub fn node_from_dev_id(&self, dev: u64) -> Result<DrmNode, CreateDrmNodeError> {
+        let ty = if cfg!(target_os = "linux") {
+            let name = self.node_name(dev)?.ok_or(CreateDrmNodeError::NotDrmNode)?;
+            NodeType::from_name(&name).map(|(ty, _)| ty)
+        } else {
+            NodeType::from_minor(rustix::fs::minor(dev as _))
+        };
+
+        Ok(DrmNode {
+            dev,
+            ty: ty.ok_or(CreateDrmNodeError::NotDrmNode)?,
+        })
+    }
+
+    fn node_from_stat(&self, stat: rustix::fs::Stat) -> Result<DrmNode, CreateDrmNodeError> {
+        if FileType::from_raw_mode(stat.st_mode as _) != FileType::CharacterDevice {
+            return Err(CreateDrmNodeError::NotDrmNode);
+        }
+
+        #[allow(clippy::unnecessary_cast)]
+        self.node_from_dev_id(stat.st_rdev as u64)
+    }
+
+    /// Looks up the name of the node with the given device number in sysfs.
+    fn node_name(&self, dev: u64) -> io::Result<Option<String>> {
+        let class = self.sys.join("class/drm");
+        for entry in fs::read_dir(&class)? {
+            let name = match entry?.file_name().into_string() {
+                Ok(name) if NodeType::from_name(&name).is_some() => name,
+                _ => continue,
+            };
+            if read_dev_file(&class.join(&name).join("dev")).ok() == Some(dev) {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn read_dev_file(path: &Path) -> io::Result<u64> {
+    let content = fs::read_to_string(path)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid device number");
+    let (major, minor) = content.trim().split_once(':').ok_or_else(invalid)?;
+    let major = major.parse().map_err(|_| invalid())?;
+    let minor = minor.parse().map_err(|_| invalid())?;
+    Ok(rustix::fs::makedev(major, minor) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bus::{PciBusInfo, PlatformBusInfo, PlatformDeviceInfo, UsbBusInfo, UsbDeviceInfo};
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// A fake `/dev/dri` and `/sys` tree with a PCI device exposing a primary
+    /// and a render node.
+    struct FakeTree {
+        root: PathBuf,
+        roots: NodeRoots,
+    }
+
+    impl FakeTree {
+        fn new(name: &str) -> FakeTree {
+            let root = std::env::temp_dir().join(format!("drm-rs-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            let dev = root.join("dev/dri");
+            let sys = root.join("sys");
+            let device = sys.join("devices/pci0000:00/0000:00:02.0");
+            fs::create_dir_all(&dev).unwrap();
+            fs::create_dir_all(sys.join("bus/pci")).unwrap();
+            fs::create_dir_all(&device).unwrap();
+            symlink(sys.join("bus/pci"), device.join("subsystem")).unwrap();
+            for (file, value) in [
+                ("vendor", "0x8086"),
+                ("device", "0x46a6"),
+                ("subsystem_vendor", "0x17aa"),
+                ("subsystem_device", "0x22e4"),
+                ("revision", "0x0c"),
+                ("boot_vga", "1"),
+            ] {
+                fs::write(device.join(file), value).unwrap();
+            }
+
+            let tree = FakeTree {
+                roots: NodeRoots::new(dev, &sys),
+                root,
+            };
+            tree.add_node("card0", "226:0", true);
+            tree.add_node("renderD128", "226:128", true);
+            // Connectors share the class directory but are not nodes
+            fs::create_dir_all(sys.join("class/drm/card0-HDMI-A-1")).unwrap();
+            tree
+        }
+
+        fn add_node(&self, name: &str, dev: &str, in_dev_dir: bool) {
+            let dir = self.roots.sys_dir().join("class/drm").join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("dev"), format!("{}\n", dev)).unwrap();
+            let device = self.roots.sys_dir().join("devices/pci0000:00/0000:00:02.0");
+            symlink(device, dir.join("device")).unwrap();
+            if in_dev_dir {
+                fs::write(self.roots.dev_dir().join(name), "").unwrap();
+            }
+        }
+    }
+
+    impl FakeTree {
+        /// Adds a device below `/sys/devices` on the given subsystem.
+        fn add_device(&self, path: &str, subsystem: &str, files: &[(&str, &str)]) -> PathBuf {
+            let sys = self.roots.sys_dir();
+            let dir = sys.join("devices").join(path);
+            let bus = sys.join("bus").join(subsystem);
+            fs::create_dir_all(&dir).unwrap();
+            fs::create_dir_all(&bus).unwrap();
+            symlink(bus, dir.join("subsystem")).unwrap();
+            for (file, value) in files {
+                fs::write(dir.join(file), value).unwrap();
+            }
+            dir
+        }
+    }
+
+    impl Drop for FakeTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn dev(major: u32, minor: u32) -> u64 {
+        rustix::fs::makedev(major, minor) as u64
+    }
+
+    #[test]
+    fn enumerate_groups_nodes() {
+        let tree = FakeTree::new("enumerate");
+        let devices = tree.roots.enumerate().unwrap();
+        assert_eq!(devices.len(), 1);
+
+        let device = &devices[0];
+        assert_eq!(device.primary().unwrap().dev_id(), dev(226, 0));
+        assert_eq!(device.render().unwrap().dev_id(), dev(226, 128));
+        assert_eq!(device.control(), None);
+        assert_eq!(
+            device.path(NodeType::Render),
+            Some(&*tree.roots.dev_dir().join("renderD128"))
+        );
+    }
+
+    #[test]
+    fn related_nodes_and_paths() {
+        let tree = FakeTree::new("related");
+        let card = tree.roots.node_from_dev_id(dev(226, 0)).unwrap();
+        assert_eq!(card.ty(), NodeType::Primary);
+
+        let render = tree.roots.node_with_type(&card, NodeType::Render).unwrap();
+        assert_eq!(render.map(|node| node.dev_id()), Some(dev(226, 128)));
+        assert_eq!(
+            tree.roots.node_with_type(&card, NodeType::Control).unwrap(),
+            None
+        );
+        assert_eq!(
+            tree.roots.dev_path(&card),
+            Some(tree.roots.dev_dir().join("card0"))
+        );
+
+        let bus = tree.roots.bus_info(&card).unwrap();
+        assert!(bus.is_boot_vga());
+        assert_eq!(bus.pci_device().unwrap().device_id, 0x46a6);
+    }
+
+    #[test]
+    fn unknown_dev_id_is_not_a_node() {
+        let tree = FakeTree::new("unknown");
+        assert!(matches!(
+            tree.roots.node_from_dev_id(dev(226, 1)),
+            Err(CreateDrmNodeError::NotDrmNode)
+        ));
+    }
+
+    #[test]
+    fn node_from_file_and_path() {
+        // Make /dev/null, a real character device, a node of the fake tree
+        let null = rustix::fs::stat("/dev/null").unwrap();
+        let tree = FakeTree::new("file");
+        let rdev = null.st_rdev as u64;
+        let number = format!(
+            "{}:{}",
+            rustix::fs::major(rdev as _),
+            rustix::fs::minor(rdev as _)
+        );
+        tree.add_node("renderD129", &number, false);
+
+        let node = tree.roots.node_from_path("/dev/null").unwrap();
+        assert_eq!(node.ty(), NodeType::Render);
+        assert_eq!(node.dev_id(), rdev);
+        assert_eq!(tree.roots.dev_path(&node), None);
+
+        let file = fs::File::open("/dev/null").unwrap();
+        assert_eq!(tree.roots.node_from_file(&file).unwrap(), node);
+
+        // Regular files are never nodes
+        let regular = tree.roots.dev_dir().join("card0");
+        assert!(matches!(
+            tree.roots.node_from_path(regular),
+            Err(CreateDrmNodeError::NotDrmNode)
+        ));
+    }
+
+    #[test]
+    fn usb_bus_info_of_an_interface() {
+        let tree = FakeTree::new("usb");
+        let files = [
+            ("busnum", "3\n"),
+            ("devnum", "7\n"),
+            ("idVendor", "17e9\n"),
+            ("idProduct", "4301\n"),
+        ];
+        tree.add_device("pci0000:00/0000:00:14.0/usb3/3-2", "usb", &files);
+        let interface = tree.add_device("pci0000:00/0000:00:14.0/usb3/3-2/3-2:1.0", "usb", &[]);
+
+        let bus = BusInfo::from_sysfs(interface).unwrap();
+        let expected = BusInfo::Usb(
+            UsbBusInfo {
+                bus: 3,
+                dev: 7,
+                port: "3-2".to_owned(),
+            },
+            Some(UsbDeviceInfo {
+                vendor: 0x17e9,
+                product: 0x4301,
+            }),
+        );
+        assert_eq!(bus, expected);
+        assert!(!bus.is_boot_vga());
+    }
+
+    #[test]
+    fn platform_and_host1x_bus_info() {
+        let tree = FakeTree::new("platform");
+        let uevent = "DRIVER=vc4-drm\nOF_NAME=gpu\nOF_FULLNAME=/gpu\n\
+                      OF_COMPATIBLE_0=brcm,bcm2711-vc5\nOF_COMPATIBLE_1=brcm,vc5\n\
+                      OF_COMPATIBLE_N=2\n";
+        let gpu = tree.add_device("platform/gpu", "platform", &[("uevent", uevent)]);
+        let compatible = vec!["brcm,bcm2711-vc5".to_owned(), "brcm,vc5".to_owned()];
+        assert_eq!(
+            BusInfo::from_sysfs(gpu).unwrap(),
+            BusInfo::Platform(
+                PlatformBusInfo {
+                    fullname: "/gpu".to_owned()
+                },
+                PlatformDeviceInfo { compatible }
+            )
+        );
+
+        // Without devicetree information the device name is used
+        let dc = tree.add_device("platform/50000000.host1x/54200000.dc", "host1x", &[]);
+        assert_eq!(
+            BusInfo::from_sysfs(dc).unwrap(),
+            BusInfo::Host1x(
+                PlatformBusInfo {
+                    fullname: "54200000.dc".to_owned()
+                },
+                PlatformDeviceInfo { compatible: vec![] }
+            )
+        );
+    }
+
+    #[test]
+    fn virtio_reports_its_transport() {
+        let tree = FakeTree::new("virtio");
+        tree.add_device("pci0000:00/0000:00:01.0", "pci", &[]);
+        let gpu = tree.add_device("pci0000:00/0000:00:01.0/virtio0", "virtio", &[]);
+
+        // The transport has no device information
+        let pci = PciBusInfo::from_address("0000:00:01.0").unwrap();
+        assert_eq!(BusInfo::from_sysfs(gpu).unwrap(), BusInfo::Pci(pci, None));
+    }
+
+    #[test]
+    fn malformed_bus_info() {
+        let tree = FakeTree::new("malformed");
+
+        // Broken uevent entries are skipped
+        let uevent = "OF_FULLNAME\nOF_COMPATIBLE_0=a\nOF_COMPATIBLE_N=two\n";
+        let dir = tree.add_device("platform/display", "platform", &[("uevent", uevent)]);
+        let expected = BusInfo::Platform(
+            PlatformBusInfo {
+                fullname: "display".to_owned(),
+            },
+            PlatformDeviceInfo { compatible: vec![] },
+        );
+        assert_eq!(BusInfo::from_sysfs(dir).unwrap(), expected);
+
+        // Missing compatible strings are left out
+        let uevent = "OF_COMPATIBLE_0=a\nOF_COMPATIBLE_N=2\n";
+        let dir = tree.add_device("platform/other", "platform", &[("uevent", uevent)]);
+        match BusInfo::from_sysfs(dir).unwrap() {
+            BusInfo::Platform(_, dev) => assert_eq!(dev.compatible, ["a"]),
+            bus => panic!("unexpected bus {:?}", bus),
+        }
+
+        let files = [("busnum", "one\n"), ("devnum", "1\n")];
+        let dir = tree.add_device("usb1/1-1", "usb", &files);
+        let err = BusInfo::from_sysfs(dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let dir = tree.add_device("pci0000:00/not-an-address", "pci", &[]);
+        let err = BusInfo::from_sysfs(dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let dir = tree.add_device("serial0", "serial", &[]);
+        let err = BusInfo::from_sysfs(dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -17,3 +17,25 @@ pub unsafe fn transmute_vec_from_u32<T: From<RawResourceHandle>>(raw: Vec<u32>)
         transmute_vec(raw)
     }
 }
+
+/// Converts a NUL terminated name of a kernel struct to a string.
+#[cfg(feature = "serde")]
+pub fn c_name_to_string(name: &[std::ffi::c_char]) -> String {
+    let bytes: Vec<u8> = name
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Converts a string to a NUL terminated name of a kernel struct, truncating
+/// it if necessary.
+#[cfg(feature = "serde")]
+pub fn string_to_c_name<const N: usize>(name: &str) -> [std::ffi::c_char; N] {
+    let mut c_name = [0; N];
+    for (c, byte) in c_name.iter_mut().zip(name.bytes().take(N - 1)) {
+        *c = byte as std::ffi::c_char;
+    }
+    c_name
+}